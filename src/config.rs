@@ -1,34 +1,543 @@
+use std::collections::HashMap;
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::time::SystemTime;
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 
-const CONFIG_FILE: &str = "data/miner_config.json";
+use crate::policy::{PolicyConfig, STANDARD_PUBKEY_HASH_LEN};
+use crate::node::miner::{CoinbaseSpec, MAX_COINBASE_TAG};
+use crate::node::banman::DEFAULT_BAN_TIME;
+use crate::node::transport::encryption::{EncryptionPolicy, NodeKey};
+use crate::node::netaddr::PeerAddress;
+use crate::node::peer_id::TransportKind;
+use crate::node::router::RouteConfig;
+use crate::node::transport::geo::GeoOptions;
+use crate::node::transport::satellite::UplinkOptions;
+use crate::node::stratum::{StratumOptions, DEFAULT_MAX_WORKERS};
 
-/// Miner configuration (POLICY ONLY)
-#[derive(Serialize, Deserialize)]
-pub struct MinerConfig {
-    /// Wallet name used for coinbase rewards
-    pub coinbase_wallet: String,
+pub const MINER_CONFIG_FILE: &str = "data/miner_config.json";
+pub const POLICY_CONFIG_FILE: &str = "data/policy_config.json";
+pub const SEED_CONFIG_FILE: &str = "data/seed_config.json";
+pub const BAN_CONFIG_FILE: &str = "data/ban_config.json";
+pub const ENCRYPTION_CONFIG_FILE: &str = "data/encryption_config.json";
+pub const PROXY_CONFIG_FILE: &str = "data/proxy_config.json";
+pub const RELAY_CONFIG_FILE: &str = "data/relay_config.json";
+pub const GEO_CONFIG_FILE: &str = "data/geo_config.json";
+pub const UPLINK_CONFIG_FILE: &str = "data/uplink_config.json";
+
+/// Checks a config must pass before the node uses it
+pub trait Validate {
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
 }
 
-/// Load miner configuration from disk
-pub fn load_miner_config() -> MinerConfig {
+/// Load a JSON config file, writing the defaults on first run
+///
+/// A file that does not parse or validate stops the node.
+pub fn load_config<T>(path: &str) -> T
+where
+    T: Default + Serialize + DeserializeOwned + Validate,
+{
     fs::create_dir_all("data").unwrap();
 
-    if let Ok(data) = fs::read_to_string(CONFIG_FILE) {
+    if let Ok(data) = fs::read_to_string(path) {
         if !data.trim().is_empty() {
-            return serde_json::from_str(&data)
-                .expect("invalid miner_config.json");
+            return parse_config(&data).unwrap_or_else(|e| panic!("invalid {}: {}", path, e));
         }
     }
 
-    let default = MinerConfig {
-        coinbase_wallet: "default".to_string(),
-    };
+    let default = T::default();
 
     fs::write(
-        CONFIG_FILE,
+        path,
         serde_json::to_string_pretty(&default).unwrap(),
     ).unwrap();
 
     default
 }
+
+fn parse_config<T: DeserializeOwned + Validate>(data: &str) -> Result<T, String> {
+    let config: T = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Where block rewards are paid
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum PayoutMode {
+    /// Always the wallet's first address
+    #[default]
+    Wallet,
+
+    /// A fresh wallet address for every block found
+    Rotate,
+
+    /// Fixed outputs sharing the reward by percentage
+    Split { outputs: Vec<PayoutSplit> },
+}
+
+/// One output of a split payout
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PayoutSplit {
+    /// Hex-encoded pubkey hash
+    pub pubkey_hash: String,
+
+    /// Share of the reward (all shares sum to 100)
+    pub percent: u64,
+}
+
+/// Miner configuration (POLICY ONLY)
+///
+/// Re-read by the node while running; changes apply
+/// from the next block template.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MinerConfig {
+    /// Wallet name used for coinbase rewards
+    pub coinbase_wallet: String,
+
+    /// Mine at all (the node still syncs and relays when off)
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Coinbase payout: wallet / rotate / split
+    #[serde(default)]
+    pub payout: PayoutMode,
+
+    /// Text carried in the coinbase (max 64 bytes)
+    #[serde(default)]
+    pub coinbase_tag: Option<String>,
+
+    /// Mining threads (0 = one per CPU core)
+    #[serde(default)]
+    pub threads: usize,
+
+    /// Stratum pool server bind address (e.g. "0.0.0.0:3333");
+    /// disabled when absent
+    #[serde(default)]
+    pub stratum_bind: Option<String>,
+
+    /// Share difficulty for Stratum workers (1 = MAX_TARGET)
+    #[serde(default = "default_share_difficulty")]
+    pub stratum_share_difficulty: u64,
+
+    /// Stratum worker connections served at once
+    #[serde(default = "default_stratum_max_workers")]
+    pub stratum_max_workers: usize,
+
+    /// Password Stratum workers must authorize with; any
+    /// credentials are accepted when absent
+    #[serde(default)]
+    pub stratum_password: Option<String>,
+}
+
+fn default_share_difficulty() -> u64 {
+    1024
+}
+
+fn default_stratum_max_workers() -> usize {
+    DEFAULT_MAX_WORKERS
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for MinerConfig {
+    fn default() -> Self {
+        Self {
+            coinbase_wallet: "default".to_string(),
+            enabled: true,
+            payout: PayoutMode::Wallet,
+            coinbase_tag: None,
+            threads: 0,
+            stratum_bind: None,
+            stratum_share_difficulty: default_share_difficulty(),
+            stratum_max_workers: default_stratum_max_workers(),
+            stratum_password: None,
+        }
+    }
+}
+
+/// Rejects configurations that cannot produce a valid coinbase
+impl Validate for MinerConfig {
+    fn validate(&self) -> Result<(), String> {
+        if let PayoutMode::Split { outputs } = &self.payout {
+            if outputs.is_empty() {
+                return Err("split payout has no outputs".into());
+            }
+
+            for o in outputs {
+                match hex::decode(&o.pubkey_hash) {
+                    Ok(h) if h.len() == STANDARD_PUBKEY_HASH_LEN => {}
+                    _ => return Err(format!("invalid pubkey hash '{}'", o.pubkey_hash)),
+                }
+                if !(1..=100).contains(&o.percent) {
+                    return Err(format!("split share {}% is not 1-100", o.percent));
+                }
+            }
+
+            let total: u64 = outputs.iter().map(|o| o.percent).sum();
+            if total != 100 {
+                return Err(format!("split percentages sum to {}, not 100", total));
+            }
+        }
+
+        if self.stratum_max_workers == 0 {
+            return Err("stratum_max_workers must be at least 1".into());
+        }
+
+        if let Some(tag) = &self.coinbase_tag {
+            if tag.len() > MAX_COINBASE_TAG {
+                return Err(format!(
+                    "coinbase tag longer than {} bytes",
+                    MAX_COINBASE_TAG
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl MinerConfig {
+    /// Settings for the Stratum server
+    pub fn stratum_options(&self) -> StratumOptions {
+        StratumOptions {
+            share_difficulty: self.stratum_share_difficulty,
+            max_workers: self.stratum_max_workers,
+            password: self.stratum_password.clone(),
+        }
+    }
+
+    /// Coinbase for the next block
+    ///
+    /// `wallet_pubkey_hash` is used by the wallet and rotate modes.
+    pub fn coinbase_spec(&self, wallet_pubkey_hash: Vec<u8>) -> CoinbaseSpec {
+        let payouts = match &self.payout {
+            PayoutMode::Wallet | PayoutMode::Rotate => {
+                vec![(wallet_pubkey_hash, 100)]
+            }
+            PayoutMode::Split { outputs } => outputs
+                .iter()
+                .map(|o| (hex::decode(&o.pubkey_hash).unwrap_or_default(), o.percent))
+                .collect(),
+        };
+
+        CoinbaseSpec {
+            payouts,
+            tag: self
+                .coinbase_tag
+                .as_ref()
+                .map(|t| t.as_bytes().to_vec())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Modification time of the miner config (for runtime reload)
+pub fn miner_config_mtime() -> Option<SystemTime> {
+    fs::metadata(MINER_CONFIG_FILE).and_then(|m| m.modified()).ok()
+}
+
+/// Re-read the miner config while running
+///
+/// Unlike `load_config` this never panics: a broken edit is
+/// reported and the caller keeps its current config.
+pub fn reload_miner_config() -> Result<MinerConfig, String> {
+    let data = fs::read_to_string(MINER_CONFIG_FILE).map_err(|e| e.to_string())?;
+    parse_config(&data)
+}
+
+/// Bootstrap seed configuration (non-consensus)
+#[derive(Serialize, Deserialize)]
+pub struct SeedConfig {
+    /// Network whose seed list is used
+    pub network: String,
+
+    /// Seed lists per network. Entries are "host:port",
+    /// "ip:port" or a DNS seed hostname (all records used;
+    /// port defaults to 8333)
+    pub seeds: HashMap<String, Vec<String>>,
+}
+
+impl Validate for SeedConfig {}
+
+impl SeedConfig {
+    /// Seed entries for the selected network
+    pub fn active_seeds(&self) -> Vec<String> {
+        self.seeds.get(&self.network).cloned().unwrap_or_default()
+    }
+}
+
+impl Default for SeedConfig {
+    fn default() -> Self {
+        let mut seeds = HashMap::new();
+        seeds.insert(
+            "main".to_string(),
+            vec![
+                "bitcoin-revelation-node.fly.dev:8333".to_string(),
+                "bitcoin-0-2.fly.dev:8333".to_string(),
+            ],
+        );
+        seeds.insert("test".to_string(), vec![]);
+
+        Self {
+            network: "main".to_string(),
+            seeds,
+        }
+    }
+}
+
+/// Peer banning configuration (POLICY ONLY)
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct BanConfig {
+    /// Trusted peers: never scored, banned or refused
+    pub allowlist: Vec<IpAddr>,
+
+    /// Automatic ban length (seconds)
+    pub ban_time: u64,
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            allowlist: vec![],
+            ban_time: DEFAULT_BAN_TIME,
+        }
+    }
+}
+
+impl Validate for BanConfig {}
+
+/// P2P transport encryption (POLICY ONLY)
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// Offer encryption to every peer
+    pub enabled: bool,
+
+    /// Refuse peers that cannot encrypt (no plaintext fallback)
+    pub require: bool,
+
+    /// "ip:port" / "<onion>.onion:port" → hex static key (33
+    /// bytes) of our own nodes; dialling them fails unless they
+    /// prove that key
+    pub peer_keys: HashMap<String, String>,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            require: false,
+            peer_keys: HashMap::new(),
+        }
+    }
+}
+
+impl EncryptionConfig {
+    /// Transport policy with our node key
+    pub fn policy(&self, node_key: NodeKey) -> Result<EncryptionPolicy, String> {
+        let mut policy = EncryptionPolicy::new(node_key);
+        policy.require = self.require;
+        policy.peer_keys = self.pinned_keys()?;
+        Ok(policy)
+    }
+
+    /// `peer_keys` parsed, by local address
+    fn pinned_keys(&self) -> Result<HashMap<SocketAddr, secp256k1::PublicKey>, String> {
+        let mut keys = HashMap::new();
+
+        for (addr, key) in &self.peer_keys {
+            let addr = PeerAddress::parse(addr)
+                .map_err(|_| format!("invalid peer address '{}'", addr))?
+                .socket_addr();
+
+            let key = hex::decode(key)
+                .ok()
+                .and_then(|b| secp256k1::PublicKey::from_slice(&b).ok())
+                .ok_or_else(|| format!("invalid key for peer {}", addr))?;
+
+            keys.insert(addr, key);
+        }
+
+        Ok(keys)
+    }
+}
+
+impl Validate for EncryptionConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.pinned_keys().map(|_| ())
+    }
+}
+
+/// Outbound proxy (POLICY ONLY)
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// SOCKS5 proxy, e.g. Tor at "127.0.0.1:9050"; enables
+    /// onion addresses
+    pub proxy: Option<SocketAddr>,
+
+    /// Every outbound connection through the proxy, our address
+    /// never announced, seeds resolved by the proxy
+    pub proxy_only: bool,
+}
+
+impl Validate for ProxyConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.proxy_only && self.proxy.is_none() {
+            return Err("proxy_only requires proxy".into());
+        }
+        Ok(())
+    }
+}
+
+/// Block / transaction relay per transport (POLICY ONLY)
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    pub tcp: RouteConfig,
+    /// LAN mesh
+    pub geo: RouteConfig,
+    pub bluetooth: RouteConfig,
+    /// Uplink (paces itself: see `UplinkOptions::rate`)
+    pub satellite: RouteConfig,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            tcp: RouteConfig {
+                priority: 2,
+                budget: 0,
+                relay_transactions: true,
+            },
+            geo: RouteConfig {
+                priority: 1,
+                budget: 256 * 1024,
+                relay_transactions: true,
+            },
+            // A few KB/s at best: blocks only
+            bluetooth: RouteConfig {
+                priority: 0,
+                budget: 2 * 1024,
+                relay_transactions: false,
+            },
+            satellite: RouteConfig {
+                priority: 0,
+                budget: 0,
+                relay_transactions: true,
+            },
+        }
+    }
+}
+
+impl Validate for RelayConfig {}
+
+impl RelayConfig {
+    /// Settings for `kind` (receive-only kinds get the default)
+    pub fn route(&self, kind: TransportKind) -> RouteConfig {
+        match kind {
+            TransportKind::Tcp => self.tcp.clone(),
+            TransportKind::Geo => self.geo.clone(),
+            TransportKind::Bluetooth => self.bluetooth.clone(),
+            TransportKind::Satellite => self.satellite.clone(),
+            _ => RouteConfig::default(),
+        }
+    }
+}
+
+impl Validate for PolicyConfig {}
+
+impl Validate for GeoOptions {}
+
+impl Validate for UplinkOptions {
+    fn validate(&self) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if self.udp.is_some() == self.file.is_some() {
+            return Err("uplink needs exactly one of udp and file".into());
+        }
+        self.fec.validate().map_err(String::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(outputs: &[(&str, u64)]) -> MinerConfig {
+        let mut config: MinerConfig =
+            serde_json::from_str(r#"{"coinbase_wallet": "miner"}"#).unwrap();
+        config.payout = PayoutMode::Split {
+            outputs: outputs
+                .iter()
+                .map(|(h, p)| PayoutSplit {
+                    pubkey_hash: h.to_string(),
+                    percent: *p,
+                })
+                .collect(),
+        };
+        config
+    }
+
+    #[test]
+    fn split_payout_is_validated() {
+        let a = "11".repeat(32);
+        let b = "22".repeat(32);
+
+        assert!(split(&[(&a, 60), (&b, 40)]).validate().is_ok());
+        assert!(split(&[]).validate().is_err());
+        assert!(split(&[(&a, 60), (&b, 30)]).validate().is_err());
+
+        // Hashes must decode to 32 bytes
+        assert!(split(&[(&a, 60), ("zz", 40)]).validate().is_err());
+        assert!(split(&[(&a, 60), ("22", 40)]).validate().is_err());
+        assert!(split(&[(&a, 60), (&"22".repeat(33), 40)])
+            .validate()
+            .is_err());
+
+        // A zero share would be a zero-value output
+        assert!(split(&[(&a, 100), (&b, 0)]).validate().is_err());
+        assert!(split(&[(&a, u64::MAX), (&b, 1)]).validate().is_err());
+    }
+    #[test]
+    fn configs_are_parsed_then_validated() {
+        // Missing fields take their defaults
+        let relay: RelayConfig = parse_config("{}").unwrap();
+        assert!(!relay.bluetooth.relay_transactions);
+        assert!(parse_config::<BanConfig>("[").is_err());
+
+        assert!(parse_config::<ProxyConfig>(r#"{"proxy_only": true}"#).is_err());
+        assert!(
+            parse_config::<ProxyConfig>(r#"{"proxy": "127.0.0.1:9050", "proxy_only": true}"#)
+                .is_ok()
+        );
+
+        assert!(parse_config::<UplinkOptions>(r#"{"enabled": true}"#).is_err());
+        assert!(
+            parse_config::<UplinkOptions>(r#"{"enabled": true, "udp": "127.0.0.1:9999"}"#).is_ok()
+        );
+        assert!(parse_config::<UplinkOptions>(
+            r#"{"enabled": true, "file": "up.dat", "fec": {"shard_size": 0}}"#
+        )
+        .is_err());
+
+        let key = hex::encode(NodeKey::generate().public.serialize());
+        let pinned = |key: &str| format!(r#"{{"peer_keys": {{"1.2.3.4:8333": "{}"}}}}"#, key);
+        assert!(parse_config::<EncryptionConfig>(&pinned(&key)).is_ok());
+        assert!(parse_config::<EncryptionConfig>(&pinned("00")).is_err());
+
+        let tag = format!(
+            r#"{{"coinbase_wallet": "miner", "coinbase_tag": "{}"}}"#,
+            "x".repeat(MAX_COINBASE_TAG + 1)
+        );
+        assert!(parse_config::<MinerConfig>(&tag).is_err());
+    }
+}
@@ -145,7 +145,9 @@ fn utxos_file() -> PathBuf {
     path
 }
 
-fn median_time_past(chain: &[Block]) -> i64 {
+/// Median timestamp of the last MTP_WINDOW blocks; a new
+/// block must be later (also used by block templates)
+pub(crate) fn median_time_past(chain: &[Block]) -> i64 {
     let mut times: Vec<i64> = chain
         .iter()
         .rev()
@@ -274,6 +276,12 @@ impl Blockchain {
                 let is_coinbase = tx_index == 0 && tx.inputs.is_empty();
 
                 for (i, o) in tx.outputs.iter().enumerate() {
+                    // Zero-value outputs only carry data (revelation
+                    // text, coinbase tag / extra-nonce): nothing to spend
+                    if o.value == 0 {
+                        continue;
+                    }
+
                     self.utxos.insert(
                         format!("{}:{}", txid, i),
                         UTXO {
@@ -304,3 +312,44 @@ impl Blockchain {
         .unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_value_outputs_stay_out_of_the_utxo_set() {
+        let coinbase = Transaction {
+            inputs: vec![],
+            outputs: vec![
+                TxOutput {
+                    value: 50,
+                    pubkey_hash: vec![1; 32],
+                },
+                TxOutput {
+                    value: 0,
+                    pubkey_hash: b"tag\x01\0\0\0\0\0\0\0".to_vec(),
+                },
+            ],
+        };
+        let txid = hex::encode(coinbase.txid());
+
+        let mut chain = Blockchain::new();
+        chain.blocks.push(Block {
+            header: BlockHeader {
+                height: 0,
+                timestamp: 0,
+                prev_hash: vec![0; 32],
+                nonce: 0,
+                target: [0xff; 32],
+                merkle_root: vec![],
+            },
+            transactions: vec![revelation_tx(), coinbase],
+            hash: vec![],
+        });
+        chain.rebuild_utxos();
+
+        assert_eq!(chain.utxos.len(), 1);
+        assert_eq!(chain.utxos[&format!("{}:0", txid)].value, 50);
+    }
+}
@@ -34,8 +34,8 @@ impl Transaction {
         sha256(&serialize_transaction(self))
     }
 
-    /// Estimated serialized size (POLICY ONLY)
+    /// Consensus-serialized size in bytes (POLICY ONLY)
     pub fn serialized_size(&self) -> usize {
-        self.inputs.len() * 148 + self.outputs.len() * 34 + 10
+        serialize_transaction(self).len()
     }
 }
@@ -7,23 +7,42 @@ use axum::{
     Router,
     Json,
     routing::{get, post},
-    extract::{State, Path},
-    http::StatusCode,
+    extract::{State, Path, Query, DefaultBodyLimit},
+    body::Bytes,
+    http::{header, StatusCode},
     response::IntoResponse,
 };
 
 use crate::chain::Blockchain;
+use crate::block::Block;
+use crate::transaction::Transaction;
 use crate::reward::block_reward;
+use crate::node::mempool::Mempool;
+use crate::node::miner::{build_template, CoinbaseSpec};
+use crate::node::p2p::P2PNetwork;
+use crate::node::peer_manager::PeerInfo;
+use crate::node::router::TransportStats;
+use crate::node::banman::{BanEntry, BanTarget, DEFAULT_BAN_TIME};
+use crate::node::fee_estimator::MAX_CONFIRM_TARGET;
+use crate::node::bundle::{Bundle, TipFile, MAX_BUNDLE_SIZE};
+use crate::node::transport::encryption::NodeKey;
 
 const COINBASE_MATURITY: u64 = 100;
 
 #[derive(Clone)]
 struct AppState {
     chain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    p2p: Arc<P2PNetwork>,
 }
 
-pub async fn start_api(chain: Arc<Mutex<Blockchain>>, port: u16) {
-    let state = AppState { chain };
+pub async fn start_api(
+    chain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    p2p: Arc<P2PNetwork>,
+    port: u16,
+) {
+    let state = AppState { chain, mempool, p2p };
 
     let app = Router::new()
         .route("/status", get(status))
@@ -32,6 +51,19 @@ pub async fn start_api(chain: Arc<Mutex<Blockchain>>, port: u16) {
         .route("/tx/:txid", get(tx_by_id))
         .route("/address/:hash", get(address_info))
         .route("/transactions/new", post(new_transaction))
+        .route("/estimatefee/:blocks", get(estimate_fee))
+        .route("/getblocktemplate", get(get_block_template))
+        .route("/submitblock", post(submit_block))
+        .route("/peers", get(peers))
+        .route("/transportstats", get(transport_stats))
+        .route("/setban", post(set_ban))
+        .route("/listbanned", get(list_banned))
+        .route("/bundle/tip", get(bundle_tip))
+        .route("/bundle/export", post(bundle_export))
+        .route(
+            "/bundle/import",
+            post(bundle_import).layer(DefaultBodyLimit::max(MAX_BUNDLE_SIZE)),
+        )
         .with_state(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
@@ -49,6 +81,8 @@ struct StatusResponse {
     blocks: usize,
     utxos: usize,
     mempool: usize,
+    /// Blocks waiting for a missing parent
+    orphans: usize,
 
     // 🔒 CONSENSUS TRUTH
     total_issued: u64,
@@ -89,6 +123,7 @@ async fn status(State(state): State<AppState>) -> Json<StatusResponse> {
         blocks: c.blocks.len(),
         utxos: c.utxos.len(),
         mempool: c.mempool.len(),
+        orphans: state.p2p.orphan_count(),
 
         total_issued,
         utxo_supply,
@@ -213,6 +248,168 @@ async fn new_transaction(
     }
 }
 
+//
+// ─── FEE ESTIMATION (POLICY) ──────────────────────
+//
+
+#[derive(Serialize)]
+struct FeeEstimateResponse {
+    blocks: usize,
+    fee_per_byte: u64,
+
+    /// false = not enough history, minimum relay fee returned
+    estimated: bool,
+}
+
+async fn estimate_fee(
+    State(state): State<AppState>,
+    Path(blocks): Path<usize>,
+) -> impl IntoResponse {
+    if blocks == 0 || blocks > MAX_CONFIRM_TARGET {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("blocks must be between 1 and {}", MAX_CONFIRM_TARGET),
+        )
+            .into_response();
+    }
+
+    let m = state.mempool.lock().unwrap();
+    let estimate = m.estimate_fee(blocks);
+
+    Json(FeeEstimateResponse {
+        blocks,
+        fee_per_byte: estimate.unwrap_or_else(|| m.min_fee_rate()),
+        estimated: estimate.is_some(),
+    })
+    .into_response()
+}
+
+//
+// ─── EXTERNAL MINING ──────────────────────────────
+//
+
+#[derive(Deserialize)]
+struct TemplateQuery {
+    /// Optional payout pubkey hash (hex); when given, a
+    /// ready-to-grind block is included in the response
+    payout: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TemplateTx {
+    txid: String,
+    fee: i64,
+    size: usize,
+    tx: Transaction,
+}
+
+#[derive(Serialize)]
+struct BlockTemplateResponse {
+    height: u64,
+    prev_hash: String,
+    target: String,
+    timestamp: i64,
+    min_timestamp: i64,
+
+    /// Block reward available to the coinbase
+    coinbase_value: u64,
+    total_fees: i64,
+
+    transactions: Vec<TemplateTx>,
+
+    /// Coinbase + transactions with merkle root set, nonce 0
+    block: Option<Block>,
+}
+
+async fn get_block_template(
+    State(state): State<AppState>,
+    Query(query): Query<TemplateQuery>,
+) -> impl IntoResponse {
+    let payout = match &query.payout {
+        Some(h) => match hex::decode(h) {
+            Ok(v) => Some(v),
+            Err(_) => return (StatusCode::BAD_REQUEST, "Invalid payout").into_response(),
+        },
+        None => None,
+    };
+
+    // Lock order: chain → mempool
+    let template = {
+        let c = state.chain.lock().unwrap();
+        let m = state.mempool.lock().unwrap();
+        build_template(
+            &c.blocks,
+            &c.utxos,
+            m.sorted_for_mining(),
+            &CoinbaseSpec::single(payout.clone().unwrap_or_default()),
+        )
+    };
+
+    let transactions = template
+        .transactions
+        .iter()
+        .zip(&template.fees)
+        .map(|(tx, fee)| TemplateTx {
+            txid: hex(&tx.txid()),
+            fee: *fee,
+            size: tx.serialized_size(),
+            tx: tx.clone(),
+        })
+        .collect();
+
+    Json(BlockTemplateResponse {
+        height: template.height,
+        prev_hash: hex(&template.prev_hash),
+        target: hex(&template.target),
+        timestamp: template.timestamp,
+        min_timestamp: template.min_timestamp,
+        coinbase_value: block_reward(template.height),
+        total_fees: template.fees.iter().sum(),
+        transactions,
+        block: payout.map(|_| template.assemble(0)),
+    })
+    .into_response()
+}
+
+#[derive(Serialize)]
+struct SubmitBlockResponse {
+    accepted: bool,
+    hash: String,
+    reason: Option<&'static str>,
+}
+
+async fn submit_block(
+    State(state): State<AppState>,
+    Json(block): Json<Block>,
+) -> impl IntoResponse {
+    let hash = hex(&block.hash);
+
+    let stale = {
+        let c = state.chain.lock().unwrap();
+        c.blocks
+            .last()
+            .map(|tip| tip.hash != block.header.prev_hash)
+            .unwrap_or(false)
+    };
+
+    // Same validation + relay path as the built-in miner
+    let accepted = state.p2p.submit_block(block);
+
+    let reason = match (accepted, stale) {
+        (true, _) => None,
+        (false, true) => Some("stale"),
+        (false, false) => Some("invalid"),
+    };
+
+    let status = if accepted {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+
+    (status, Json(SubmitBlockResponse { accepted, hash, reason })).into_response()
+}
+
 //
 // ─── ADDRESS INFO ─────────────────────────────────
 //
@@ -262,6 +459,178 @@ async fn address_info(
     })
 }
 
+//
+// ─── PEERS ────────────────────────────────────────
+//
+
+async fn peers(State(state): State<AppState>) -> Json<Vec<PeerInfo>> {
+    Json(state.p2p.peer_info())
+}
+
+async fn transport_stats(State(state): State<AppState>) -> Json<Vec<TransportStats>> {
+    Json(state.p2p.transport_stats())
+}
+
+//
+// ─── BANS ─────────────────────────────────────────
+//
+
+#[derive(Deserialize)]
+struct SetBanRequest {
+    /// IP, or peer id of a non-IP peer ("ble:<device>", ...)
+    #[serde(alias = "ip")]
+    target: BanTarget,
+    /// "add" or "remove"
+    command: String,
+    /// Seconds (default 24h)
+    bantime: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct SetBanResponse {
+    ok: bool,
+    reason: Option<String>,
+}
+
+async fn set_ban(
+    State(state): State<AppState>,
+    Json(req): Json<SetBanRequest>,
+) -> impl IntoResponse {
+    let (status, ok, reason) = match req.command.as_str() {
+        "add" => {
+            state.p2p.set_ban(
+                req.target,
+                req.bantime.unwrap_or(DEFAULT_BAN_TIME),
+                "manual",
+            );
+            (StatusCode::OK, true, None)
+        }
+        "remove" => {
+            if state.p2p.unban(&req.target) {
+                (StatusCode::OK, true, None)
+            } else {
+                (StatusCode::NOT_FOUND, false, Some("not banned".to_string()))
+            }
+        }
+        _ => (
+            StatusCode::BAD_REQUEST,
+            false,
+            Some("command must be add or remove".to_string()),
+        ),
+    };
+
+    (status, Json(SetBanResponse { ok, reason }))
+}
+
+async fn list_banned(State(state): State<AppState>) -> Json<Vec<BanEntry>> {
+    Json(state.p2p.list_banned())
+}
+
+//
+// ─── SYNC BUNDLES ─────────────────────────────────
+//
+
+async fn bundle_tip(State(state): State<AppState>) -> Json<TipFile> {
+    Json(TipFile::from_chain(&state.chain.lock().unwrap()))
+}
+
+#[derive(Deserialize)]
+struct BundleExportRequest {
+    /// Blocks above this height
+    above: Option<u64>,
+    /// Or: whatever the node that wrote this tip file lacks
+    tip: Option<TipFile>,
+    /// Include the mempool
+    #[serde(default)]
+    transactions: bool,
+    /// Sign with the node key
+    #[serde(default)]
+    sign: bool,
+}
+
+#[derive(Serialize)]
+struct BundleError {
+    error: &'static str,
+}
+
+fn bundle_error(error: &'static str) -> axum::response::Response {
+    (StatusCode::BAD_REQUEST, Json(BundleError { error })).into_response()
+}
+
+async fn bundle_export(
+    State(state): State<AppState>,
+    Json(req): Json<BundleExportRequest>,
+) -> impl IntoResponse {
+    let bundle = {
+        let c = state.chain.lock().unwrap();
+        let above = match (req.above, &req.tip) {
+            (Some(h), None) => h,
+            (None, Some(tip)) => match tip.fork_height(&c) {
+                Ok(h) => h,
+                Err(e) => return bundle_error(e),
+            },
+            _ => return bundle_error("give exactly one of above and tip"),
+        };
+
+        let m = state.mempool.lock().unwrap();
+        Bundle::export(&c, &m, above, req.transactions)
+    };
+
+    let key = req.sign.then(NodeKey::load_or_create);
+    let data = bundle.encode(key.as_ref());
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/octet-stream")],
+        data,
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct BundleImportQuery {
+    /// Trusted signers: refuse the bundle unless signed by one
+    /// of these keys (hex, comma-separated)
+    signer: Option<String>,
+    /// Name the import is reported under (`offline:<source>`)
+    source: Option<String>,
+}
+
+async fn bundle_import(
+    State(state): State<AppState>,
+    Query(query): Query<BundleImportQuery>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let bundle = match Bundle::decode(&body) {
+        Ok(b) => b,
+        Err(e) => return bundle_error(e),
+    };
+
+    let trusted = match &query.signer {
+        Some(list) => {
+            let keys: Result<Vec<Vec<u8>>, _> = list.split(',').map(|k| hex::decode(k.trim())).collect();
+            match keys {
+                Ok(keys) if bundle.signed_by(&keys) => true,
+                Ok(_) => return bundle_error("bundle not signed by a trusted key"),
+                Err(_) => return bundle_error("invalid signer key"),
+            }
+        }
+        None => false,
+    };
+
+    let source = query.source.unwrap_or_else(|| "bundle".to_string());
+    let p2p = Arc::clone(&state.p2p);
+
+    // Every connected block is written to disk: off the runtime
+    match tokio::task::spawn_blocking(move || p2p.import_bundle(bundle, &source)).await {
+        Ok(mut report) => {
+            report.trusted = trusted;
+            Json(report).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
 //
 // ─── HELPER ───────────────────────────────────────
 //
@@ -1,12 +1,20 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
 use std::sync::{Arc, Mutex};
 
 use crate::core::chain::Blockchain;
+use crate::node::bundle::Bundle;
 use crate::node::mempool::Mempool;
+use crate::node::fee_estimator::DEFAULT_CONFIRM_TARGET;
 use crate::wallet::Wallet;
 use crate::core::validation::validate_transaction;
 
 const COINBASE_MATURITY: u64 = 100;
 
+/// API of the node the bundle commands work on
+const API_ADDR: &str = "127.0.0.1:8080";
+
 /// CLI wallet & transaction commands
 pub fn handle_command(
     args: Vec<String>,
@@ -17,7 +25,8 @@ pub fn handle_command(
     if args.len() < 3 {
         println!("Usage:");
         println!("  wallet balance");
-        println!("  wallet send <to_pubkey_hash_hex> <amount>");
+        println!("  wallet send <to_pubkey_hash_hex> <amount> [fee_per_byte]");
+        println!("  wallet estimatefee [blocks]");
         return;
     }
 
@@ -25,7 +34,7 @@ pub fn handle_command(
         // ───────────────── BALANCE ─────────────────
         "balance" => {
             let chain_guard = chain.lock().unwrap();
-            let my_hash = wallet.address().expect("wallet locked");
+            let my_hashes = wallet.addresses().expect("wallet locked");
             let current_height = chain_guard.height();
 
             let mut total = 0u64;
@@ -33,7 +42,7 @@ pub fn handle_command(
             let mut locked = 0u64;
 
             for u in chain_guard.utxos.values() {
-                if u.pubkey_hash != my_hash {
+                if !my_hashes.contains(&u.pubkey_hash) {
                     continue;
                 }
 
@@ -55,8 +64,8 @@ pub fn handle_command(
 
         // ───────────────── SEND ─────────────────
         "send" => {
-            if args.len() != 5 {
-                println!("Usage: wallet send <to_pubkey_hash_hex> <amount>");
+            if args.len() != 5 && args.len() != 6 {
+                println!("Usage: wallet send <to_pubkey_hash_hex> <amount> [fee_per_byte]");
                 return;
             }
 
@@ -76,6 +85,24 @@ pub fn handle_command(
                 }
             };
 
+            // Explicit fee rate, else the estimate, else the relay minimum
+            let fee_per_byte = match args.get(5) {
+                Some(v) => match v.parse::<u64>() {
+                    Ok(v) => v,
+                    Err(_) => {
+                        println!("Invalid fee rate");
+                        return;
+                    }
+                },
+                None => {
+                    let m = mempool.lock().unwrap();
+                    m.estimate_fee(DEFAULT_CONFIRM_TARGET)
+                        .unwrap_or_else(|| m.min_fee_rate())
+                }
+            };
+
+            let policy = mempool.lock().unwrap().policy().clone();
+
             let chain_guard = chain.lock().unwrap();
             let current_height = chain_guard.height();
 
@@ -83,6 +110,8 @@ pub fn handle_command(
                 &chain_guard.utxos,
                 to,
                 amount,
+                fee_per_byte,
+                &policy,
             ) {
                 Ok(t) => t,
                 Err(e) => {
@@ -108,8 +137,281 @@ pub fn handle_command(
             }
         }
 
+        // ───────────────── ESTIMATE FEE ─────────────────
+        "estimatefee" => {
+            let blocks: usize = match args.get(3) {
+                Some(v) => match v.parse() {
+                    Ok(v) if v > 0 => v,
+                    _ => {
+                        println!("Invalid block count");
+                        return;
+                    }
+                },
+                None => DEFAULT_CONFIRM_TARGET,
+            };
+
+            let m = mempool.lock().unwrap();
+            match m.estimate_fee(blocks) {
+                Some(rate) => {
+                    println!("⛽ Fee to confirm within {} blocks: {} per byte", blocks, rate);
+                }
+                None => {
+                    println!(
+                        "⛽ Not enough history; minimum relay fee: {} per byte",
+                        m.min_fee_rate()
+                    );
+                }
+            }
+        }
+
         _ => {
             println!("Unknown wallet command");
         }
     }
 }
+
+/// CLI sync bundle commands (talk to the running node's API)
+pub fn handle_bundle_command(args: Vec<String>) {
+    if args.len() < 4 {
+        println!("Usage:");
+        println!("  bundle tip <tip_file>");
+        println!("  bundle export <file> (--above <height> | --tip <tip_file>) [--transactions] [--sign]");
+        println!("  bundle import <file> [--signer <pubkey_hex>[,<pubkey_hex>...]]");
+        println!("  bundle inspect <file>");
+        return;
+    }
+
+    let file = &args[3];
+    let flag = |name: &str| args[4..].iter().any(|a| a == name);
+    let option = |name: &str| {
+        args[4..]
+            .iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 5))
+    };
+
+    match args[2].as_str() {
+        // ───────────────── TIP ─────────────────
+        "tip" => match api_request("GET", "/bundle/tip", &[]) {
+            Ok((200, body)) => match fs::write(file, body) {
+                Ok(()) => println!("📍 Tip written to {}", file),
+                Err(e) => println!("❌ Cannot write {}: {}", file, e),
+            },
+            Ok((_, body)) => println!("❌ Node refused: {}", String::from_utf8_lossy(&body)),
+            Err(e) => println!("❌ {}", e),
+        },
+
+        // ───────────────── EXPORT ─────────────────
+        "export" => {
+            let mut request = serde_json::json!({
+                "transactions": flag("--transactions"),
+                "sign": flag("--sign"),
+            });
+
+            match (option("--above"), option("--tip")) {
+                (Some(h), None) => match h.parse::<u64>() {
+                    Ok(h) => request["above"] = h.into(),
+                    Err(_) => {
+                        println!("Invalid height");
+                        return;
+                    }
+                },
+                (None, Some(path)) => {
+                    let tip = fs::read_to_string(path)
+                        .ok()
+                        .and_then(|t| serde_json::from_str::<serde_json::Value>(&t).ok());
+                    match tip {
+                        Some(tip) => request["tip"] = tip,
+                        None => {
+                            println!("❌ Cannot read tip file {}", path);
+                            return;
+                        }
+                    }
+                }
+                _ => {
+                    println!("Usage: bundle export <file> (--above <height> | --tip <tip_file>) [--transactions] [--sign]");
+                    return;
+                }
+            }
+
+            let body = request.to_string().into_bytes();
+            let data = match api_request("POST", "/bundle/export", &body) {
+                Ok((200, data)) => data,
+                Ok((_, body)) => {
+                    println!("❌ Node refused: {}", String::from_utf8_lossy(&body));
+                    return;
+                }
+                Err(e) => {
+                    println!("❌ {}", e);
+                    return;
+                }
+            };
+
+            if let Err(e) = fs::write(file, &data) {
+                println!("❌ Cannot write {}: {}", file, e);
+                return;
+            }
+
+            match Bundle::decode(&data) {
+                Ok(bundle) => {
+                    print_bundle(&bundle, data.len());
+                    println!("✅ Bundle written to {}", file);
+                }
+                Err(e) => println!("❌ Node sent a bad bundle: {}", e),
+            }
+        }
+
+        // ───────────────── IMPORT ─────────────────
+        "import" => {
+            let data = match fs::read(file) {
+                Ok(d) => d,
+                Err(e) => {
+                    println!("❌ Cannot read {}: {}", file, e);
+                    return;
+                }
+            };
+
+            let source = std::path::Path::new(file)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| file.clone());
+            let mut path = format!("/bundle/import?source={}", url_encode(&source));
+            if let Some(signer) = option("--signer") {
+                path.push_str(&format!("&signer={}", url_encode(signer)));
+            }
+
+            match api_request("POST", &path, &data) {
+                Ok((200, body)) => match serde_json::from_slice(&body) {
+                    Ok(report) => print_import_report(&report),
+                    Err(_) => println!("❌ Unreadable report"),
+                },
+                Ok((_, body)) => println!("❌ Bundle refused: {}", String::from_utf8_lossy(&body)),
+                Err(e) => println!("❌ {}", e),
+            }
+        }
+
+        // ───────────────── INSPECT ─────────────────
+        "inspect" => {
+            let data = match fs::read(file) {
+                Ok(d) => d,
+                Err(e) => {
+                    println!("❌ Cannot read {}: {}", file, e);
+                    return;
+                }
+            };
+
+            match Bundle::decode(&data) {
+                Ok(bundle) => {
+                    print_bundle(&bundle, data.len());
+                    println!("✅ Checksum and signature valid");
+                }
+                Err(e) => println!("❌ {}", e),
+            }
+        }
+
+        _ => {
+            println!("Unknown bundle command");
+        }
+    }
+}
+
+fn print_bundle(bundle: &Bundle, size: usize) {
+    match bundle.last_height() {
+        Some(last) => println!("📦 Blocks {}..={} ({})", bundle.first_height, last, bundle.blocks.len()),
+        None => println!("📦 No blocks above {}", bundle.first_height.saturating_sub(1)),
+    }
+    println!("🧾 Transactions: {}", bundle.transactions.len());
+    println!("💾 Size: {} bytes, created {}", size, bundle.created);
+    match &bundle.signer {
+        Some(key) => println!("✍️ Signed by {}", hex::encode(key)),
+        None => println!("✍️ Unsigned"),
+    }
+}
+
+fn print_import_report(report: &serde_json::Value) {
+    let count = |key: &str| report[key].as_u64().unwrap_or(0);
+    let rejected = |key: &str| report[key].as_array().cloned().unwrap_or_default();
+
+    match report["signer"].as_str() {
+        Some(key) if report["trusted"].as_bool() == Some(true) => println!("✍️ Signed by {} (trusted)", key),
+        Some(key) => println!("✍️ Signed by {} (not checked: no --signer given)", key),
+        None => println!("✍️ Unsigned"),
+    }
+
+    let blocks = rejected("blocks_rejected");
+    println!(
+        "⛓ Blocks: {} applied, {} skipped, {} rejected",
+        count("blocks_applied"),
+        count("blocks_skipped"),
+        blocks.len()
+    );
+    for r in &blocks {
+        println!("   ❌ {} ({})", r["id"].as_str().unwrap_or("?"), r["reason"].as_str().unwrap_or("?"));
+    }
+
+    let txs = rejected("transactions_rejected");
+    println!(
+        "🧾 Transactions: {} applied, {} skipped, {} rejected",
+        count("transactions_applied"),
+        count("transactions_skipped"),
+        txs.len()
+    );
+    for r in &txs {
+        println!("   ❌ {} ({})", r["id"].as_str().unwrap_or("?"), r["reason"].as_str().unwrap_or("?"));
+    }
+
+    println!("📏 Height now {}", count("height"));
+}
+
+/// Minimal HTTP/1.1 request to the node API: (status, body)
+fn api_request(method: &str, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>), String> {
+    let mut stream = TcpStream::connect(API_ADDR)
+        .map_err(|e| format!("Node API not reachable at {} ({}); is the node running?", API_ADDR, e))?;
+
+    let content_type = if body.first() == Some(&b'{') {
+        "application/json"
+    } else {
+        "application/octet-stream"
+    };
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        API_ADDR,
+        content_type,
+        body.len()
+    );
+
+    let mut response = Vec::new();
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .and_then(|_| stream.read_to_end(&mut response))
+        .map_err(|e| format!("Node API request failed: {}", e))?;
+
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("Bad response from node API")?;
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or("Bad response from node API")?;
+
+    if head.to_lowercase().contains("transfer-encoding: chunked") {
+        return Err("Unsupported chunked response from node API".to_string());
+    }
+
+    Ok((status, response[split + 4..].to_vec()))
+}
+
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
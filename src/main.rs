@@ -4,7 +4,6 @@ use std::thread::sleep;
 use std::time::{Duration, Instant};
 use std::io::{self, Write};
 use std::env;
-use std::net::SocketAddr;
 
 use tokio::runtime::Runtime;
 use rpassword::read_password;
@@ -12,17 +11,28 @@ use rpassword::read_password;
 // ───────── Imports from the LIB crate ─────────
 use bitcoin_v0_2_revelation::core::chain::Blockchain;
 use bitcoin_v0_2_revelation::node::p2p::P2PNetwork;
-use bitcoin_v0_2_revelation::node::transport::tcp::TcpTransport;
-use bitcoin_v0_2_revelation::node::transport::satellite::SatelliteTransport;
+use bitcoin_v0_2_revelation::node::transport::tcp::{TcpOptions, TcpTransport};
+use bitcoin_v0_2_revelation::node::transport::encryption::NodeKey;
+use bitcoin_v0_2_revelation::node::transport::satellite::{SatelliteTransport, SatelliteUplink, UplinkOptions};
 use bitcoin_v0_2_revelation::node::transport::bluetooth::BluetoothTransport;
-use bitcoin_v0_2_revelation::node::transport::geo::GeoTransport;
+use bitcoin_v0_2_revelation::node::transport::geo::{GeoTransport, LanNode, LocalNode};
 use bitcoin_v0_2_revelation::node::dedup::MessageDeduplicator;
+use bitcoin_v0_2_revelation::node::transport::{PeerEvent, Transport};
+use bitcoin_v0_2_revelation::node::peer_id::{PeerId, TransportKind};
+use bitcoin_v0_2_revelation::node::wire::peek_command;
 use bitcoin_v0_2_revelation::interface::{api::start_api, cli};
 use bitcoin_v0_2_revelation::node::mempool::Mempool;
+use bitcoin_v0_2_revelation::node::fee_estimator::{FeeEstimator, FEE_ESTIMATES_FILE};
 use bitcoin_v0_2_revelation::wallet::Wallet;
 use bitcoin_v0_2_revelation::wallet_store::load_wallet_store;
-use bitcoin_v0_2_revelation::config::load_miner_config;
-use bitcoin_v0_2_revelation::node::miner;
+use bitcoin_v0_2_revelation::config::{
+    load_config, miner_config_mtime, reload_miner_config, BanConfig, EncryptionConfig, MinerConfig, PayoutMode,
+    ProxyConfig, RelayConfig, SeedConfig, BAN_CONFIG_FILE, ENCRYPTION_CONFIG_FILE, GEO_CONFIG_FILE, MINER_CONFIG_FILE,
+    POLICY_CONFIG_FILE, PROXY_CONFIG_FILE, RELAY_CONFIG_FILE, SEED_CONFIG_FILE, UPLINK_CONFIG_FILE,
+};
+use bitcoin_v0_2_revelation::node::seeds::SystemResolver;
+use bitcoin_v0_2_revelation::node::miner::Miner;
+use bitcoin_v0_2_revelation::node::stratum::StratumServer;
 
 enum NodeMode {
     Syncing,
@@ -36,17 +46,68 @@ fn prompt_secret(msg: &str) -> String {
     read_password().unwrap()
 }
 
-/// 🌱 Bootstrap seeds (non-consensus)
-const BOOTSTRAP_SEEDS: &[&str] = &[
-    "bitcoin-revelation-node.fly.dev:8333",
-];
+/// P2P listen address; the port is advertised to peers so
+/// they can pass it on through address exchange
+const P2P_BIND: &str = "0.0.0.0:8333";
+
+/// Report miner config changes picked up at runtime
+///
+/// `threads` and the Stratum settings only apply on restart.
+fn apply_miner_config(old: &MinerConfig, new: &MinerConfig) {
+    if old.enabled != new.enabled {
+        if new.enabled {
+            println!("⛏ Mining enabled");
+        } else {
+            println!("⏸ Mining disabled");
+        }
+    }
+
+    if old.threads != new.threads
+        || old.stratum_bind != new.stratum_bind
+        || old.stratum_share_difficulty != new.stratum_share_difficulty
+        || old.stratum_max_workers != new.stratum_max_workers
+        || old.stratum_password != new.stratum_password
+    {
+        println!("ℹ️ threads / stratum changes take effect after restart");
+    }
+
+    println!("🔄 Miner config reloaded");
+}
+
+/// Fresh wallet address for the next block (PayoutMode::Rotate),
+/// for the built-in miner and the Stratum server alike
+fn rotate_payout(
+    wallet: &mut Wallet,
+    miner_config: &MinerConfig,
+    miner_pubkey_hash: &mut Vec<u8>,
+    stratum: Option<&StratumServer>,
+) {
+    match wallet.new_address() {
+        Ok(a) => *miner_pubkey_hash = a,
+        Err(e) => {
+            eprintln!("⚠️ address rotation failed: {}", e);
+            return;
+        }
+    }
+
+    if let Some(s) = stratum {
+        s.set_coinbase(miner_config.coinbase_spec(miner_pubkey_hash.clone()));
+    }
+}
 
 fn main() {
     println!("⛓ Bitcoin v0.3.3 — Revelation Edition (Consensus v3)");
 
+    // ───────── Sync bundles (no wallet needed) ─────────
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "bundle" {
+        cli::handle_bundle_command(args);
+        return;
+    }
+
     // ───────── Wallet & Miner Config ─────────
     let wallet_store = load_wallet_store();
-    let miner_config = load_miner_config();
+    let miner_config: MinerConfig = load_config(MINER_CONFIG_FILE);
 
     if wallet_store.get_path(&miner_config.coinbase_wallet).is_none() {
         panic!("Configured wallet '{}' not found", miner_config.coinbase_wallet);
@@ -56,7 +117,7 @@ fn main() {
     let password = prompt_secret("🔑 Enter wallet password: ");
 
     let mut wallet = Wallet::load_or_create(&password);
-    let miner_pubkey_hash = wallet.address().expect("wallet locked");
+    let mut miner_pubkey_hash = wallet.address().expect("wallet locked");
 
     println!(
         "👛 Miner pubkey hash ({}): {}",
@@ -69,10 +130,14 @@ fn main() {
     local_chain.initialize();
 
     let chain = Arc::new(Mutex::new(local_chain));
-    let mempool = Arc::new(Mutex::new(Mempool::new()));
+    let mempool = Arc::new(Mutex::new(
+        Mempool::with_estimator(
+            load_config(POLICY_CONFIG_FILE),
+            FeeEstimator::load(FEE_ESTIMATES_FILE),
+        )
+    ));
 
     // ───────── CLI MODE ─────────
-    let args: Vec<String> = env::args().collect();
     if args.len() > 1 && args[1] == "wallet" {
         cli::handle_command(
             args,
@@ -83,15 +148,6 @@ fn main() {
         return;
     }
 
-    // ───────── API Server ─────────
-    let api_chain = Arc::clone(&chain);
-    thread::spawn(move || {
-        let rt = Runtime::new().expect("Tokio runtime failed");
-        rt.block_on(start_api(api_chain, 8080));
-    });
-
-    println!("🌐 Explorer running at http://127.0.0.1:8080");
-
     // ───────── P2P BOOTSTRAP ─────────
 
     // Break circular ownership
@@ -108,64 +164,228 @@ fn main() {
         let p2p_holder = Arc::clone(&p2p_holder);
         let dedup = Arc::clone(&dedup);
 
-        move |addr: SocketAddr, data: Vec<u8>| {
-            let is_new = {
-                let mut d = dedup.lock().unwrap();
-                d.check_and_insert(&data)
+        move |peer: PeerId, data: Vec<u8>| {
+            // Only relayed data repeats across transports; per-peer
+            // control messages (hello, ping, ...) are identical bytes
+            // from different peers and must not be deduplicated
+            let command = peek_command(&data);
+            let relayed = matches!(command.as_deref(), Some("block") | Some("tx"));
+
+            let Some(p2p) = p2p_holder.lock().unwrap().clone() else {
+                return;
             };
 
-            if !is_new {
+            // A block we asked this peer for is its reply, not a
+            // relay, even if the same block was relayed just before
+            let requested =
+                command.as_deref() == Some("block") && p2p.is_requested_block(&peer, &data);
+
+            if relayed && !requested && !dedup.lock().unwrap().check_and_insert(&data) {
                 return; // drop duplicate silently
             }
 
+            p2p.on_receive(peer, data);
+        }
+    });
+
+    // Connection events (handshake starts here)
+    let on_event = Arc::new({
+        let p2p_holder = Arc::clone(&p2p_holder);
+
+        move |event: PeerEvent| {
             if let Some(p2p) = &*p2p_holder.lock().unwrap() {
-                p2p.on_receive(addr, data);
+                p2p.on_peer_event(event);
             }
         }
     });
 
     // ───────── TCP Transport ─────────
-    let transport = TcpTransport::new("0.0.0.0:0", on_receive.clone());
-
-    let p2p = Arc::new(
-        P2PNetwork::new(transport.clone(), Arc::clone(&chain))
-    );
+    let encryption: EncryptionConfig = load_config(ENCRYPTION_CONFIG_FILE);
+    let proxy: ProxyConfig = load_config(PROXY_CONFIG_FILE);
+
+    let mut options = TcpOptions {
+        proxy: proxy.proxy,
+        proxy_only: proxy.proxy_only,
+        ..Default::default()
+    };
+
+    if encryption.enabled {
+        let node_key = NodeKey::load_or_create();
+        println!("🔑 Node key {}", hex::encode(node_key.public.serialize()));
+
+        options.encryption = Some(
+            encryption
+                .policy(node_key)
+                .unwrap_or_else(|e| panic!("invalid encryption_config.json: {}", e)),
+        );
+    }
 
-    *p2p_holder.lock().unwrap() = Some(Arc::clone(&p2p));
+    if let Some(p) = proxy.proxy {
+        let mode = if proxy.proxy_only { "all outbound" } else { "onion only" };
+        println!("🧅 SOCKS5 proxy {} ({})", p, mode);
+    }
 
-    println!("🔗 P2P TCP transport initialized");
+    let transport =
+        TcpTransport::with_options(P2P_BIND, on_receive.clone(), on_event.clone(), options);
 
     // ───────── Satellite Transport (receive-only) ─────────
-    SatelliteTransport::listen_udp("0.0.0.0:9999", on_receive.clone());
-    // SatelliteTransport::listen_file("satellite.dat", on_receive.clone());
+    SatelliteTransport::listen_udp("0.0.0.0:9999", on_receive.clone(), on_event.clone());
+    // SatelliteTransport::listen_file("satellite.dat", on_receive.clone(), on_event.clone());
 
     // ───────── GEO / LAN Mesh ─────────
-    GeoTransport::start("0.0.0.0:9333", on_receive.clone());
+    // Announces our TCP port (unless proxy-only) and height;
+    // announced LAN nodes are dialled
+    let local_node = LocalNode {
+        tcp_port: if proxy.proxy_only {
+            0
+        } else {
+            transport.local_addr().map(|a| a.port()).unwrap_or(0)
+        },
+        height: {
+            let chain = Arc::clone(&chain);
+            Arc::new(move || chain.lock().unwrap().height())
+        },
+    };
+    let on_discover = Arc::new({
+        let p2p_holder = Arc::clone(&p2p_holder);
+
+        move |node: LanNode| {
+            if let Some(p2p) = &*p2p_holder.lock().unwrap() {
+                p2p.on_lan_node(node);
+            }
+        }
+    });
+    let geo = GeoTransport::start(
+        load_config(GEO_CONFIG_FILE),
+        local_node,
+        on_receive.clone(),
+        on_event.clone(),
+        on_discover,
+    );
 
     // ───────── Bluetooth BLE (async) ─────────
+    let bluetooth = BluetoothTransport::start(on_receive.clone(), on_event.clone());
+
+    // ───────── Satellite uplink (optional) ─────────
+    // Carousel of recent blocks and transactions, plus relays
+    let uplink_config: UplinkOptions = load_config(UPLINK_CONFIG_FILE);
+    let uplink = uplink_config.enabled.then(|| {
+        let blocks = uplink_config.carousel_blocks;
+        let transactions = uplink_config.carousel_transactions;
+        let carousel = Arc::new({
+            let p2p_holder = Arc::clone(&p2p_holder);
+
+            move || match &*p2p_holder.lock().unwrap() {
+                Some(p2p) => p2p.carousel(blocks, transactions),
+                None => Vec::new(),
+            }
+        });
+
+        SatelliteUplink::start(uplink_config.clone(), carousel)
+            .unwrap_or_else(|e| panic!("invalid uplink_config.json: {}", e))
+    });
+
+    // Relays go out over every transport that can send
+    let relay: RelayConfig = load_config(RELAY_CONFIG_FILE);
+    let ban_config: BanConfig = load_config(BAN_CONFIG_FILE);
+    let mut p2p = P2PNetwork::new(
+        transport.clone(),
+        Arc::clone(&chain),
+        Arc::clone(&mempool),
+    )
+    .with_route(TransportKind::Tcp, relay.route(TransportKind::Tcp))
+    .with_transport(geo, relay.route(TransportKind::Geo))
+    .with_transport(bluetooth, relay.route(TransportKind::Bluetooth))
+    .with_ban_config(ban_config.allowlist, ban_config.ban_time)
+    .with_proxy_only(proxy.proxy_only);
+
+    if let Some(uplink) = uplink {
+        p2p = p2p.with_transport(uplink, relay.route(TransportKind::Satellite));
+    }
+    let p2p = Arc::new(p2p);
+
+    *p2p_holder.lock().unwrap() = Some(Arc::clone(&p2p));
+
+    println!("🔗 P2P transports initialized (tcp, geo, ble, satellite)");
+
+    // ───────── API Server ─────────
+    let api_chain = Arc::clone(&chain);
+    let api_mempool = Arc::clone(&mempool);
+    let api_p2p = Arc::clone(&p2p);
+    thread::spawn(move || {
+        let rt = Runtime::new().expect("Tokio runtime failed");
+        rt.block_on(start_api(api_chain, api_mempool, api_p2p, 8080));
+    });
+
+    println!("🌐 Explorer running at http://127.0.0.1:8080");
+
+    // ───────── Bootstrap seeds (non-consensus) ─────────
+    let seed_config: SeedConfig = load_config(SEED_CONFIG_FILE);
+    println!("🌱 Network '{}'", seed_config.network);
+    p2p.bootstrap(seed_config.active_seeds(), Arc::new(SystemResolver));
+
+    // Handshakes, keepalive and outbound connections
+    p2p.start();
+
+    println!("🔄 Requesting sync from peers");
+
+    // ───────── Shutdown (Ctrl-C) ─────────
     {
-        let on_receive = on_receive.clone();
+        let transport = Arc::clone(&transport);
+        let p2p = Arc::clone(&p2p);
         thread::spawn(move || {
-            let rt = Runtime::new().unwrap();
-            rt.block_on(BluetoothTransport::start(on_receive));
+            let rt = Runtime::new().expect("Tokio runtime failed");
+            if rt.block_on(tokio::signal::ctrl_c()).is_err() {
+                return;
+            }
+
+            println!("🛑 Shutting down, closing peer connections");
+            transport.shutdown_blocking();
+            p2p.save_state();
+            std::process::exit(0);
         });
     }
 
-    // ───────── Connect bootstrap seeds ─────────
-    for seed in BOOTSTRAP_SEEDS {
-        if let Ok(addr) = seed.parse::<SocketAddr>() {
-            println!("🌱 Connecting to seed {}", seed);
-            transport.connect(addr);
-        }
+    // ───────── Payout ─────────
+    let mut miner_config = miner_config;
+    let mut config_mtime = miner_config_mtime();
+
+    if let PayoutMode::Rotate = miner_config.payout {
+        miner_pubkey_hash = wallet.new_address().expect("wallet locked");
     }
 
-    println!("🔄 Requesting sync from peers");
+    // ───────── Stratum pool server (optional) ─────────
+    let stratum = miner_config.stratum_bind.as_ref().and_then(|bind| {
+        StratumServer::start(
+            bind,
+            Arc::clone(&chain),
+            Arc::clone(&mempool),
+            Arc::clone(&p2p),
+            miner_config.coinbase_spec(miner_pubkey_hash.clone()),
+            miner_config.stratum_options(),
+        )
+        .map_err(|e| eprintln!("❌ Stratum server failed: {}", e))
+        .ok()
+    });
+
+    // ───────── Miner ─────────
+    let miner = Miner::new(
+        Arc::clone(&chain),
+        Arc::clone(&mempool),
+        miner_config.threads,
+    );
+    if miner_config.enabled {
+        println!("⛏ Mining with {} thread(s)", miner.threads());
+    } else {
+        println!("⏸ Mining disabled (miner_config.json)");
+    }
 
     // ───────── Node Loop ─────────
     let mut mode = NodeMode::Syncing;
     let mut last_height = chain.lock().unwrap().height();
     let mut last_change = Instant::now();
     let mut last_balance: u64 = 0;
+    let mut stratum_blocks: u64 = 0;
 
     loop {
         match mode {
@@ -186,37 +406,84 @@ fn main() {
             }
 
             NodeMode::Normal => {
-                let txs = mempool.lock().unwrap().sorted_for_mining();
+                // Pick up miner_config.json edits
+                let mtime = miner_config_mtime();
+                if mtime != config_mtime {
+                    config_mtime = mtime;
+                    match reload_miner_config() {
+                        Ok(new_config) => {
+                            apply_miner_config(&miner_config, &new_config);
+                            miner_config = new_config;
+
+                            if let PayoutMode::Rotate = miner_config.payout {
+                                rotate_payout(
+                                    &mut wallet,
+                                    &miner_config,
+                                    &mut miner_pubkey_hash,
+                                    stratum.as_ref(),
+                                );
+                            } else {
+                                if let PayoutMode::Wallet = miner_config.payout {
+                                    miner_pubkey_hash =
+                                        wallet.address().expect("wallet locked");
+                                }
+
+                                if let Some(s) = &stratum {
+                                    s.set_coinbase(
+                                        miner_config.coinbase_spec(miner_pubkey_hash.clone()),
+                                    );
+                                }
+                            }
+                        }
+                        Err(e) => eprintln!("⚠️ miner_config.json ignored: {}", e),
+                    }
+                }
 
-                let candidate_block = {
-                    let c = chain.lock().unwrap();
-                    let prev = c.blocks.last().unwrap();
-                    miner::mine_block(
-                        prev,
-                        &c.utxos,
-                        txs,
-                        miner_pubkey_hash.clone(),
-                        &c.blocks,
-                    )
-                };
+                // Blocks found by pool workers also use up the address
+                if let Some(s) = &stratum {
+                    let found = s.blocks_found();
+                    if found != stratum_blocks {
+                        stratum_blocks = found;
+                        if let PayoutMode::Rotate = miner_config.payout {
+                            rotate_payout(
+                                &mut wallet,
+                                &miner_config,
+                                &mut miner_pubkey_hash,
+                                stratum.as_ref(),
+                            );
+                        }
+                    }
+                }
 
-                let accepted = {
-                    let mut c = chain.lock().unwrap();
-                    c.validate_and_add_block(candidate_block.clone())
-                };
+                if !miner_config.enabled {
+                    sleep(Duration::from_secs(1));
+                    continue;
+                }
 
-                if accepted {
-                    p2p.broadcast_block(&candidate_block);
+                let coinbase = miner_config.coinbase_spec(miner_pubkey_hash.clone());
 
-                    mempool
-                        .lock()
-                        .unwrap()
-                        .remove_confirmed(&candidate_block.transactions);
+                // None = template went stale (new tip / mempool change)
+                let candidate_block = match miner.mine_next(&coinbase) {
+                    Some(b) => b,
+                    None => continue,
+                };
+
+                if p2p.submit_block(candidate_block) {
+                    // Fresh address for the next block
+                    if let PayoutMode::Rotate = miner_config.payout {
+                        rotate_payout(
+                            &mut wallet,
+                            &miner_config,
+                            &mut miner_pubkey_hash,
+                            stratum.as_ref(),
+                        );
+                    }
 
+                    let my_hashes = wallet.addresses().expect("wallet locked");
                     let c = chain.lock().unwrap();
                     let balance: u64 = c.utxos
                         .values()
-                        .filter(|u| u.pubkey_hash == miner_pubkey_hash)
+                        .filter(|u| my_hashes.contains(&u.pubkey_hash))
                         .map(|u| u.value)
                         .sum();
 
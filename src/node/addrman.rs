@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::node::message::NetAddress;
+use crate::node::netaddr::PeerAddress;
+
+const PEERS_FILE: &str = "data/peers.dat";
+
+/// Most addresses accepted in / sent in one Addr message
+pub const MAX_ADDR_PER_MSG: usize = 1000;
+
+/// Table sizes
+const MAX_NEW: usize = 4096;
+const MAX_TRIED: usize = 1024;
+
+/// Addresses not heard of for this long are forgotten (30 days)
+const ADDR_HORIZON: i64 = 30 * 24 * 60 * 60;
+
+/// Timestamps further ahead than this are clamped to now
+const MAX_FUTURE_SKEW: i64 = 10 * 60;
+
+/// Do not re-dial an address sooner than this (seconds)
+const RETRY_INTERVAL: i64 = 60;
+
+/// Never-successful addresses are dropped after this many tries
+const MAX_FAILURES: u32 = 5;
+
+/// A known peer address
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AddrEntry {
+    pub addr: String,
+    pub services: u64,
+
+    /// Last time the network reported the address alive (unix)
+    pub last_seen: i64,
+    /// Last connection attempt / success (unix, 0 = never)
+    pub last_try: i64,
+    pub last_success: i64,
+
+    /// Failed attempts since the last success
+    pub attempts: u32,
+}
+
+/// Address manager (POLICY ONLY)
+///
+/// Two tables, as in Bitcoin Core:
+///   new   — heard of (Addr messages, seeds), never connected
+///   tried — completed a handshake with us at least once
+///
+/// Outbound peers are picked from both tables evenly, so a
+/// flood of fake addresses into `new` cannot crowd out peers
+/// known to work. Persisted to data/peers.dat.
+///
+/// Entries are "ip:port" or "<onion>.onion:port"; onion
+/// addresses are kept and gossiped even by nodes that cannot
+/// reach them. LAN and other non-public addresses are taken
+/// only from the operator or LAN discovery, and never gossiped.
+#[derive(Serialize, Deserialize, Default)]
+pub struct AddrMan {
+    new: HashMap<String, AddrEntry>,
+    tried: HashMap<String, AddrEntry>,
+
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl AddrMan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the address book, or start empty
+    pub fn load() -> Self {
+        match fs::read(PEERS_FILE) {
+            Ok(data) => bincode::deserialize(&data).unwrap_or_default(),
+            Err(_) => Self::new(),
+        }
+    }
+
+    pub fn save(&mut self) {
+        if fs::create_dir_all("data").is_ok()
+            && fs::write(PEERS_FILE, bincode::serialize(self).unwrap()).is_ok()
+        {
+            self.dirty = false;
+        }
+    }
+
+    /// Changed since the last save
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn len(&self) -> usize {
+        self.new.len() + self.tried.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Learn addresses from the network (Addr messages, DNS
+    /// seeds, inbound listeners)
+    ///
+    /// Non-public, unusable or stale entries are skipped.
+    /// Returns how many were new to us.
+    pub fn add(&mut self, addrs: &[NetAddress]) -> usize {
+        self.learn(addrs, is_public)
+    }
+
+    /// Add one address seen alive just now (DNS seeds, inbound
+    /// peers' listening address)
+    pub fn add_seen(&mut self, addr: &str, services: u64) -> bool {
+        self.add(&[seen_now(addr, services)]) > 0
+    }
+
+    /// Add an address from the operator (seed list entries) or
+    /// LAN discovery; private ranges are allowed
+    pub fn add_local(&mut self, addr: &str, services: u64) -> bool {
+        self.learn(&[seen_now(addr, services)], is_routable) > 0
+    }
+
+    fn learn(&mut self, addrs: &[NetAddress], acceptable: fn(&str) -> bool) -> usize {
+        let now = unix_now();
+        let mut added = 0;
+
+        for a in addrs {
+            if !acceptable(&a.addr) {
+                continue;
+            }
+
+            let last_seen = if a.timestamp > now + MAX_FUTURE_SKEW {
+                now
+            } else {
+                a.timestamp
+            };
+
+            if now - last_seen > ADDR_HORIZON {
+                continue;
+            }
+
+            if let Some(e) = self.tried.get_mut(&a.addr) {
+                e.last_seen = e.last_seen.max(last_seen);
+                e.services |= a.services;
+                continue;
+            }
+
+            match self.new.get_mut(&a.addr) {
+                Some(e) => {
+                    e.last_seen = e.last_seen.max(last_seen);
+                    e.services |= a.services;
+                }
+                None => {
+                    if self.new.len() >= MAX_NEW {
+                        evict_oldest(&mut self.new);
+                    }
+
+                    self.new.insert(
+                        a.addr.clone(),
+                        AddrEntry {
+                            addr: a.addr.clone(),
+                            services: a.services,
+                            last_seen,
+                            last_try: 0,
+                            last_success: 0,
+                            attempts: 0,
+                        },
+                    );
+                    added += 1;
+                }
+            }
+        }
+
+        if added > 0 {
+            self.dirty = true;
+        }
+
+        added
+    }
+
+    /// About to dial `addr`
+    pub fn attempt(&mut self, addr: &str) {
+        let now = unix_now();
+
+        if let Some(e) = self.tried.get_mut(addr) {
+            e.last_try = now;
+            e.attempts += 1;
+        } else if let Some(e) = self.new.get_mut(addr) {
+            e.last_try = now;
+            e.attempts += 1;
+
+            if e.attempts >= MAX_FAILURES {
+                self.new.remove(addr);
+            }
+        }
+
+        self.dirty = true;
+    }
+
+    /// Handshake with `addr` completed: move it to `tried`
+    pub fn good(&mut self, addr: &str) {
+        if !is_routable(addr) {
+            return;
+        }
+
+        let now = unix_now();
+
+        let mut entry = self
+            .tried
+            .remove(addr)
+            .or_else(|| self.new.remove(addr))
+            .unwrap_or_else(|| AddrEntry {
+                addr: addr.to_string(),
+                services: 0,
+                last_seen: now,
+                last_try: now,
+                last_success: 0,
+                attempts: 0,
+            });
+
+        entry.last_seen = now;
+        entry.last_success = now;
+        entry.attempts = 0;
+
+        // Full: the stalest tried entry goes back to `new`
+        if self.tried.len() >= MAX_TRIED {
+            if let Some(oldest) = oldest_key(&self.tried) {
+                if let Some(e) = self.tried.remove(&oldest) {
+                    if self.new.len() >= MAX_NEW {
+                        evict_oldest(&mut self.new);
+                    }
+                    self.new.insert(oldest, e);
+                }
+            }
+        }
+
+        self.tried.insert(addr.to_string(), entry);
+        self.dirty = true;
+    }
+
+    /// Pick an address to dial, not in `exclude` and not
+    /// tried within RETRY_INTERVAL
+    ///
+    /// Onion addresses only if `onion` (a proxy can reach them).
+    pub fn select(&self, exclude: &[String], onion: bool) -> Option<String> {
+        let now = unix_now();
+        let eligible = |e: &&AddrEntry| {
+            now - e.last_try >= RETRY_INTERVAL
+                && !exclude.contains(&e.addr)
+                && (onion || !is_onion(&e.addr))
+        };
+
+        let mut rng = rand::thread_rng();
+        let from_tried = self.tried.values().filter(eligible).choose(&mut rng);
+        let from_new = self.new.values().filter(eligible).choose(&mut rng);
+
+        let pick = match (from_tried, from_new) {
+            (Some(t), Some(n)) => {
+                if rng.gen_bool(0.5) { t } else { n }
+            }
+            (Some(t), None) => t,
+            (None, Some(n)) => n,
+            (None, None) => return None,
+        };
+
+        Some(pick.addr.clone())
+    }
+
+    /// Random sample of live public addresses for a GetAddr reply
+    pub fn get_addr(&self, max: usize) -> Vec<NetAddress> {
+        let now = unix_now();
+        let mut rng = rand::thread_rng();
+
+        self.tried
+            .values()
+            .chain(self.new.values())
+            .filter(|e| now - e.last_seen <= ADDR_HORIZON && is_public(&e.addr))
+            .choose_multiple(&mut rng, max.min(MAX_ADDR_PER_MSG))
+            .into_iter()
+            .map(|e| NetAddress {
+                addr: e.addr.clone(),
+                services: e.services,
+                timestamp: e.last_seen,
+            })
+            .collect()
+    }
+}
+
+/// Address we could plausibly connect to: an IP endpoint or
+/// an onion service (other host names are not gossiped)
+fn is_routable(addr: &str) -> bool {
+    match PeerAddress::parse(addr) {
+        Ok(PeerAddress::Ip(a)) => {
+            a.port() != 0 && !a.ip().is_unspecified() && !a.ip().is_multicast()
+        }
+        Ok(p) => p.is_onion() && p.port() != 0,
+        Err(_) => false,
+    }
+}
+
+/// Address worth telling the network about
+fn is_public(addr: &str) -> bool {
+    PeerAddress::parse(addr).map(|p| p.is_public()).unwrap_or(false)
+}
+
+fn seen_now(addr: &str, services: u64) -> NetAddress {
+    NetAddress {
+        addr: addr.to_string(),
+        services,
+        timestamp: unix_now(),
+    }
+}
+
+fn is_onion(addr: &str) -> bool {
+    PeerAddress::parse(addr).map(|p| p.is_onion()).unwrap_or(false)
+}
+
+fn oldest_key(table: &HashMap<String, AddrEntry>) -> Option<String> {
+    table
+        .values()
+        .min_by_key(|e| e.last_seen)
+        .map(|e| e.addr.clone())
+}
+
+fn evict_oldest(table: &mut HashMap<String, AddrEntry>) {
+    if let Some(k) = oldest_key(table) {
+        table.remove(&k);
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gossiped(addr: &str) -> NetAddress {
+        NetAddress {
+            addr: addr.to_string(),
+            services: 1,
+            timestamp: unix_now(),
+        }
+    }
+
+    #[test]
+    fn gossip_takes_public_addresses_only() {
+        let onion = format!("{}.onion:8333", "a".repeat(56));
+        let public = ["8.8.8.8:8333", "[2001:4860::8888]:8333", onion.as_str()];
+        let private = [
+            "127.0.0.1:8333",
+            "10.1.2.3:8333",
+            "172.16.0.1:8333",
+            "192.168.1.10:8333",
+            "169.254.1.1:8333",
+            "100.64.0.1:8333",
+            "[::1]:8333",
+            "[fd00::1]:8333",
+            "[fe80::1]:8333",
+            "[::ffff:192.168.1.10]:8333",
+            "0.0.0.0:8333",
+            "8.8.8.8:0",
+        ];
+
+        let mut book = AddrMan::new();
+        let all: Vec<NetAddress> = public.iter().chain(&private).map(|a| gossiped(a)).collect();
+        assert_eq!(book.add(&all), public.len());
+
+        for a in private {
+            assert!(!book.add_seen(a, 1), "{} accepted from the network", a);
+        }
+    }
+
+    #[test]
+    fn local_addresses_are_kept_but_not_gossiped() {
+        let mut book = AddrMan::new();
+        assert!(book.add_local("192.168.1.10:8333", 1));
+        assert!(book.add_local("8.8.8.8:8333", 1));
+        assert!(!book.add_local("0.0.0.0:8333", 1));
+
+        // A LAN peer we connected to is still ours to dial
+        book.good("10.0.0.7:8333");
+        assert_eq!(book.len(), 3);
+
+        let shared: Vec<String> = book.get_addr(100).into_iter().map(|a| a.addr).collect();
+        assert_eq!(shared, ["8.8.8.8:8333"]);
+    }
+}
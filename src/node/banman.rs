@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize, Deserializer, Serializer};
+
+use crate::node::peer_id::PeerId;
+
+const BANLIST_FILE: &str = "data/banlist.json";
+
+/// Ban length when none is given (24 hours)
+pub const DEFAULT_BAN_TIME: u64 = 24 * 60 * 60;
+
+/// What a ban applies to
+///
+/// Peers with an IP address are banned by IP (all their
+/// connections); the others (BLE device, satellite feed, ...)
+/// by their peer id. Text form: "1.2.3.4" or "ble:<device>".
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BanTarget {
+    Ip(IpAddr),
+    Peer(PeerId),
+}
+
+impl BanTarget {
+    /// Target that covers `peer`
+    pub fn of(peer: &PeerId) -> Self {
+        match peer.ip() {
+            Some(ip) => BanTarget::Ip(ip),
+            None => BanTarget::Peer(peer.clone()),
+        }
+    }
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanTarget::Ip(ip) => write!(f, "{}", ip),
+            BanTarget::Peer(p) => write!(f, "{}", p),
+        }
+    }
+}
+
+impl FromStr for BanTarget {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(BanTarget::Ip(ip));
+        }
+        Ok(BanTarget::of(&s.parse::<PeerId>()?))
+    }
+}
+
+impl Serialize for BanTarget {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BanTarget {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A banned address
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BanEntry {
+    /// "ip" in ban lists written before non-IP peers
+    #[serde(alias = "ip")]
+    pub target: BanTarget,
+    /// Unix time the ban was set / lifts
+    pub created: i64,
+    pub until: i64,
+    pub reason: String,
+}
+
+/// Ban list (POLICY ONLY)
+///
+/// Time-limited bans by IP or peer id, persisted to data/banlist.json so
+/// they survive restarts. Expired bans are dropped lazily.
+#[derive(Serialize, Deserialize, Default)]
+pub struct BanMan {
+    bans: HashMap<BanTarget, BanEntry>,
+}
+
+impl BanMan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the ban list, or start empty
+    pub fn load() -> Self {
+        let mut banman: Self = match fs::read_to_string(BANLIST_FILE) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
+            Err(_) => Self::new(),
+        };
+        banman.sweep();
+        banman
+    }
+
+    pub fn save(&self) {
+        if fs::create_dir_all("data").is_ok() {
+            let _ = fs::write(
+                BANLIST_FILE,
+                serde_json::to_string_pretty(self).unwrap(),
+            );
+        }
+    }
+
+    /// Ban `target` for `duration` seconds (extends, never shortens)
+    pub fn ban(&mut self, target: BanTarget, duration: u64, reason: &str) {
+        let now = unix_now();
+        let until = now.saturating_add(duration as i64);
+
+        let entry = self.bans.entry(target.clone()).or_insert(BanEntry {
+            target,
+            created: now,
+            until,
+            reason: reason.to_string(),
+        });
+
+        if until > entry.until {
+            entry.until = until;
+            entry.reason = reason.to_string();
+        }
+
+        self.save();
+    }
+
+    /// Lift a ban; false if `target` was not banned
+    pub fn unban(&mut self, target: &BanTarget) -> bool {
+        let removed = self.bans.remove(target).is_some();
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    pub fn is_banned(&self, target: &BanTarget) -> bool {
+        self.bans
+            .get(target)
+            .map(|b| b.until > unix_now())
+            .unwrap_or(false)
+    }
+
+    /// Active bans
+    pub fn list(&mut self) -> Vec<BanEntry> {
+        self.sweep();
+        self.bans.values().cloned().collect()
+    }
+
+    /// Drop expired bans
+    pub fn sweep(&mut self) {
+        let now = unix_now();
+        let before = self.bans.len();
+        self.bans.retain(|_, b| b.until > now);
+
+        if self.bans.len() != before {
+            self.save();
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
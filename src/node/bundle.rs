@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::block::Block;
+use crate::chain::Blockchain;
+use crate::crypto::verify_signature;
+use crate::node::mempool::Mempool;
+use crate::node::message::NetworkMessage;
+use crate::node::transport::encryption::NodeKey;
+use crate::node::wire::{decode_message, encode_message, NETWORK_MAGIC};
+use crate::transaction::Transaction;
+
+/// First bytes of every bundle
+pub const BUNDLE_TAG: [u8; 4] = *b"RVSB";
+const BUNDLE_VERSION: u8 = 1;
+
+/// tag | version | magic | created | first height | blocks | txs
+const HEADER_SIZE: usize = 4 + 1 + 4 + 8 + 8 + 4 + 4;
+
+/// Largest bundle written or read; an export stops adding
+/// blocks before this (the rest goes in the next bundle)
+pub const MAX_BUNDLE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Most recent block hashes in a tip file, before the
+/// locator starts skipping
+const LOCATOR_DENSE: usize = 10;
+
+/// Sneakernet sync bundle
+///
+/// Blocks (consecutive, from `first_height`) and transactions
+/// to carry to a node that cannot reach the network. The
+/// manifest (network, height range, block hashes) is checked
+/// against the contents, the whole file against a SHA-256
+/// checksum, and the checksum against an optional node-key
+/// signature, so a damaged or tampered file is refused as a
+/// whole. Blocks are still fully validated on import.
+///
+/// Layout (integers big-endian):
+/// header | block hashes (32 each) | entries (len u32 | wire
+/// frame), blocks then transactions | SHA-256 of all that |
+/// 0, or 1 | signer key (33) | signature of the checksum (64)
+pub struct Bundle {
+    /// Unix time of the export
+    pub created: u64,
+    pub first_height: u64,
+    pub blocks: Vec<Block>,
+    pub transactions: Vec<Transaction>,
+    /// Compressed public key of the signing node
+    pub signer: Option<Vec<u8>>,
+}
+
+impl Bundle {
+    /// Blocks above `height` (at most what fits the size cap),
+    /// plus the mempool if `transactions`
+    pub fn export(chain: &Blockchain, mempool: &Mempool, height: u64, transactions: bool) -> Self {
+        let first_height = height + 1;
+        let mut size = HEADER_SIZE + 32 + 1 + 33 + 64;
+        let mut blocks = Vec::new();
+
+        for block in chain.blocks.iter().skip(first_height as usize) {
+            size += 32 + 4 + encode_message(&NetworkMessage::Block(block.clone())).len();
+            if size > MAX_BUNDLE_SIZE {
+                break;
+            }
+            blocks.push(block.clone());
+        }
+
+        let mut txs = Vec::new();
+        if transactions {
+            for tx in mempool.sorted_for_mining() {
+                size += 4 + encode_message(&NetworkMessage::Transaction(tx.clone())).len();
+                if size > MAX_BUNDLE_SIZE {
+                    break;
+                }
+                txs.push(tx);
+            }
+        }
+
+        Self {
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            first_height,
+            blocks,
+            transactions: txs,
+            signer: None,
+        }
+    }
+
+    /// Height of the last block, if any
+    pub fn last_height(&self) -> Option<u64> {
+        (!self.blocks.is_empty()).then(|| self.first_height + self.blocks.len() as u64 - 1)
+    }
+
+    /// Serialize, signed with `key` if given
+    pub fn encode(&self, key: Option<&NodeKey>) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&BUNDLE_TAG);
+        out.push(BUNDLE_VERSION);
+        out.extend_from_slice(&NETWORK_MAGIC);
+        out.extend_from_slice(&self.created.to_be_bytes());
+        out.extend_from_slice(&self.first_height.to_be_bytes());
+        out.extend_from_slice(&(self.blocks.len() as u32).to_be_bytes());
+        out.extend_from_slice(&(self.transactions.len() as u32).to_be_bytes());
+
+        for block in &self.blocks {
+            out.extend_from_slice(&hash32(&block.hash));
+        }
+
+        let frames = self
+            .blocks
+            .iter()
+            .map(|b| encode_message(&NetworkMessage::Block(b.clone())))
+            .chain(
+                self.transactions
+                    .iter()
+                    .map(|tx| encode_message(&NetworkMessage::Transaction(tx.clone()))),
+            );
+        for frame in frames {
+            out.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            out.extend_from_slice(&frame);
+        }
+
+        let checksum = Sha256::digest(&out);
+        out.extend_from_slice(&checksum);
+
+        match key {
+            Some(key) => {
+                out.push(1);
+                out.extend_from_slice(&key.public.serialize());
+                out.extend_from_slice(&key.sign(&checksum));
+            }
+            None => out.push(0),
+        }
+
+        out
+    }
+
+    /// Signed by one of `keys` (compressed public keys)
+    pub fn signed_by(&self, keys: &[Vec<u8>]) -> bool {
+        self.signer.as_ref().is_some_and(|s| keys.contains(s))
+    }
+
+    /// Parse and verify a bundle of our network
+    ///
+    /// A signature by any key is accepted: it proves which node
+    /// wrote the file, not that the node is trusted. Check the
+    /// signer with `signed_by` before relying on it.
+    pub fn decode(data: &[u8]) -> Result<Self, &'static str> {
+        if data.len() > MAX_BUNDLE_SIZE {
+            return Err("bundle too large");
+        }
+        if data.len() < HEADER_SIZE || !data.starts_with(&BUNDLE_TAG) {
+            return Err("not a bundle");
+        }
+        if data[4] != BUNDLE_VERSION {
+            return Err("unsupported bundle version");
+        }
+        if data[5..9] != NETWORK_MAGIC {
+            return Err("bundle is for another network");
+        }
+
+        let created = u64::from_be_bytes(data[9..17].try_into().unwrap());
+        let first_height = u64::from_be_bytes(data[17..25].try_into().unwrap());
+        let block_count = u32::from_be_bytes(data[25..29].try_into().unwrap()) as usize;
+        let tx_count = u32::from_be_bytes(data[29..33].try_into().unwrap()) as usize;
+
+        // Everything up to the checksum
+        let mut reader = Reader { data, pos: HEADER_SIZE };
+        let hashes: Vec<&[u8]> = (0..block_count)
+            .map(|_| reader.take(32))
+            .collect::<Result<_, _>>()?;
+
+        // Counts are unchecked until the entries are read
+        let mut frames = Vec::new();
+        for _ in 0..block_count + tx_count {
+            let len = u32::from_be_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+            frames.push(reader.take(len)?);
+        }
+
+        let body_end = reader.pos;
+        let checksum = reader.take(32)?;
+        if Sha256::digest(&data[..body_end])[..] != *checksum {
+            return Err("bundle checksum mismatch");
+        }
+
+        let signer = match reader.take(1)?[0] {
+            0 => None,
+            1 => {
+                let key = reader.take(33)?;
+                let sig = reader.take(64)?;
+                if !verify_signature(checksum, sig, key) {
+                    return Err("bad bundle signature");
+                }
+                Some(key.to_vec())
+            }
+            _ => return Err("bad signature flag"),
+        };
+
+        if reader.pos != data.len() {
+            return Err("trailing bytes after bundle");
+        }
+
+        // Contents must match the manifest
+        let mut blocks = Vec::with_capacity(block_count);
+        for (i, frame) in frames[..block_count].iter().enumerate() {
+            let block = match decode_message(frame) {
+                Ok(NetworkMessage::Block(b)) => b,
+                _ => return Err("bad block entry"),
+            };
+            if block.hash != hashes[i] || block.header.height != first_height + i as u64 {
+                return Err("block does not match manifest");
+            }
+            blocks.push(block);
+        }
+
+        let mut transactions = Vec::with_capacity(tx_count);
+        for frame in &frames[block_count..] {
+            match decode_message(frame) {
+                Ok(NetworkMessage::Transaction(tx)) => transactions.push(tx),
+                _ => return Err("bad transaction entry"),
+            }
+        }
+
+        Ok(Self {
+            created,
+            first_height,
+            blocks,
+            transactions,
+            signer,
+        })
+    }
+}
+
+/// Bounds-checked cursor over a bundle
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.pos.checked_add(len).ok_or("bundle truncated")?;
+        let out = self.data.get(self.pos..end).ok_or("bundle truncated")?;
+        self.pos = end;
+        Ok(out)
+    }
+}
+
+/// Block hashes are 32 bytes; anything else is padded / cut
+/// (and then fails the manifest check on import)
+fn hash32(hash: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    let n = hash.len().min(32);
+    out[..n].copy_from_slice(&hash[..n]);
+    out
+}
+
+/// What an offline node has, so a connected one can export
+/// exactly what it lacks
+///
+/// `locator`: hashes (hex) from the tip back, the last ten
+/// blocks one by one, then doubling the step down to genesis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TipFile {
+    /// Network magic, hex
+    pub network: String,
+    pub height: u64,
+    pub locator: Vec<String>,
+}
+
+impl TipFile {
+    pub fn from_chain(chain: &Blockchain) -> Self {
+        let mut locator = Vec::new();
+
+        if let Some(tip) = chain.blocks.last() {
+            let mut height = tip.header.height;
+            let mut step = 1;
+
+            loop {
+                if let Some(block) = chain.blocks.get(height as usize) {
+                    locator.push(hex::encode(&block.hash));
+                }
+                if height == 0 {
+                    break;
+                }
+                if locator.len() >= LOCATOR_DENSE {
+                    step *= 2;
+                }
+                height = height.saturating_sub(step);
+            }
+        }
+
+        Self {
+            network: hex::encode(NETWORK_MAGIC),
+            height: chain.height(),
+            locator,
+        }
+    }
+
+    /// Height of the highest locator block on our chain: the
+    /// other node has everything up to there
+    pub fn fork_height(&self, chain: &Blockchain) -> Result<u64, &'static str> {
+        if self.network != hex::encode(NETWORK_MAGIC) {
+            return Err("tip file is for another network");
+        }
+
+        let ours: HashMap<String, u64> = chain
+            .blocks
+            .iter()
+            .map(|b| (hex::encode(&b.hash), b.header.height))
+            .collect();
+
+        self.locator
+            .iter()
+            .find_map(|h| ours.get(h).copied())
+            .ok_or("no block in common with the tip file")
+    }
+}
+
+/// A bundle entry that was not applied
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejected {
+    /// Block hash or txid, hex
+    pub id: String,
+    pub reason: &'static str,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ImportReport {
+    /// Signing node key, hex
+    pub signer: Option<String>,
+    /// The signer is one of the keys the import was told to
+    /// trust (false: not checked)
+    pub trusted: bool,
+    pub blocks_applied: usize,
+    /// Already on our chain
+    pub blocks_skipped: usize,
+    pub blocks_rejected: Vec<Rejected>,
+    pub transactions_applied: usize,
+    /// Already in our mempool
+    pub transactions_skipped: usize,
+    pub transactions_rejected: Vec<Rejected>,
+    /// Our height afterwards
+    pub height: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> Bundle {
+        Bundle {
+            created: 1_730_000_000,
+            first_height: 5,
+            blocks: vec![],
+            transactions: vec![],
+            signer: None,
+        }
+    }
+
+    #[test]
+    fn any_signer_decodes_but_only_listed_ones_are_trusted() {
+        let ours = NodeKey::generate();
+        let theirs = NodeKey::generate();
+        let trusted = [ours.public.serialize().to_vec()];
+
+        let signed = Bundle::decode(&empty().encode(Some(&ours))).unwrap();
+        assert!(signed.signed_by(&trusted));
+
+        let stranger = Bundle::decode(&empty().encode(Some(&theirs))).unwrap();
+        assert_eq!(stranger.signer, Some(theirs.public.serialize().to_vec()));
+        assert!(!stranger.signed_by(&trusted));
+
+        let unsigned = Bundle::decode(&empty().encode(None)).unwrap();
+        assert!(!unsigned.signed_by(&trusted));
+    }
+
+    #[test]
+    fn damaged_bundles_are_refused() {
+        let key = NodeKey::generate();
+        let data = empty().encode(Some(&key));
+
+        for i in [5, HEADER_SIZE + 3, data.len() - 1] {
+            let mut bad = data.clone();
+            bad[i] ^= 1;
+            assert!(Bundle::decode(&bad).is_err(), "byte {}", i);
+        }
+        assert!(Bundle::decode(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -7,6 +7,10 @@ use sha2::{Sha256, Digest};
 ///
 /// Prevents the same raw message bytes from being processed
 /// multiple times across different transports.
+///
+/// Keyed by content only: who delivered a copy does not matter
+/// for relayed data, and the receive path already hands the
+/// sender's PeerId to P2P for everything that gets through.
 pub struct MessageDeduplicator {
     seen: HashMap<[u8; 32], Instant>,
     ttl: Duration,
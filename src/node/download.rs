@@ -0,0 +1,374 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::core::block::Block;
+use crate::node::peer_id::PeerId;
+
+/// Blocks per GetBlocks request
+pub const BLOCKS_PER_REQUEST: u64 = 16;
+
+/// How far past our tip blocks are fetched
+pub const DOWNLOAD_WINDOW: u64 = 1024;
+
+/// Requests outstanding per peer
+pub const MAX_IN_FLIGHT_PER_PEER: usize = 4;
+
+/// A request not completed in this time is reassigned
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Stalls before the peer is dropped
+const MAX_STALLS: u32 = 3;
+
+/// An outstanding GetBlocks
+struct Request {
+    peer: PeerId,
+    from: u64,
+    count: u64,
+    sent: Instant,
+    received: u64,
+}
+
+impl Request {
+    fn covers(&self, height: u64) -> bool {
+        height >= self.from && height < self.from + self.count
+    }
+}
+
+/// Block download scheduler (POLICY ONLY)
+///
+/// Splits the heights between our tip and the longest chain
+/// peers announced into fixed chunks, spreads the chunks
+/// over all handshaked peers within a moving window, and
+/// buffers what arrives so blocks connect strictly in order.
+///
+/// A slow peer only delays its own chunks: requests that
+/// stall are handed to someone else, and repeat stallers
+/// are reported for disconnection.
+#[derive(Default)]
+pub struct BlockDownloader {
+    /// Chunk start → request
+    in_flight: HashMap<u64, Request>,
+
+    /// Downloaded, waiting for their turn to connect
+    received: BTreeMap<u64, (Block, PeerId)>,
+
+    stalls: HashMap<PeerId, u32>,
+}
+
+impl BlockDownloader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests to send now: (peer, from_height, count)
+    ///
+    /// `peers` are handshaked peers with their chain length
+    /// (blocks held, as announced in Hello).
+    pub fn schedule(
+        &mut self,
+        local_height: u64,
+        peers: &[(PeerId, u64)],
+    ) -> Vec<(PeerId, u64, u64)> {
+        self.prune(local_height);
+
+        let target = match peers.iter().map(|(_, len)| *len).max() {
+            Some(t) if t > local_height => t,
+            _ => return vec![],
+        };
+
+        let window_end = target.min(local_height + DOWNLOAD_WINDOW);
+        let mut out = Vec::new();
+
+        let mut start = local_height - local_height % BLOCKS_PER_REQUEST;
+        while start < window_end {
+            let chunk_start = start;
+            start += BLOCKS_PER_REQUEST;
+
+            if self.in_flight.contains_key(&chunk_start) {
+                continue;
+            }
+
+            let from = chunk_start.max(local_height);
+            let end = (chunk_start + BLOCKS_PER_REQUEST).min(window_end);
+
+            if (from..end).all(|h| self.received.contains_key(&h)) {
+                continue;
+            }
+
+            // Least loaded peer that has the whole range
+            let peer = peers
+                .iter()
+                .filter(|(_, len)| *len >= end)
+                .map(|(a, _)| (a, self.load(a)))
+                .filter(|(_, load)| *load < MAX_IN_FLIGHT_PER_PEER)
+                .min_by_key(|(_, load)| *load)
+                .map(|(a, _)| a.clone());
+
+            let Some(peer) = peer else {
+                continue;
+            };
+
+            let count = end - from;
+            self.in_flight.insert(
+                chunk_start,
+                Request {
+                    peer: peer.clone(),
+                    from,
+                    count,
+                    sent: Instant::now(),
+                    received: 0,
+                },
+            );
+            out.push((peer, from, count));
+        }
+
+        out
+    }
+
+    /// Block at `height` was requested from `peer`
+    pub fn expects(&self, peer: &PeerId, height: u64) -> bool {
+        self.in_flight
+            .values()
+            .any(|r| r.peer == *peer && r.covers(height))
+    }
+
+    /// Buffer a requested block
+    pub fn block_received(&mut self, peer: PeerId, block: Block) {
+        let height = block.header.height;
+
+        let key = self
+            .in_flight
+            .iter()
+            .find(|(_, r)| r.peer == peer && r.covers(height))
+            .map(|(k, _)| *k);
+
+        let Some(key) = key else {
+            return;
+        };
+
+        let done = {
+            let r = self.in_flight.get_mut(&key).unwrap();
+            r.received += 1;
+            r.received >= r.count
+        };
+
+        if done {
+            self.in_flight.remove(&key);
+            self.stalls.remove(&peer);
+        }
+
+        self.received.insert(height, (block, peer));
+    }
+
+    /// Next block to connect on top of `local_height`
+    pub fn take_next(&mut self, local_height: u64) -> Option<(Block, PeerId)> {
+        self.received.remove(&local_height)
+    }
+
+    /// Forget everything downloaded from `peer` (its data failed
+    /// validation); the heights are fetched again elsewhere
+    pub fn discard_from(&mut self, peer: &PeerId) {
+        self.received.retain(|_, (_, from)| from != peer);
+        self.in_flight.retain(|_, r| r.peer != *peer);
+    }
+
+    pub fn peer_disconnected(&mut self, peer: &PeerId) {
+        self.in_flight.retain(|_, r| r.peer != *peer);
+        self.stalls.remove(peer);
+    }
+
+    /// Drop stalled requests so they are reassigned
+    ///
+    /// Requests the chain already covers are pruned first, so a
+    /// peer is not blamed for blocks we got elsewhere.
+    /// Returns peers that stalled MAX_STALLS times in a row.
+    pub fn check_stalls(&mut self, local_height: u64) -> Vec<PeerId> {
+        self.prune(local_height);
+
+        let stalled: Vec<u64> = self
+            .in_flight
+            .iter()
+            .filter(|(_, r)| r.sent.elapsed() > STALL_TIMEOUT)
+            .map(|(k, _)| *k)
+            .collect();
+
+        let mut drop = Vec::new();
+
+        for key in stalled {
+            if let Some(r) = self.in_flight.remove(&key) {
+                let count = self.stalls.entry(r.peer.clone()).or_insert(0);
+                *count += 1;
+
+                if *count >= MAX_STALLS && !drop.contains(&r.peer) {
+                    drop.push(r.peer);
+                }
+            }
+        }
+
+        drop
+    }
+
+    fn load(&self, peer: &PeerId) -> usize {
+        self.in_flight.values().filter(|r| r.peer == *peer).count()
+    }
+
+    /// Drop what the chain already has
+    fn prune(&mut self, local_height: u64) {
+        self.received = self.received.split_off(&local_height);
+        self.in_flight
+            .retain(|_, r| r.from + r.count > local_height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::BlockHeader;
+
+    fn peer(n: u8) -> PeerId {
+        PeerId::Tcp(format!("10.0.0.{}:8333", n).parse().unwrap())
+    }
+
+    fn block(height: u64) -> Block {
+        Block {
+            header: BlockHeader {
+                height,
+                timestamp: 0,
+                prev_hash: vec![],
+                nonce: 0,
+                target: [0; 32],
+                merkle_root: vec![],
+            },
+            transactions: vec![],
+            hash: vec![height as u8],
+        }
+    }
+
+    /// Make every outstanding request look timed out
+    fn age_requests(downloader: &mut BlockDownloader) {
+        let sent = Instant::now() - STALL_TIMEOUT - Duration::from_secs(1);
+        for r in downloader.in_flight.values_mut() {
+            r.sent = sent;
+        }
+    }
+
+    #[test]
+    fn spreads_chunks_over_peers_within_the_window() {
+        let (short, tall) = (peer(1), peer(2));
+        let peers = [(short.clone(), 200), (tall.clone(), 5000)];
+
+        let mut downloader = BlockDownloader::new();
+        let requests = downloader.schedule(0, &peers);
+
+        // Both peers filled up to MAX_IN_FLIGHT_PER_PEER
+        assert_eq!(requests.len(), 2 * MAX_IN_FLIGHT_PER_PEER);
+        for p in [&short, &tall] {
+            assert_eq!(
+                requests.iter().filter(|(a, _, _)| a == p).count(),
+                MAX_IN_FLIGHT_PER_PEER
+            );
+        }
+
+        for (a, from, count) in &requests {
+            assert_eq!(from % BLOCKS_PER_REQUEST, 0);
+            assert_eq!(*count, BLOCKS_PER_REQUEST);
+            assert!(from + count <= DOWNLOAD_WINDOW);
+            // Only asked for blocks the peer has
+            if *a == short {
+                assert!(from + count <= 200);
+            }
+        }
+
+        // Nothing more until requests complete
+        assert!(downloader.schedule(0, &peers).is_empty());
+        // Nobody is ahead of us
+        assert!(BlockDownloader::new().schedule(5000, &peers).is_empty());
+    }
+
+    #[test]
+    fn clips_requests_to_the_tip_and_target() {
+        let a = peer(1);
+        let mut downloader = BlockDownloader::new();
+
+        let requests = downloader.schedule(20, &[(a.clone(), 40)]);
+        assert_eq!(requests, vec![(a.clone(), 20, 12), (a, 32, 8)]);
+    }
+
+    #[test]
+    fn connects_strictly_in_order() {
+        let (a, b) = (peer(1), peer(2));
+        let mut downloader = BlockDownloader::new();
+        downloader.schedule(0, &[(a.clone(), 16)]);
+
+        assert!(downloader.expects(&a, 0));
+        assert!(!downloader.expects(&b, 0));
+
+        for h in [2, 1, 0] {
+            downloader.block_received(a.clone(), block(h));
+        }
+        // Unrequested blocks are ignored
+        downloader.block_received(b, block(3));
+
+        assert_eq!(downloader.take_next(0).unwrap().0.header.height, 0);
+        assert_eq!(downloader.take_next(1).unwrap().0.header.height, 1);
+        assert_eq!(downloader.take_next(2).unwrap().0.header.height, 2);
+        assert!(downloader.take_next(3).is_none());
+    }
+
+    #[test]
+    fn reassigns_stalled_requests_and_drops_repeat_stallers() {
+        let (slow, fast) = (peer(1), peer(2));
+        let mut downloader = BlockDownloader::new();
+
+        assert_eq!(downloader.schedule(0, &[(slow.clone(), 32)]).len(), 2);
+        age_requests(&mut downloader);
+        assert!(downloader.check_stalls(0).is_empty());
+
+        // The chunks are free again for another peer
+        let requests = downloader.schedule(0, &[(fast.clone(), 32)]);
+        assert_eq!(
+            requests,
+            vec![(fast.clone(), 0, 16), (fast.clone(), 16, 16)]
+        );
+
+        downloader.peer_disconnected(&fast);
+        downloader.schedule(0, &[(slow.clone(), 32)]);
+        age_requests(&mut downloader);
+        assert_eq!(downloader.check_stalls(0), vec![slow]);
+    }
+
+    #[test]
+    fn requests_the_chain_caught_up_with_are_not_stalls() {
+        let a = peer(1);
+        let mut downloader = BlockDownloader::new();
+
+        downloader.schedule(0, &[(a.clone(), 16)]);
+        age_requests(&mut downloader);
+
+        // Blocks 0..16 arrived from elsewhere meanwhile
+        assert!(downloader.check_stalls(16).is_empty());
+        assert!(downloader.in_flight.is_empty());
+        assert!(downloader.stalls.is_empty());
+    }
+
+    #[test]
+    fn discards_and_forgets_a_peer() {
+        let (bad, good) = (peer(1), peer(2));
+        let mut downloader = BlockDownloader::new();
+
+        downloader.schedule(0, &[(bad.clone(), 32)]);
+        downloader.block_received(bad.clone(), block(0));
+        downloader.discard_from(&bad);
+
+        assert!(downloader.take_next(0).is_none());
+        assert!(!downloader.expects(&bad, 0));
+        assert_eq!(downloader.schedule(0, &[(good.clone(), 32)]).len(), 2);
+
+        // A stall history ends with the connection
+        age_requests(&mut downloader);
+        downloader.check_stalls(0);
+        downloader.peer_disconnected(&good);
+        assert!(downloader.in_flight.is_empty());
+        assert!(downloader.stalls.is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+pub const FEE_ESTIMATES_FILE: &str = "data/fee_estimates.json";
+
+/// Longest confirmation target we keep statistics for (blocks)
+pub const MAX_CONFIRM_TARGET: usize = 25;
+
+/// Confirmation target used when the caller does not pick one
+pub const DEFAULT_CONFIRM_TARGET: usize = 6;
+
+/// Bucket boundaries grow geometrically from 1 per byte
+const BUCKET_SPACING: f64 = 1.25;
+const MAX_BUCKET_FEE_RATE: f64 = 10_000.0;
+
+/// Older observations fade out (per block)
+const DECAY: f64 = 0.995;
+
+/// A fee rate "works" for a target if this share of
+/// transactions paying it confirmed within the target
+const SUCCESS_THRESHOLD: f64 = 0.85;
+
+/// Minimum (decayed) number of samples before trusting a range
+const MIN_SAMPLES: f64 = 2.0;
+
+/// Per fee-rate bucket confirmation statistics
+#[derive(Serialize, Deserialize, Clone)]
+struct FeeBucket {
+    /// Lowest fee rate (per byte) that falls into this bucket
+    min_fee_rate: u64,
+    /// confirmed_within[t] = txs confirmed in at most t + 1 blocks
+    confirmed_within: Vec<f64>,
+    /// Txs that left the mempool (confirmed or not)
+    total: f64,
+}
+
+/// A transaction currently waiting in the mempool
+struct TrackedTx {
+    bucket: usize,
+    entry_height: u64,
+}
+
+/// Fee estimator (POLICY ONLY)
+///
+/// Watches how many blocks mempool transactions at each fee
+/// rate take to confirm, and answers:
+///   "what fee rate confirms within N blocks?"
+///
+/// Statistics loaded from a file are saved back to it, so
+/// short-lived processes (CLI, restarts) still get estimates.
+/// `new()` keeps them in memory only.
+#[derive(Serialize, Deserialize)]
+pub struct FeeEstimator {
+    buckets: Vec<FeeBucket>,
+    best_height: u64,
+
+    #[serde(skip)]
+    tracked: HashMap<Vec<u8>, TrackedTx>,
+
+    /// Where `save` writes (None = not persisted)
+    #[serde(skip)]
+    path: Option<String>,
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        let mut buckets = Vec::new();
+        let mut rate = 1.0f64;
+
+        while rate <= MAX_BUCKET_FEE_RATE {
+            let min_fee_rate = rate.ceil() as u64;
+            if buckets
+                .last()
+                .map(|b: &FeeBucket| b.min_fee_rate != min_fee_rate)
+                .unwrap_or(true)
+            {
+                buckets.push(FeeBucket {
+                    min_fee_rate,
+                    confirmed_within: vec![0.0; MAX_CONFIRM_TARGET],
+                    total: 0.0,
+                });
+            }
+            rate *= BUCKET_SPACING;
+        }
+
+        Self {
+            buckets,
+            best_height: 0,
+            tracked: HashMap::new(),
+            path: None,
+        }
+    }
+
+    /// Load statistics persisted at `path` (or start empty),
+    /// saving back there from then on
+    pub fn load(path: &str) -> Self {
+        let mut estimator: Self = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|_| Self::new()),
+            Err(_) => Self::new(),
+        };
+        estimator.path = Some(path.to_string());
+        estimator
+    }
+
+    pub fn save(&self) {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return,
+        };
+
+        let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+        if fs::create_dir_all(dir).is_ok() {
+            let _ = fs::write(path, serde_json::to_string_pretty(self).unwrap());
+        }
+    }
+
+    /// Start tracking a transaction that entered the mempool
+    ///
+    /// entry_height = height of the next block at entry time
+    pub fn track(&mut self, txid: Vec<u8>, fee_rate: u64, entry_height: u64) {
+        let bucket = self.bucket_for(fee_rate);
+        self.tracked.insert(txid, TrackedTx { bucket, entry_height });
+    }
+
+    /// Record a tracked transaction confirmed at block_height
+    pub fn confirmed(&mut self, txid: &[u8], block_height: u64) {
+        let tracked = match self.tracked.remove(txid) {
+            Some(t) => t,
+            None => return,
+        };
+
+        // Confirmed in the very next block = 1 block
+        let blocks = block_height.saturating_sub(tracked.entry_height) as usize + 1;
+
+        let bucket = &mut self.buckets[tracked.bucket];
+        bucket.total += 1.0;
+
+        for t in (blocks - 1)..MAX_CONFIRM_TARGET {
+            bucket.confirmed_within[t] += 1.0;
+        }
+    }
+
+    /// Record a tracked transaction that left the mempool unconfirmed
+    /// (conflict, eviction, expiry)
+    pub fn removed(&mut self, txid: &[u8]) {
+        if let Some(tracked) = self.tracked.remove(txid) {
+            self.buckets[tracked.bucket].total += 1.0;
+        }
+    }
+
+    /// Age all statistics by one block
+    pub fn block_processed(&mut self, block_height: u64) {
+        if block_height <= self.best_height {
+            return;
+        }
+        self.best_height = block_height;
+
+        for bucket in &mut self.buckets {
+            bucket.total *= DECAY;
+            for c in &mut bucket.confirmed_within {
+                *c *= DECAY;
+            }
+        }
+    }
+
+    /// Lowest fee rate (per byte) expected to confirm within
+    /// `target` blocks, or None without enough history
+    pub fn estimate_fee(&self, target: usize) -> Option<u64> {
+        let target = target.clamp(1, MAX_CONFIRM_TARGET);
+
+        // Transactions still waiting longer than the target count as failures
+        let mut waiting = vec![0.0f64; self.buckets.len()];
+        for t in self.tracked.values() {
+            let age = self.best_height.saturating_sub(t.entry_height) as usize + 1;
+            if age > target {
+                waiting[t.bucket] += 1.0;
+            }
+        }
+
+        let mut best = None;
+        let mut confirmed = 0.0;
+        let mut total = 0.0;
+
+        // Scan from the highest fee rate down, merging buckets
+        // until each range has enough samples to judge
+        for (i, bucket) in self.buckets.iter().enumerate().rev() {
+            confirmed += bucket.confirmed_within[target - 1];
+            total += bucket.total + waiting[i];
+
+            if total < MIN_SAMPLES {
+                continue;
+            }
+
+            if confirmed / total < SUCCESS_THRESHOLD {
+                break;
+            }
+
+            best = Some(bucket.min_fee_rate);
+            confirmed = 0.0;
+            total = 0.0;
+        }
+
+        best
+    }
+
+    fn bucket_for(&self, fee_rate: u64) -> usize {
+        self.buckets
+            .iter()
+            .rposition(|b| b.min_fee_rate <= fee_rate)
+            .unwrap_or(0)
+    }
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txid(n: u32) -> Vec<u8> {
+        n.to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn buckets_grow_geometrically_from_one() {
+        let estimator = FeeEstimator::new();
+
+        assert_eq!(estimator.buckets[0].min_fee_rate, 1);
+        assert!(estimator
+            .buckets
+            .windows(2)
+            .all(|w| w[0].min_fee_rate < w[1].min_fee_rate));
+        assert!(estimator.buckets.last().unwrap().min_fee_rate <= MAX_BUCKET_FEE_RATE as u64);
+
+        assert_eq!(estimator.bucket_for(0), 0);
+        assert_eq!(estimator.bucket_for(1), 0);
+        assert_eq!(estimator.bucket_for(u64::MAX), estimator.buckets.len() - 1);
+
+        let b = estimator.bucket_for(100);
+        assert!(estimator.buckets[b].min_fee_rate <= 100);
+        assert!(estimator.buckets[b + 1].min_fee_rate > 100);
+    }
+
+    #[test]
+    fn statistics_decay_once_per_new_block() {
+        let mut estimator = FeeEstimator::new();
+        estimator.track(txid(1), 50, 1);
+        estimator.confirmed(&txid(1), 3);
+
+        let b = estimator.bucket_for(50);
+        assert_eq!(estimator.buckets[b].total, 1.0);
+        // Confirmed after 3 blocks: counts for targets 3 and up
+        assert_eq!(estimator.buckets[b].confirmed_within[1], 0.0);
+        assert_eq!(estimator.buckets[b].confirmed_within[2], 1.0);
+
+        estimator.block_processed(3);
+        assert_eq!(estimator.buckets[b].total, DECAY);
+
+        // Already seen heights do not decay again
+        estimator.block_processed(3);
+        estimator.block_processed(2);
+        assert_eq!(estimator.buckets[b].total, DECAY);
+        assert_eq!(estimator.buckets[b].confirmed_within[2], DECAY);
+    }
+
+    #[test]
+    fn estimates_the_lowest_rate_that_confirms_in_time() {
+        let mut estimator = FeeEstimator::new();
+        assert_eq!(estimator.estimate_fee(1), None);
+
+        // Fast: fee rate 100 confirms in the next block
+        for n in 0..10 {
+            estimator.track(txid(n), 100, 1);
+            estimator.confirmed(&txid(n), 1);
+        }
+        // Slow: fee rate 2 is still waiting 20 blocks later
+        for n in 10..20 {
+            estimator.track(txid(n), 2, 1);
+        }
+        estimator.block_processed(20);
+
+        let fast = estimator.buckets[estimator.bucket_for(100)].min_fee_rate;
+        assert_eq!(estimator.estimate_fee(1), Some(fast));
+        assert_eq!(estimator.estimate_fee(0), Some(fast));
+
+        // Within 25 blocks the slow ones have not failed yet,
+        // but there are no confirmations below the fast bucket
+        assert_eq!(estimator.estimate_fee(MAX_CONFIRM_TARGET), Some(fast));
+
+        // Once the slow ones leave unconfirmed, they still count
+        // against their bucket
+        for n in 10..20 {
+            estimator.removed(&txid(n));
+        }
+        assert_eq!(estimator.estimate_fee(MAX_CONFIRM_TARGET), Some(fast));
+    }
+
+    #[test]
+    fn persists_only_when_loaded_from_a_path() {
+        let dir = std::env::temp_dir().join(format!("fee_estimates_test_{}", std::process::id()));
+        let path = dir.join("fee_estimates.json");
+        let path = path.to_str().unwrap();
+
+        let mut estimator = FeeEstimator::load(path);
+        assert_eq!(estimator.estimate_fee(1), None);
+
+        for n in 0..5 {
+            estimator.track(txid(n), 100, 1);
+            estimator.confirmed(&txid(n), 1);
+        }
+        estimator.block_processed(1);
+
+        // An in-memory estimator writes nothing
+        FeeEstimator::new().save();
+        assert!(!Path::new(path).exists());
+
+        estimator.save();
+        let reloaded = FeeEstimator::load(path);
+        assert_eq!(reloaded.best_height, 1);
+        assert_eq!(reloaded.estimate_fee(1), estimator.estimate_fee(1));
+        assert!(reloaded.estimate_fee(1).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,23 @@
 use crate::transaction::Transaction;
 use crate::utxo::UTXOSet;
-use crate::policy::MAX_TX_SIZE;
+use crate::policy::{check_standard, fee_rate, PolicyConfig};
 use crate::validation::validate_transaction;
 use crate::block::Block;
+use crate::chain::Blockchain;
+use crate::node::fee_estimator::FeeEstimator;
 
 use std::collections::HashSet;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-const MAX_MEMPOOL_TXS: usize = 50_000;
+/// Once raised by evictions, the minimum fee halves this often (seconds)
+const ROLLING_FEE_HALFLIFE: i64 = 12 * 60 * 60;
+
+/// A replacement must beat the highest evicted fee rate by this much
+const INCREMENTAL_FEE_PER_BYTE: u64 = 1;
+
+/// How many recently connected blocks are remembered so that
+/// transactions can be resurrected if those blocks are disconnected
+const MAX_TRACKED_BLOCKS: usize = 100;
 
 #[derive(Clone)]
 pub struct MempoolEntry {
@@ -19,38 +29,98 @@ pub struct MempoolEntry {
 
 pub struct Mempool {
     entries: Vec<MempoolEntry>,
+    /// Txids of `entries`, for constant-time lookups
+    txids: HashSet<Vec<u8>>,
     spent_outpoints: HashSet<(Vec<u8>, u32)>,
+    /// Our last view of the active chain, tip last
+    recent_blocks: Vec<Block>,
+    estimator: FeeEstimator,
+
+    policy: PolicyConfig,
+    total_bytes: usize,
+
+    /// Fee rate floor raised when the pool is full, decaying
+    /// back to the relay minimum over time
+    rolling_min_fee_rate: u64,
+    rolling_updated: i64,
+
+    /// Bumped on every change, so miners can spot stale templates
+    revision: u64,
+
+    /// Consensus check on admission and after disconnects
+    /// (validate_transaction; tests swap in a stand-in)
+    validate: fn(&Transaction, &UTXOSet, u64) -> bool,
 }
 
 impl Mempool {
     pub fn new() -> Self {
+        Self::with_policy(PolicyConfig::default())
+    }
+
+    /// Mempool whose fee statistics live in memory only
+    pub fn with_policy(policy: PolicyConfig) -> Self {
+        Self::with_estimator(policy, FeeEstimator::new())
+    }
+
+    /// Mempool feeding `estimator` (e.g. one loaded from disk,
+    /// saved back whenever the tip moves)
+    pub fn with_estimator(policy: PolicyConfig, estimator: FeeEstimator) -> Self {
         Self {
             entries: Vec::new(),
+            txids: HashSet::new(),
             spent_outpoints: HashSet::new(),
+            recent_blocks: Vec::new(),
+            estimator,
+            policy,
+            total_bytes: 0,
+            rolling_min_fee_rate: 0,
+            rolling_updated: now(),
+            revision: 0,
+            validate: validate_transaction,
         }
     }
 
+    /// Standardness settings the pool admits by
+    pub fn policy(&self) -> &PolicyConfig {
+        &self.policy
+    }
+
     pub fn size(&self) -> usize {
         self.entries.len()
     }
 
+    /// Changes whenever entries are added or removed
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Total serialized bytes of all entries
+    pub fn bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Current minimum fee rate (per byte) for admission
+    ///
+    /// The relay minimum, or higher while the pool is full.
+    pub fn min_fee_rate(&self) -> u64 {
+        self.decayed_rolling_fee()
+            .max(self.policy.min_relay_fee_per_byte)
+    }
+
     pub fn add_transaction(
         &mut self,
         tx: Transaction,
         utxos: &UTXOSet,
         chain_height: u64,
     ) -> bool {
-        // Coinbase not allowed in mempool
-        if tx.inputs.is_empty() {
+        // Standardness (coinbase, size, counts, dust)
+        if check_standard(&tx, &self.policy).is_err() {
             return false;
         }
 
         let size = tx.serialized_size();
-        if size > MAX_TX_SIZE {
-            return false;
-        }
 
-        if !validate_transaction(&tx, utxos, chain_height) {
+        if !(self.validate)(&tx, utxos, chain_height) {
             return false;
         }
 
@@ -67,32 +137,39 @@ impl Mempool {
             _ => return false,
         };
 
+        let rate = fee_rate(fee as u64, size);
+        if rate < self.min_fee_rate() {
+            return false;
+        }
+
         for input in &tx.inputs {
             self.spent_outpoints
                 .insert((input.txid.clone(), input.index));
         }
 
+        let txid = tx.txid();
+        self.estimator.track(txid.clone(), rate, chain_height);
+        self.txids.insert(txid.clone());
+
         self.entries.push(MempoolEntry {
             tx,
             fee,
             size,
             timestamp: now(),
         });
+        self.total_bytes += size;
+        self.revision += 1;
 
         // 🔒 MEMPOOL SIZE CAP + EVICTION (POLICY ONLY)
-        if self.entries.len() > MAX_MEMPOOL_TXS {
-            // Evict lowest fee-rate first
-            self.entries.sort_by(|a, b| {
-                let lhs = a.fee * b.size as i64;
-                let rhs = b.fee * a.size as i64;
-                lhs.cmp(&rhs)
-            });
+        let evicted = self.trim_to_size();
 
-            self.entries.truncate(MAX_MEMPOOL_TXS);
-            self.rebuild_spent_outpoints();
-        }
+        // The new entry itself may have been the cheapest
+        !evicted.contains(&txid)
+    }
 
-        true
+    /// Transaction `txid` is waiting in the pool
+    pub fn contains(&self, txid: &[u8]) -> bool {
+        self.txids.contains(txid)
     }
 
     /// Transactions sorted by fee-rate for mining
@@ -108,6 +185,14 @@ impl Mempool {
         entries.into_iter().map(|e| e.tx).collect()
     }
 
+    /// Fee rate (per byte) expected to confirm within `target` blocks,
+    /// never below the current admission minimum
+    pub fn estimate_fee(&self, target: usize) -> Option<u64> {
+        self.estimator
+            .estimate_fee(target)
+            .map(|rate| rate.max(self.min_fee_rate()))
+    }
+
     /// Remove confirmed transactions after block acceptance
     pub fn remove_confirmed(&mut self, confirmed: &[Transaction]) {
         self.entries.retain(|entry| {
@@ -116,7 +201,120 @@ impl Mempool {
                 .any(|tx| tx.txid() == entry.tx.txid())
         });
 
-        self.rebuild_spent_outpoints();
+        self.rebuild_index();
+    }
+
+    /// Bring the mempool in line with the active chain
+    ///
+    /// Compares the chain against the blocks we last saw and
+    /// replays the difference:
+    /// - blocks no longer on the active chain are disconnected
+    /// - blocks above the fork point are connected
+    ///
+    /// Safe to call repeatedly; does nothing if the tip is unchanged.
+    pub fn update_chain_tip(&mut self, chain: &Blockchain) {
+        if chain.blocks.is_empty() {
+            return;
+        }
+
+        // Walk back our view until it agrees with the active chain
+        let mut disconnected = Vec::new();
+        while let Some(last) = self.recent_blocks.last() {
+            let still_active = chain
+                .blocks
+                .get(last.header.height as usize)
+                .map(|b| b.hash == last.hash)
+                .unwrap_or(false);
+
+            if still_active {
+                break;
+            }
+
+            disconnected.push(self.recent_blocks.pop().unwrap());
+        }
+
+        let tip_before = self.recent_blocks.last().map(|b| b.hash.clone());
+
+        let start = match (self.recent_blocks.last(), disconnected.last()) {
+            (Some(b), _) => b.header.height as usize + 1,
+            // Our whole view was disconnected; if the chain is now
+            // shorter, re-apply its tip so there is one to compare to
+            (None, Some(lowest)) => (lowest.header.height as usize).min(chain.blocks.len() - 1),
+            // First call: nothing to replay, just remember the tip
+            (None, None) => chain.blocks.len() - 1,
+        };
+
+        if !disconnected.is_empty() {
+            self.blocks_disconnected(
+                disconnected,
+                &chain.utxos,
+                chain.height(),
+            );
+        }
+
+        for block in chain.blocks.iter().skip(start) {
+            self.block_connected(block, &chain.utxos);
+            self.recent_blocks.push(block.clone());
+        }
+
+        if self.recent_blocks.len() > MAX_TRACKED_BLOCKS {
+            let excess = self.recent_blocks.len() - MAX_TRACKED_BLOCKS;
+            self.recent_blocks.drain(..excess);
+        }
+
+        if self.recent_blocks.last().map(|b| b.hash.clone()) != tip_before {
+            self.estimator.save();
+        }
+    }
+
+    /// Apply a newly connected block
+    ///
+    /// Removes:
+    /// - transactions confirmed by the block
+    /// - transactions spending an outpoint the block spent (conflicts)
+    /// - transactions whose inputs are no longer in the UTXO set
+    pub fn block_connected(&mut self, block: &Block, utxos: &UTXOSet) {
+        let confirmed: HashSet<Vec<u8>> =
+            block.transactions.iter().map(|tx| tx.txid()).collect();
+
+        for txid in &confirmed {
+            self.estimator.confirmed(txid, block.header.height);
+        }
+
+        let block_spends: HashSet<(Vec<u8>, u32)> = block
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.inputs.iter())
+            .map(|i| (i.txid.clone(), i.index))
+            .collect();
+
+        self.remove_where(|entry| {
+            confirmed.contains(&entry.tx.txid())
+                || entry.tx.inputs.iter().any(|i| {
+                    block_spends.contains(&(i.txid.clone(), i.index))
+                        || !utxos.contains_key(&outpoint_key(&i.txid, i.index))
+                })
+        });
+
+        self.estimator.block_processed(block.header.height);
+    }
+
+    /// Apply blocks removed from the active chain (tip moved backwards
+    /// or a reorg), given the UTXO set and height AFTER the change
+    ///
+    /// Remaining entries are fully revalidated, since spends of
+    /// coinbase outputs may no longer be mature at the lower height.
+    /// Transactions from the disconnected blocks are then re-added.
+    pub fn blocks_disconnected(
+        &mut self,
+        disconnected: Vec<Block>,
+        utxos: &UTXOSet,
+        chain_height: u64,
+    ) {
+        let validate = self.validate;
+        self.remove_where(|entry| !validate(&entry.tx, utxos, chain_height));
+
+        self.resurrect_from_orphans(disconnected, utxos, chain_height);
     }
 
     /// Re-add transactions from orphaned blocks
@@ -133,9 +331,93 @@ impl Mempool {
         }
     }
 
-    fn rebuild_spent_outpoints(&mut self) {
+    /// Drop matching entries, telling the fee estimator they left
+    /// the mempool (already-confirmed ones are ignored by it)
+    fn remove_where<F>(&mut self, mut remove: F)
+    where
+        F: FnMut(&MempoolEntry) -> bool,
+    {
+        let estimator = &mut self.estimator;
+        self.entries.retain(|entry| {
+            if remove(entry) {
+                estimator.removed(&entry.tx.txid());
+                false
+            } else {
+                true
+            }
+        });
+
+        self.rebuild_index();
+    }
+
+    /// Evict lowest fee-rate entries until within capacity
+    ///
+    /// Raises the rolling minimum fee above the best evicted rate,
+    /// so cheaper transactions are refused instead of churning.
+    /// Returns the evicted txids.
+    fn trim_to_size(&mut self) -> Vec<Vec<u8>> {
+        let mut evicted = Vec::new();
+
+        if self.entries.len() <= self.policy.max_mempool_txs
+            && self.total_bytes <= self.policy.max_mempool_bytes
+        {
+            return evicted;
+        }
+
+        // Highest fee-rate first, so the cheapest sit at the end
+        self.entries.sort_by(|a, b| {
+            let lhs = a.fee * b.size as i64;
+            let rhs = b.fee * a.size as i64;
+            rhs.cmp(&lhs)
+        });
+
+        let mut max_evicted_rate = 0;
+
+        while self.entries.len() > self.policy.max_mempool_txs
+            || self.total_bytes > self.policy.max_mempool_bytes
+        {
+            let entry = match self.entries.pop() {
+                Some(e) => e,
+                None => break,
+            };
+
+            self.total_bytes -= entry.size;
+            max_evicted_rate =
+                max_evicted_rate.max(fee_rate(entry.fee as u64, entry.size));
+
+            let txid = entry.tx.txid();
+            self.estimator.removed(&txid);
+            evicted.push(txid);
+        }
+
+        self.rolling_min_fee_rate = self
+            .decayed_rolling_fee()
+            .max(max_evicted_rate + INCREMENTAL_FEE_PER_BYTE);
+        self.rolling_updated = now();
+
+        self.rebuild_index();
+        evicted
+    }
+
+    /// Rolling minimum fee after time-based decay
+    fn decayed_rolling_fee(&self) -> u64 {
+        let halvings =
+            ((now() - self.rolling_updated) / ROLLING_FEE_HALFLIFE).max(0);
+        if halvings >= 64 {
+            0
+        } else {
+            self.rolling_min_fee_rate >> halvings
+        }
+    }
+
+    fn rebuild_index(&mut self) {
+        self.revision += 1;
+        self.total_bytes = self.entries.iter().map(|e| e.size).sum();
+
+        self.txids.clear();
         self.spent_outpoints.clear();
         for entry in &self.entries {
+            self.txids.insert(entry.tx.txid());
             for input in &entry.tx.inputs {
                 self.spent_outpoints
                     .insert((input.txid.clone(), input.index));
@@ -149,12 +431,7 @@ fn calculate_fee(tx: &Transaction, utxos: &UTXOSet) -> Option<i64> {
     let mut output_sum = 0i64;
 
     for input in &tx.inputs {
-        let key = format!(
-            "{}:{}",
-            hex::encode(&input.txid),
-            input.index
-        );
-        let utxo = utxos.get(&key)?;
+        let utxo = utxos.get(&outpoint_key(&input.txid, input.index))?;
         input_sum += utxo.value as i64;
     }
 
@@ -165,9 +442,250 @@ fn calculate_fee(tx: &Transaction, utxos: &UTXOSet) -> Option<i64> {
     Some(input_sum - output_sum)
 }
 
+fn outpoint_key(txid: &[u8], index: u32) -> String {
+    format!("{}:{}", hex::encode(txid), index)
+}
+
 fn now() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("system time")
         .as_secs() as i64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHeader;
+    use crate::transaction::{TxInput, TxOutput};
+
+    const COINBASE_MATURITY: u64 = 100;
+
+    /// Stand-in for validate_transaction: inputs exist and
+    /// coinbase outputs are mature (signatures are not the
+    /// mempool's concern here)
+    fn spendable(tx: &Transaction, utxos: &UTXOSet, height: u64) -> bool {
+        tx.inputs
+            .iter()
+            .all(|i| match utxos.get(&outpoint_key(&i.txid, i.index)) {
+                Some(u) => !u.is_coinbase || height >= u.height + COINBASE_MATURITY,
+                None => false,
+            })
+    }
+
+    fn mempool() -> Mempool {
+        let mut mempool = Mempool::new();
+        mempool.validate = spendable;
+        mempool
+    }
+
+    fn output(value: u64, owner: u8) -> TxOutput {
+        TxOutput {
+            value,
+            pubkey_hash: vec![owner; 32],
+        }
+    }
+
+    /// Transaction with `outputs` worth 10_000 each (funded from
+    /// outside the test chain, so not a coinbase)
+    fn funding(outputs: usize) -> Transaction {
+        Transaction {
+            inputs: vec![spend_input(&[0xee; 32], 0)],
+            outputs: (0..outputs).map(|_| output(10_000, 1)).collect(),
+        }
+    }
+
+    fn spend_input(txid: &[u8], index: u32) -> TxInput {
+        TxInput {
+            txid: txid.to_vec(),
+            index,
+            pubkey: vec![],
+            signature: vec![],
+            address_index: 0,
+        }
+    }
+
+    /// Spend `from:index` to `owner`, paying a 1000 fee
+    fn spend(from: &Transaction, index: u32, owner: u8) -> Transaction {
+        pay(from, index, owner, 1_000)
+    }
+
+    fn pay(from: &Transaction, index: u32, owner: u8, fee: u64) -> Transaction {
+        Transaction {
+            inputs: vec![spend_input(&from.txid(), index)],
+            outputs: vec![output(10_000 - fee, owner)],
+        }
+    }
+
+    fn block(height: u64, variant: u8, transactions: Vec<Transaction>) -> Block {
+        Block {
+            header: BlockHeader {
+                height,
+                timestamp: 0,
+                prev_hash: vec![],
+                nonce: 0,
+                target: [0xff; 32],
+                merkle_root: vec![],
+            },
+            transactions,
+            hash: vec![height as u8, (height >> 8) as u8, variant],
+        }
+    }
+
+    fn coinbase(owner: u8) -> Transaction {
+        Transaction {
+            inputs: vec![],
+            outputs: vec![output(10_000, owner)],
+        }
+    }
+
+    fn chain(blocks: Vec<Block>) -> Blockchain {
+        let mut chain = Blockchain::new();
+        chain.blocks = blocks;
+        chain.rebuild_utxos();
+        chain
+    }
+
+    #[test]
+    fn connected_block_removes_confirmed_and_conflicting() {
+        let fund = funding(3);
+        let mut chain = chain(vec![block(0, 0, vec![fund.clone()])]);
+
+        let mut mempool = mempool();
+        mempool.update_chain_tip(&chain);
+
+        let (a, b, c) = (spend(&fund, 0, 2), spend(&fund, 1, 2), spend(&fund, 2, 2));
+        for tx in [&a, &b, &c] {
+            assert!(mempool.add_transaction(tx.clone(), &chain.utxos, chain.height()));
+        }
+        // Double spend of a pooled outpoint
+        assert!(!mempool.add_transaction(spend(&fund, 0, 3), &chain.utxos, chain.height()));
+
+        // The block confirms `b` and spends a's outpoint elsewhere
+        let conflict = spend(&fund, 0, 3);
+        chain
+            .blocks
+            .push(block(1, 0, vec![coinbase(9), b.clone(), conflict]));
+        chain.rebuild_utxos();
+        mempool.update_chain_tip(&chain);
+
+        assert_eq!(mempool.size(), 1);
+        assert!(mempool.contains(&c.txid()));
+        assert!(!mempool.contains(&a.txid()) && !mempool.contains(&b.txid()));
+
+        // The freed outpoint can not be pooled again
+        assert!(!mempool.add_transaction(a, &chain.utxos, chain.height()));
+    }
+
+    #[test]
+    fn disconnected_block_transactions_return_to_the_pool() {
+        let fund = funding(2);
+        let a = spend(&fund, 0, 2);
+        let mut chain = chain(vec![
+            block(0, 0, vec![fund.clone()]),
+            block(1, 0, vec![coinbase(9), a.clone()]),
+        ]);
+
+        let mut mempool = mempool();
+        mempool.update_chain_tip(&chain);
+        let b = spend(&fund, 1, 2);
+        assert!(mempool.add_transaction(b.clone(), &chain.utxos, chain.height()));
+
+        // Block 1 is replaced by one without `a`
+        chain.blocks[1] = block(1, 1, vec![coinbase(8)]);
+        chain.rebuild_utxos();
+        mempool.update_chain_tip(&chain);
+
+        assert_eq!(mempool.size(), 2);
+        assert!(mempool.contains(&a.txid()) && mempool.contains(&b.txid()));
+    }
+
+    #[test]
+    fn disconnect_rechecks_coinbase_maturity() {
+        let reward = coinbase(1);
+        let mut blocks = vec![block(0, 0, vec![reward.clone()])];
+        blocks.extend((1..=COINBASE_MATURITY).map(|h| block(h, 0, vec![])));
+        let mut chain = chain(blocks);
+
+        let mut mempool = mempool();
+        mempool.update_chain_tip(&chain);
+        let tx = spend(&reward, 0, 2);
+        assert!(mempool.add_transaction(tx.clone(), &chain.utxos, chain.height()));
+
+        // Still mature at the lower height
+        chain.blocks.truncate(COINBASE_MATURITY as usize);
+        chain.rebuild_utxos();
+        mempool.update_chain_tip(&chain);
+        assert!(mempool.contains(&tx.txid()));
+
+        // No longer mature
+        chain.blocks.truncate(COINBASE_MATURITY as usize - 1);
+        chain.rebuild_utxos();
+        mempool.update_chain_tip(&chain);
+        assert!(!mempool.contains(&tx.txid()));
+    }
+
+    #[test]
+    fn full_pool_evicts_the_cheapest_and_raises_the_minimum() {
+        let fund = funding(4);
+        let chain = chain(vec![block(0, 0, vec![fund.clone()])]);
+        let height = chain.height();
+
+        let mut mempool = Mempool::with_policy(PolicyConfig {
+            max_mempool_txs: 2,
+            ..PolicyConfig::default()
+        });
+        mempool.validate = spendable;
+        mempool.update_chain_tip(&chain);
+
+        let (cheap, mid, rich) = (
+            pay(&fund, 0, 2, 300),
+            pay(&fund, 1, 2, 600),
+            pay(&fund, 2, 2, 900),
+        );
+        let size = cheap.serialized_size();
+        for tx in [&cheap, &mid, &rich] {
+            assert!(mempool.add_transaction(tx.clone(), &chain.utxos, height));
+        }
+
+        assert_eq!(mempool.size(), 2);
+        assert!(!mempool.contains(&cheap.txid()));
+        assert_eq!(
+            mempool.min_fee_rate(),
+            fee_rate(300, size) + INCREMENTAL_FEE_PER_BYTE
+        );
+
+        // Admitted, but then the cheapest entry: evicted at once
+        let between = pay(&fund, 3, 2, 450);
+        assert!(fee_rate(450, size) >= mempool.min_fee_rate());
+        assert!(!mempool.add_transaction(between.clone(), &chain.utxos, height));
+        assert!(!mempool.contains(&between.txid()));
+        assert_eq!(
+            mempool.min_fee_rate(),
+            fee_rate(450, size) + INCREMENTAL_FEE_PER_BYTE
+        );
+
+        // Now below the raised minimum: refused outright
+        assert!(!mempool.add_transaction(cheap, &chain.utxos, height));
+        assert_eq!(mempool.size(), 2);
+    }
+
+    #[test]
+    fn raised_minimum_fee_halves_back_to_the_relay_minimum() {
+        let mut mempool = Mempool::new();
+        let relay_min = mempool.policy().min_relay_fee_per_byte;
+
+        mempool.rolling_min_fee_rate = 40;
+        mempool.rolling_updated = now();
+        assert_eq!(mempool.min_fee_rate(), 40);
+
+        mempool.rolling_updated = now() - ROLLING_FEE_HALFLIFE;
+        assert_eq!(mempool.min_fee_rate(), 20);
+
+        mempool.rolling_updated = now() - 2 * ROLLING_FEE_HALFLIFE - 60;
+        assert_eq!(mempool.min_fee_rate(), 10);
+
+        mempool.rolling_updated = now() - 100 * ROLLING_FEE_HALFLIFE;
+        assert_eq!(mempool.min_fee_rate(), relay_min);
+    }
+}
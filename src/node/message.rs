@@ -2,7 +2,28 @@ use serde::{Serialize, Deserialize};
 use crate::core::block::Block;
 use crate::core::transaction::Transaction;
 
-pub const PROTOCOL_VERSION: u32 = 3;
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// Oldest peer version we talk to (4 = framed messages + HelloAck)
+pub const MIN_PROTOCOL_VERSION: u32 = 4;
+
+/// First version that serves GetBlocks (older peers only SyncRequest)
+pub const GETBLOCKS_VERSION: u32 = 5;
+
+/// Most blocks returned for one GetBlocks
+pub const MAX_GETBLOCKS: u64 = 128;
+
+pub const USER_AGENT: &str = concat!("/revelation:", env!("CARGO_PKG_VERSION"), "/");
+
+/// Peer address as exchanged in Addr messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetAddress {
+    /// "ip:port" or "<onion>.onion:port"
+    pub addr: String,
+    pub services: u64,
+    /// Last time the address was known to be alive (unix)
+    pub timestamp: i64,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NetworkMessage {
@@ -11,15 +32,25 @@ pub enum NetworkMessage {
         version: u32,
         height: u64,
         agent: String,
+        /// Service bits (peer_manager::NODE_NETWORK, ...)
+        services: u64,
+        /// Random per-node value; detects connections to self
+        nonce: u64,
+        /// Port we accept connections on (0 = not listening)
+        listen_port: u16,
     },
 
+    /// Hello accepted
+    HelloAck,
+
     /// Ask peer for known addresses
     GetAddr,
 
-    /// Peer address list
-    Addr(Vec<String>),
+    /// Peer address list (at most addrman::MAX_ADDR_PER_MSG)
+    Addr(Vec<NetAddress>),
 
-    /// Request blocks from height
+    /// Request blocks from height (at most MAX_GETBLOCKS
+    /// served; ask again from the new tip for more)
     SyncRequest {
         from_height: u64,
     },
@@ -33,4 +64,29 @@ pub enum NetworkMessage {
     /// Keepalive
     Ping,
     Pong,
+
+    /// Request `count` blocks starting at `from_height`
+    /// (at most MAX_GETBLOCKS served)
+    GetBlocks {
+        from_height: u64,
+        count: u64,
+    },
+}
+
+impl NetworkMessage {
+    /// Command name carried in the frame envelope
+    pub fn command(&self) -> &'static str {
+        match self {
+            NetworkMessage::Hello { .. } => "hello",
+            NetworkMessage::HelloAck => "helloack",
+            NetworkMessage::GetAddr => "getaddr",
+            NetworkMessage::Addr(_) => "addr",
+            NetworkMessage::SyncRequest { .. } => "syncrequest",
+            NetworkMessage::Block(_) => "block",
+            NetworkMessage::Transaction(_) => "tx",
+            NetworkMessage::Ping => "ping",
+            NetworkMessage::Pong => "pong",
+            NetworkMessage::GetBlocks { .. } => "getblocks",
+        }
+    }
 }
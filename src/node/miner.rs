@@ -1,3 +1,8 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
 use time::OffsetDateTime;
 
 use crate::{
@@ -6,36 +11,171 @@ use crate::{
     reward::block_reward,
     consensus::difficulty::calculate_next_target,
     merkle::merkle_root,
-    pow::mine,
+    pow::valid_pow,
     validation::validate_transaction,
     utxo::UTXOSet,
+    chain::{median_time_past, Blockchain},
+    node::mempool::Mempool,
     policy::{MAX_BLOCK_TXS, MAX_BLOCK_TX_BYTES},
 };
 
-const MIN_FEE_PER_BYTE: i64 = 1; // POLICY ONLY
+/// Nonces each thread scans before rolling timestamp / extra-nonce
+const NONCE_RANGE: u64 = 1 << 32;
+
+/// Hashes between checks of the abort flag (power of two)
+const ABORT_CHECK_INTERVAL: u64 = 1 << 12;
+
+/// How often the watcher looks for a new tip or mempool change
+const STALE_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Longest coinbase tag carried (bytes)
+pub const MAX_COINBASE_TAG: usize = 64;
+
+/// Who the coinbase pays and what it carries (POLICY ONLY)
+#[derive(Clone, Debug, Default)]
+pub struct CoinbaseSpec {
+    /// (pubkey_hash, percent of the reward); percents sum to 100
+    pub payouts: Vec<(Vec<u8>, u64)>,
+
+    /// Optional tag carried in the coinbase extra data
+    pub tag: Vec<u8>,
+}
+
+impl CoinbaseSpec {
+    /// Whole reward to one pubkey hash, no tag
+    pub fn single(pubkey_hash: Vec<u8>) -> Self {
+        Self {
+            payouts: vec![(pubkey_hash, 100)],
+            tag: vec![],
+        }
+    }
+
+    /// Split `reward` by percent; rounding dust goes to the first output
+    fn outputs(&self, reward: u64) -> Vec<TxOutput> {
+        let mut outputs: Vec<TxOutput> = self
+            .payouts
+            .iter()
+            .map(|(pubkey_hash, percent)| TxOutput {
+                value: reward / 100 * percent + reward % 100 * percent / 100,
+                pubkey_hash: pubkey_hash.clone(),
+            })
+            .collect();
+
+        let paid: u64 = outputs.iter().map(|o| o.value).sum();
+        if let Some(first) = outputs.first_mut() {
+            first.value += reward.saturating_sub(paid);
+        }
+
+        outputs
+    }
+}
+
+/// Unmined block contents (POLICY ONLY)
+///
+/// Everything needed to produce candidate blocks without
+/// holding the chain lock while grinding.
+#[derive(Clone)]
+pub struct BlockTemplate {
+    pub height: u64,
+    pub prev_hash: Vec<u8>,
+    pub target: [u8; 32],
+
+    /// Earliest valid timestamp (median time past + 1)
+    pub min_timestamp: i64,
+    pub timestamp: i64,
+
+    /// Coinbase payout outputs (reward only, no extra data)
+    pub coinbase_outputs: Vec<TxOutput>,
+
+    /// Tag placed before the extra-nonce in the coinbase extra data
+    pub coinbase_tag: Vec<u8>,
 
-pub fn mine_block(
-    prev_block: &Block,
+    /// Selected mempool transactions and their fees
+    pub transactions: Vec<Transaction>,
+    pub fees: Vec<i64>,
+}
+
+impl BlockTemplate {
+    /// Coinbase carrying `extranonce`
+    ///
+    /// Extra data (tag || extranonce) rides in a trailing zero-value
+    /// output, the same carrier the revelation genesis transaction
+    /// uses. Zero-value outputs never enter the UTXO set, and this
+    /// one is only added when needed (a tag or extranonce != 0).
+    pub fn coinbase(&self, extranonce: u64) -> Transaction {
+        let mut outputs = self.coinbase_outputs.clone();
+
+        if extranonce != 0 || !self.coinbase_tag.is_empty() {
+            let mut extra = self.coinbase_tag.clone();
+            if extranonce != 0 {
+                extra.extend_from_slice(&extranonce.to_le_bytes());
+            }
+
+            outputs.push(TxOutput {
+                value: 0,
+                pubkey_hash: extra,
+            });
+        }
+
+        Transaction {
+            inputs: vec![],
+            outputs,
+        }
+    }
+
+    /// Candidate block for the given extra-nonce (nonce = 0)
+    pub fn assemble(&self, extranonce: u64) -> Block {
+        let mut transactions = Vec::with_capacity(self.transactions.len() + 1);
+        transactions.push(self.coinbase(extranonce));
+        transactions.extend(self.transactions.iter().cloned());
+
+        Block {
+            header: BlockHeader {
+                height: self.height,
+                timestamp: self.timestamp,
+                prev_hash: self.prev_hash.clone(),
+                nonce: 0,
+                target: self.target,
+                merkle_root: merkle_root(&transactions),
+            },
+            transactions,
+            hash: vec![],
+        }
+    }
+}
+
+/// Build a template on top of `chain`
+///
+/// `mempool_txs` should be in priority order (highest fee-rate first).
+pub fn build_template(
+    chain: &[Block],
     utxos: &UTXOSet,
     mempool_txs: Vec<Transaction>,
-    miner_pubkey_hash: Vec<u8>,
-    chain: &[Block],
-) -> Block {
+    coinbase: &CoinbaseSpec,
+) -> BlockTemplate {
+    let prev_block = chain.last().expect("chain has genesis");
     let height = prev_block.header.height + 1;
 
-    let coinbase = Transaction {
+    let coinbase_outputs = coinbase.outputs(block_reward(height));
+
+    let mut coinbase_tag = coinbase.tag.clone();
+    coinbase_tag.truncate(MAX_COINBASE_TAG);
+
+    // Room for the extra-data output (tag + extranonce)
+    let coinbase_size = Transaction {
         inputs: vec![],
-        outputs: vec![TxOutput {
-            value: block_reward(height),
-            pubkey_hash: miner_pubkey_hash,
-        }],
-    };
+        outputs: coinbase_outputs.clone(),
+    }
+    .serialized_size()
+        + 16
+        + coinbase_tag.len();
 
-    let mut selected = vec![coinbase];
-    let mut total_bytes = selected[0].serialized_size();
+    let mut transactions = Vec::new();
+    let mut fees = Vec::new();
+    let mut total_bytes = coinbase_size;
 
     for tx in mempool_txs {
-        if selected.len() >= MAX_BLOCK_TXS {
+        if transactions.len() + 1 >= MAX_BLOCK_TXS {
             break;
         }
 
@@ -66,35 +206,195 @@ pub fn mine_block(
             output += o.value as i64;
         }
 
+        // Minimum fee rate is enforced at mempool admission
         let fee = input - output;
         if fee <= 0 {
             continue;
         }
 
-        let fee_rate = fee / size as i64;
-        if fee_rate < MIN_FEE_PER_BYTE {
-            continue;
+        total_bytes += size;
+        transactions.push(tx);
+        fees.push(fee);
+    }
+
+    let min_timestamp = median_time_past(chain) + 1;
+
+    BlockTemplate {
+        height,
+        prev_hash: prev_block.hash.clone(),
+        target: calculate_next_target(chain),
+        min_timestamp,
+        timestamp: unix_now().max(min_timestamp),
+        coinbase_outputs,
+        coinbase_tag,
+        transactions,
+        fees,
+    }
+}
+
+/// Search for a valid block on `threads` threads
+///
+/// Nonce space is partitioned: thread i scans
+///   [i * NONCE_RANGE, (i + 1) * NONCE_RANGE)
+/// When its range is exhausted it rolls the timestamp forward,
+/// or, if the clock has not moved, its own extra-nonce
+/// (i + 1, i + 1 + threads, ...), and scans the range again.
+///
+/// Returns None if `abort` is raised before a solution is found.
+pub fn solve(
+    template: &BlockTemplate,
+    threads: usize,
+    abort: &AtomicBool,
+) -> Option<Block> {
+    let threads = threads.max(1);
+    let found = AtomicBool::new(false);
+    let solution = Mutex::new(None);
+
+    thread::scope(|s| {
+        for i in 0..threads {
+            let found = &found;
+            let solution = &solution;
+
+            s.spawn(move || {
+                let start = i as u64 * NONCE_RANGE;
+                let mut extranonce = 0u64;
+                let mut block = template.assemble(extranonce);
+
+                loop {
+                    let mut nonce = start;
+
+                    while nonce < start + NONCE_RANGE {
+                        if (nonce - start) & (ABORT_CHECK_INTERVAL - 1) == 0
+                            && (found.load(Ordering::Relaxed)
+                                || abort.load(Ordering::Relaxed))
+                        {
+                            return;
+                        }
+
+                        block.header.nonce = nonce;
+                        let hash = block.hash_header();
+
+                        if valid_pow(&hash, &block.header.target) {
+                            block.hash = hash;
+                            if !found.swap(true, Ordering::SeqCst) {
+                                *solution.lock().unwrap() = Some(block);
+                            }
+                            return;
+                        }
+
+                        nonce += 1;
+                    }
+
+                    // Range exhausted: prefer a fresh timestamp
+                    let now = unix_now();
+                    if now > block.header.timestamp {
+                        block.header.timestamp = now;
+                    } else {
+                        extranonce = if extranonce == 0 {
+                            i as u64 + 1
+                        } else {
+                            extranonce + threads as u64
+                        };
+
+                        let timestamp = block.header.timestamp;
+                        block = template.assemble(extranonce);
+                        block.header.timestamp = timestamp;
+                    }
+                }
+            });
         }
+    });
 
-        total_bytes += size;
-        selected.push(tx);
+    solution.into_inner().unwrap()
+}
+
+/// Mining subsystem (POLICY ONLY)
+///
+/// Builds a template from the current tip and mempool, then
+/// grinds it on several threads without holding any lock.
+/// Work is abandoned as soon as the tip or the mempool changes.
+pub struct Miner {
+    chain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    threads: usize,
+}
+
+impl Miner {
+    /// threads = 0 → one per available CPU core
+    pub fn new(
+        chain: Arc<Mutex<Blockchain>>,
+        mempool: Arc<Mutex<Mempool>>,
+        threads: usize,
+    ) -> Self {
+        let threads = if threads == 0 {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        } else {
+            threads
+        };
+
+        Self { chain, mempool, threads }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
     }
 
-    let target = calculate_next_target(chain);
-
-    let mut block = Block {
-        header: BlockHeader {
-            height,
-            timestamp: OffsetDateTime::now_utc().unix_timestamp(),
-            prev_hash: prev_block.hash.clone(),
-            nonce: 0,
-            target,
-            merkle_root: merkle_root(&selected),
-        },
-        transactions: selected,
-        hash: vec![],
-    };
-
-    mine(&mut block);
-    block
+    /// Try to mine one block on the current tip
+    ///
+    /// Returns None if the template went stale (new tip or
+    /// mempool update); the caller should simply call again.
+    pub fn mine_next(&self, coinbase: &CoinbaseSpec) -> Option<Block> {
+        // Lock order: chain → mempool
+        let (template, revision) = {
+            let c = self.chain.lock().unwrap();
+            let m = self.mempool.lock().unwrap();
+
+            let template = build_template(
+                &c.blocks,
+                &c.utxos,
+                m.sorted_for_mining(),
+                coinbase,
+            );
+
+            (template, m.revision())
+        };
+
+        let abort = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                while !abort.load(Ordering::Relaxed) {
+                    if self.is_stale(&template, revision) {
+                        abort.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    thread::sleep(STALE_CHECK_INTERVAL);
+                }
+            });
+
+            let result = solve(&template, self.threads, &abort);
+
+            // Stop the watcher
+            abort.store(true, Ordering::Relaxed);
+            result
+        })
+    }
+
+    fn is_stale(&self, template: &BlockTemplate, revision: u64) -> bool {
+        let tip_changed = {
+            let c = self.chain.lock().unwrap();
+            c.blocks
+                .last()
+                .map(|b| b.hash != template.prev_hash)
+                .unwrap_or(true)
+        };
+
+        tip_changed || self.mempool.lock().unwrap().revision() != revision
+    }
+}
+
+fn unix_now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
 }
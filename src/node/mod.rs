@@ -1,8 +1,21 @@
 pub mod message;
+pub mod wire;
+pub mod netaddr;
+pub mod peer_id;
+pub mod peer_manager;
+pub mod addrman;
+pub mod seeds;
+pub mod banman;
+pub mod orphans;
+pub mod download;
+pub mod router;
+pub mod bundle;
 pub mod p2p;
 pub mod miner;
 pub mod mempool;
 pub mod dedup;
+pub mod fee_estimator;
+pub mod stratum;
 
 // transport is a submodule directory
 pub mod transport;
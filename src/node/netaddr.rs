@@ -0,0 +1,229 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use sha2::{Sha256, Digest};
+
+/// IPv6 prefix host names are mapped into (OnionCat range,
+/// as Bitcoin Core used for Tor)
+const HOST_MAP_PREFIX: [u8; 6] = [0xfd, 0x87, 0xd8, 0x7e, 0xeb, 0x43];
+
+/// Length of a v3 onion name without ".onion"
+const ONION_V3_LEN: usize = 56;
+
+/// A peer address as gossiped: IP endpoint or host name
+///
+/// Host names are reachable only through a proxy; onion
+/// addresses are the ones worth keeping in the address book.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerAddress {
+    Ip(SocketAddr),
+    Host { host: String, port: u16 },
+}
+
+impl PeerAddress {
+    /// Parse "ip:port", "[ipv6]:port" or "host:port"
+    pub fn parse(s: &str) -> Result<Self, &'static str> {
+        if let Ok(a) = s.parse::<SocketAddr>() {
+            return Ok(PeerAddress::Ip(a));
+        }
+
+        let (host, port) = s.rsplit_once(':').ok_or("missing port")?;
+        let port: u16 = port.parse().map_err(|_| "invalid port")?;
+
+        if host.is_empty() || host.contains([':', '[', ']', '/']) {
+            return Err("invalid host");
+        }
+
+        Ok(PeerAddress::Host {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+
+    pub fn is_onion(&self) -> bool {
+        match self {
+            PeerAddress::Host { host, .. } => is_onion_host(host),
+            PeerAddress::Ip(_) => false,
+        }
+    }
+
+    /// Reachable from anywhere: a public IP or an onion
+    /// service (LAN and special-purpose ranges are not)
+    pub fn is_public(&self) -> bool {
+        match self {
+            PeerAddress::Ip(a) => a.port() != 0 && is_public_ip(&a.ip()),
+            PeerAddress::Host { port, .. } => *port != 0 && self.is_onion(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            PeerAddress::Ip(a) => a.port(),
+            PeerAddress::Host { port, .. } => *port,
+        }
+    }
+
+    /// Address the connection is known by locally (host names
+    /// get a stable mapped address, see `host_socket_addr`)
+    pub fn socket_addr(&self) -> SocketAddr {
+        match self {
+            PeerAddress::Ip(a) => *a,
+            PeerAddress::Host { host, port } => host_socket_addr(host, *port),
+        }
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddress::Ip(a) => write!(f, "{}", a),
+            PeerAddress::Host { host, port } => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+/// Tor v3 onion service name (56 base32 characters + ".onion")
+pub fn is_onion_host(host: &str) -> bool {
+    match host.strip_suffix(".onion") {
+        Some(name) => {
+            name.len() == ONION_V3_LEN
+                && name.bytes().all(|b| b.is_ascii_lowercase() || (b'2'..=b'7').contains(&b))
+        }
+        None => false,
+    }
+}
+
+/// Globally routable IP
+///
+/// Excludes unspecified, loopback, multicast and broadcast
+/// addresses, RFC 1918 private and 100.64.0.0/10 carrier-grade
+/// NAT ranges, link-local (169.254.0.0/16, fe80::/10) and
+/// unique-local fc00::/7 (which holds the host-name mapping).
+/// IPv4-mapped IPv6 is judged by its IPv4 address.
+pub fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_public_v4(v4),
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_v4(&v4);
+            }
+            let first = v6.segments()[0];
+            !(v6.is_unspecified()
+                || v6.is_loopback()
+                || v6.is_multicast()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        || (a == 100 && b & 0xc0 == 64))
+}
+
+/// Stable IPv6 stand-in for a host name
+///
+/// Connections are keyed by SocketAddr; a proxied connection
+/// has no peer IP of its own, so the name is hashed into the
+/// fd87:d87e:eb43::/48 range. Bans and connection limits then
+/// apply per name.
+pub fn host_socket_addr(host: &str, port: u16) -> SocketAddr {
+    let hash = Sha256::digest(host.to_ascii_lowercase().as_bytes());
+
+    let mut octets = [0u8; 16];
+    octets[..6].copy_from_slice(&HOST_MAP_PREFIX);
+    octets[6..].copy_from_slice(&hash[..10]);
+
+    SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)
+}
+
+/// Address produced by `host_socket_addr`
+pub fn is_host_mapped(addr: &SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V6(ip) => ip.octets()[..6] == HOST_MAP_PREFIX,
+        IpAddr::V4(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn onion() -> String {
+        format!(
+            "{}.onion",
+            &"abcdefghijklmnopqrstuvwxyz234567".repeat(2)[..56]
+        )
+    }
+
+    #[test]
+    fn parses_ip_and_host_addresses() {
+        assert_eq!(
+            PeerAddress::parse("1.2.3.4:8333"),
+            Ok(PeerAddress::Ip("1.2.3.4:8333".parse().unwrap()))
+        );
+        assert_eq!(
+            PeerAddress::parse("[2001:db8::1]:8333"),
+            Ok(PeerAddress::Ip("[2001:db8::1]:8333".parse().unwrap()))
+        );
+
+        let upper = format!("{}:9000", onion().to_uppercase());
+        let addr = PeerAddress::parse(&upper).unwrap();
+        assert_eq!(addr.to_string(), format!("{}:9000", onion()));
+        assert!(addr.is_onion() && addr.is_public());
+        assert_eq!(addr.port(), 9000);
+
+        for bad in [
+            "1.2.3.4",
+            "host",
+            "host:port",
+            ":8333",
+            "2001:db8::1:x",
+            "a/b:8333",
+        ] {
+            assert!(PeerAddress::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn only_v3_onions_count() {
+        assert!(is_onion_host(&onion()));
+        assert!(!is_onion_host(&format!("{}.onion", "a".repeat(16))));
+        assert!(!is_onion_host(&format!("{}.onion", "1".repeat(56))));
+        assert!(!is_onion_host("example.org"));
+
+        // Other names are not worth keeping
+        let name = PeerAddress::parse("seed.example.org:8333").unwrap();
+        assert!(!name.is_onion() && !name.is_public());
+        assert!(!PeerAddress::parse(&format!("{}:0", onion()))
+            .unwrap()
+            .is_public());
+    }
+
+    #[test]
+    fn host_names_map_to_stable_local_addresses() {
+        let a = host_socket_addr(&onion(), 8333);
+        assert_eq!(a, host_socket_addr(&onion().to_uppercase(), 8333));
+        assert_ne!(a, host_socket_addr("seed.example.org", 8333));
+        assert_eq!(a.port(), 8333);
+        assert_eq!(
+            PeerAddress::parse(&format!("{}:8333", onion()))
+                .unwrap()
+                .socket_addr(),
+            a
+        );
+
+        // Recognisable, and never taken for a routable address
+        assert!(is_host_mapped(&a));
+        assert!(!is_public_ip(&a.ip()));
+        assert!(!is_host_mapped(&"[fd00::1]:8333".parse().unwrap()));
+        assert!(!is_host_mapped(&"1.2.3.4:8333".parse().unwrap()));
+    }
+}
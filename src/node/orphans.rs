@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::core::block::Block;
+use crate::node::peer_id::PeerId;
+
+/// Most orphan blocks held
+pub const MAX_ORPHAN_BLOCKS: usize = 100;
+
+/// Most orphan bytes held
+pub const MAX_ORPHAN_BYTES: usize = 32 * 1024 * 1024;
+
+/// Orphans whose parent never shows up are dropped after this
+const ORPHAN_EXPIRY: Duration = Duration::from_secs(20 * 60);
+
+struct OrphanBlock {
+    block: Block,
+    from: PeerId,
+    received: Instant,
+    size: usize,
+}
+
+/// Blocks waiting for their parent (POLICY ONLY)
+///
+/// Satellite, GEO and offline transports do not preserve
+/// ordering, so a child can arrive before its parent. Such
+/// blocks wait here, keyed by the missing parent hash, and are
+/// handed back once the parent connects.
+///
+/// Bounded by count and bytes; the oldest orphan goes first.
+#[derive(Default)]
+pub struct OrphanPool {
+    blocks: HashMap<Vec<u8>, OrphanBlock>,
+    /// parent hash → orphan hashes
+    by_parent: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    total_bytes: usize,
+}
+
+impl OrphanPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    /// Hold `block` until its parent arrives
+    ///
+    /// Only blocks whose proof of work checks out are kept, so
+    /// filling the pool costs real work. Returns false if the
+    /// block was rejected or is already held.
+    pub fn add(&mut self, block: Block, from: PeerId) -> bool {
+        if self.blocks.contains_key(&block.hash) || !block.verify_pow() {
+            return false;
+        }
+
+        // Size as carried on the wire
+        let size = bincode::serialized_size(&block)
+            .map(|s| s as usize)
+            .unwrap_or(usize::MAX);
+        if size > MAX_ORPHAN_BYTES {
+            return false;
+        }
+
+        self.expire();
+
+        while self.blocks.len() >= MAX_ORPHAN_BLOCKS
+            || self.total_bytes + size > MAX_ORPHAN_BYTES
+        {
+            if !self.evict_oldest() {
+                break;
+            }
+        }
+
+        self.by_parent
+            .entry(block.header.prev_hash.clone())
+            .or_default()
+            .push(block.hash.clone());
+
+        self.total_bytes += size;
+        self.blocks.insert(
+            block.hash.clone(),
+            OrphanBlock {
+                block,
+                from,
+                received: Instant::now(),
+                size,
+            },
+        );
+
+        true
+    }
+
+    /// Remove and return the orphans whose parent is `parent_hash`
+    /// (with the address each came from)
+    pub fn take_children(&mut self, parent_hash: &[u8]) -> Vec<(Block, PeerId)> {
+        let hashes = self.by_parent.remove(parent_hash).unwrap_or_default();
+
+        hashes
+            .into_iter()
+            .filter_map(|h| self.blocks.remove(&h))
+            .map(|o| {
+                self.total_bytes -= o.size;
+                (o.block, o.from)
+            })
+            .collect()
+    }
+
+    /// Drop orphans older than ORPHAN_EXPIRY
+    pub fn expire(&mut self) {
+        let expired: Vec<Vec<u8>> = self
+            .blocks
+            .iter()
+            .filter(|(_, o)| o.received.elapsed() > ORPHAN_EXPIRY)
+            .map(|(h, _)| h.clone())
+            .collect();
+
+        for h in expired {
+            self.remove(&h);
+        }
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self
+            .blocks
+            .iter()
+            .min_by_key(|(_, o)| o.received)
+            .map(|(h, _)| h.clone());
+
+        match oldest {
+            Some(h) => {
+                self.remove(&h);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&mut self, hash: &[u8]) {
+        let orphan = match self.blocks.remove(hash) {
+            Some(o) => o,
+            None => return,
+        };
+
+        self.total_bytes -= orphan.size;
+
+        let parent = &orphan.block.header.prev_hash;
+        if let Some(children) = self.by_parent.get_mut(parent) {
+            children.retain(|h| h != hash);
+            if children.is_empty() {
+                self.by_parent.remove(parent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::BlockHeader;
+
+    fn peer() -> PeerId {
+        PeerId::Tcp("1.2.3.4:8333".parse().unwrap())
+    }
+
+    /// A block with valid (trivial) proof of work, padded to
+    /// roughly `padding` bytes
+    fn orphan(parent: u8, nonce: u64, padding: usize) -> Block {
+        let mut block = Block {
+            header: BlockHeader {
+                height: 10,
+                timestamp: 0,
+                prev_hash: vec![parent; 32],
+                nonce,
+                target: [0xff; 32],
+                merkle_root: vec![0; padding],
+            },
+            transactions: vec![],
+            hash: vec![],
+        };
+        block.hash = block.hash_header();
+        block
+    }
+
+    fn backdate(pool: &mut OrphanPool, hash: &[u8], age: Duration) {
+        pool.blocks.get_mut(hash).unwrap().received = Instant::now() - age;
+    }
+
+    #[test]
+    fn keeps_only_blocks_with_valid_work() {
+        let mut pool = OrphanPool::new();
+
+        let mut forged = orphan(1, 0, 32);
+        forged.hash = vec![0; 32];
+        assert!(!pool.add(forged, peer()));
+
+        let mut hard = orphan(1, 0, 32);
+        hard.header.target = [0; 32];
+        hard.hash = hard.hash_header();
+        assert!(!pool.add(hard, peer()));
+
+        let block = orphan(1, 0, 32);
+        assert!(pool.add(block.clone(), peer()));
+        assert!(!pool.add(block.clone(), peer()));
+        assert!(pool.contains(&block.hash));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn hands_back_children_of_a_parent() {
+        let mut pool = OrphanPool::new();
+        let (a, b, other) = (orphan(1, 0, 32), orphan(1, 1, 32), orphan(2, 0, 32));
+        for block in [&a, &b, &other] {
+            assert!(pool.add(block.clone(), peer()));
+        }
+
+        let mut children: Vec<Vec<u8>> = pool
+            .take_children(&[1; 32])
+            .into_iter()
+            .map(|(block, from)| {
+                assert_eq!(from, peer());
+                block.hash
+            })
+            .collect();
+        children.sort();
+        let mut expected = vec![a.hash, b.hash];
+        expected.sort();
+
+        assert_eq!(children, expected);
+        assert!(pool.take_children(&[1; 32]).is_empty());
+        assert_eq!(pool.len(), 1);
+        assert!(pool.contains(&other.hash));
+    }
+
+    #[test]
+    fn evicts_the_oldest_beyond_the_count_limit() {
+        let mut pool = OrphanPool::new();
+        let first = orphan(1, 0, 32);
+        pool.add(first.clone(), peer());
+        backdate(&mut pool, &first.hash, Duration::from_secs(60));
+
+        for nonce in 1..=MAX_ORPHAN_BLOCKS as u64 {
+            assert!(pool.add(orphan(1, nonce, 32), peer()));
+        }
+
+        assert_eq!(pool.len(), MAX_ORPHAN_BLOCKS);
+        assert!(!pool.contains(&first.hash));
+    }
+
+    #[test]
+    fn evicts_the_oldest_beyond_the_byte_limit() {
+        let mut pool = OrphanPool::new();
+        let third = MAX_ORPHAN_BYTES / 3;
+
+        let first = orphan(1, 0, third);
+        pool.add(first.clone(), peer());
+        backdate(&mut pool, &first.hash, Duration::from_secs(60));
+        let second = orphan(1, 1, third);
+        pool.add(second.clone(), peer());
+
+        // Three thirds plus headers do not fit
+        let third_block = orphan(1, 2, third);
+        assert!(pool.add(third_block.clone(), peer()));
+        assert_eq!(pool.len(), 2);
+        assert!(!pool.contains(&first.hash));
+        assert!(pool.contains(&second.hash) && pool.contains(&third_block.hash));
+        assert!(pool.total_bytes <= MAX_ORPHAN_BYTES);
+
+        // Larger than the whole pool: refused outright
+        assert!(!pool.add(orphan(1, 3, MAX_ORPHAN_BYTES), peer()));
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn expires_orphans_after_twenty_minutes() {
+        let mut pool = OrphanPool::new();
+        let (old, fresh) = (orphan(1, 0, 32), orphan(1, 1, 32));
+        pool.add(old.clone(), peer());
+        pool.add(fresh.clone(), peer());
+
+        backdate(&mut pool, &old.hash, ORPHAN_EXPIRY + Duration::from_secs(1));
+        backdate(
+            &mut pool,
+            &fresh.hash,
+            ORPHAN_EXPIRY - Duration::from_secs(60),
+        );
+        pool.expire();
+
+        assert!(!pool.contains(&old.hash));
+        assert!(pool.contains(&fresh.hash));
+        // Its parent index went with it
+        assert_eq!(pool.take_children(&[1; 32]).len(), 1);
+        assert_eq!(pool.total_bytes, 0);
+    }
+}
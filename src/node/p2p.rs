@@ -1,96 +1,1096 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::random;
+use time::OffsetDateTime;
 
 use crate::core::block::Block;
 use crate::core::transaction::Transaction;
 use crate::core::chain::Blockchain;
 use crate::validation::validate_transaction;
-use crate::node::message::{NetworkMessage, PROTOCOL_VERSION};
-use crate::node::transport::Transport;
+use crate::consensus::params::MAX_FUTURE_DRIFT;
+use crate::node::message::{
+    NetworkMessage, GETBLOCKS_VERSION, MAX_GETBLOCKS, PROTOCOL_VERSION, USER_AGENT,
+};
+use crate::node::wire::{decode_message, encode_message};
+use crate::node::mempool::Mempool;
+use crate::node::peer_manager::{HandshakeError, PeerInfo, PeerManager, NODE_NETWORK, TARGET_OUTBOUND};
+use crate::node::addrman::{AddrMan, MAX_ADDR_PER_MSG};
+use crate::node::banman::{BanEntry, BanMan, BanTarget, DEFAULT_BAN_TIME};
+use crate::node::orphans::OrphanPool;
+use crate::node::download::BlockDownloader;
+use crate::node::bundle::{Bundle, ImportReport, Rejected};
+use crate::node::seeds::{parse_seed, resolve_seeds, Resolver, DEFAULT_PORT};
+use crate::node::netaddr::PeerAddress;
+use crate::node::peer_id::{PeerId, TransportKind};
+use crate::node::router::{RelayClass, RouteConfig, TransportRouter, TransportStats};
+use crate::node::transport::geo::LanNode;
+use crate::node::transport::{PeerEvent, Transport};
+
+/// How often timeouts, pings and outbound dialling are handled
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Ask a peer for addresses this often
+const GETADDR_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Write peers.dat at most this often
+const ADDRMAN_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A proxied dial that has not connected by now has failed
+const HOST_DIAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// With a saved address book, give it this long to connect
+/// before falling back to the seeds
+const SEED_DELAY: Duration = Duration::from_secs(11);
+
+// ───────── Misbehaviour scores (BAN_THRESHOLD = 100) ─────────
+
+/// Bytes that are not a frame (bad magic, checksum, oversize)
+const SCORE_MALFORMED_FRAME: u32 = 100;
+/// Valid frame, undecodable or mislabelled payload
+const SCORE_BAD_PAYLOAD: u32 = 20;
+/// Message before the handshake completed
+const SCORE_PRE_HANDSHAKE: u32 = 10;
+/// Repeated Hello / HelloAck
+const SCORE_HANDSHAKE_VIOLATION: u32 = 20;
+/// Addr message over MAX_ADDR_PER_MSG
+const SCORE_OVERSIZED_ADDR: u32 = 20;
+/// Block on our tip that fails validation
+const SCORE_INVALID_BLOCK: u32 = 100;
+/// Transaction that fails validation for reasons not explained
+/// by us lagging behind (all inputs known to us)
+const SCORE_INVALID_TX: u32 = 10;
+
+/// A LAN node announced over the GEO mesh is dialled again
+/// after this long if we are still not connected
+const LAN_REDIAL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Blocks relayed at most per block received (the block plus
+/// orphans it connected)
+const MAX_RELAY_BLOCKS: usize = 16;
 
 pub struct P2PNetwork {
-    transport: Arc<dyn Transport>,
+    /// Every transport we send over (the first one dials)
+    router: TransportRouter,
     chain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    peers: Mutex<PeerManager>,
+    addrman: Mutex<AddrMan>,
+
+    /// Last periodic GetAddr / peers.dat write
+    last_getaddr: Mutex<Instant>,
+    last_addrman_save: Mutex<Instant>,
+
+    banman: Mutex<BanMan>,
+
+    /// Blocks that arrived before their parent
+    orphans: Mutex<OrphanPool>,
+
+    /// Parallel block download (taken after the chain lock)
+    downloader: Mutex<BlockDownloader>,
+
+    /// Never scored, banned or refused
+    allowlist: Vec<IpAddr>,
+    /// Automatic ban length (seconds)
+    ban_time: u64,
+
+    /// Connections to host names (onion) → their address book
+    /// entry; the transport reports them under a mapped address
+    host_names: Mutex<HashMap<SocketAddr, (String, Instant)>>,
+
+    /// LAN nodes (TCP listener) → when we last dialled them
+    lan_dials: Mutex<HashMap<SocketAddr, Instant>>,
+
+    /// Never reveal our address: no listen port in Hello, seeds
+    /// dialled by name through the proxy instead of resolved
+    proxy_only: bool,
 }
 
 impl P2PNetwork {
     pub fn new(
         transport: Arc<dyn Transport>,
         chain: Arc<Mutex<Blockchain>>,
+        mempool: Arc<Mutex<Mempool>>,
     ) -> Self {
-        Self { transport, chain }
+        Self {
+            router: TransportRouter::new(transport, RouteConfig::default()),
+            chain,
+            mempool,
+            peers: Mutex::new(PeerManager::new(random(), TARGET_OUTBOUND)),
+            addrman: Mutex::new(AddrMan::load()),
+            last_getaddr: Mutex::new(Instant::now()),
+            last_addrman_save: Mutex::new(Instant::now()),
+            banman: Mutex::new(BanMan::load()),
+            orphans: Mutex::new(OrphanPool::new()),
+            downloader: Mutex::new(BlockDownloader::new()),
+            allowlist: vec![],
+            ban_time: DEFAULT_BAN_TIME,
+            host_names: Mutex::new(HashMap::new()),
+            lan_dials: Mutex::new(HashMap::new()),
+            proxy_only: false,
+        }
+    }
+
+    /// Trusted peers and automatic ban length
+    pub fn with_ban_config(mut self, allowlist: Vec<IpAddr>, ban_time: u64) -> Self {
+        self.allowlist = allowlist;
+        self.ban_time = ban_time;
+        self
+    }
+
+    /// Relay over another transport too (GEO mesh, BLE, ...)
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>, route: RouteConfig) -> Self {
+        self.router.register(transport, route);
+        self
+    }
+
+    /// Relay settings of a registered transport
+    pub fn with_route(mut self, kind: TransportKind, route: RouteConfig) -> Self {
+        self.router.set_route(kind, route);
+        self
+    }
+
+    /// Connect only through the transport's proxy
+    pub fn with_proxy_only(mut self, proxy_only: bool) -> Self {
+        self.proxy_only = proxy_only;
+        self
+    }
+
+    /// Start the maintenance thread (timeouts, pings, outbound
+    /// connections)
+    pub fn start(self: &Arc<Self>) {
+        self.router.start();
+
+        let p2p = Arc::clone(self);
+        thread::spawn(move || loop {
+            p2p.maintain();
+            thread::sleep(MAINTENANCE_INTERVAL);
+        });
+    }
+
+    /// Connection opened / closed by a transport (one-way
+    /// transports report sources the same way)
+    pub fn on_peer_event(&self, event: PeerEvent) {
+        match event {
+            PeerEvent::Connected { peer, inbound, encrypted } => {
+                if self.is_banned(&peer)
+                    || !self.peers.lock().unwrap().connected(peer.clone(), inbound, encrypted)
+                {
+                    self.router.disconnect(&peer);
+                    return;
+                }
+
+                if peer.kind().handshakes() {
+                    self.send_hello(&peer);
+                }
+            }
+            PeerEvent::Malformed(peer) => {
+                self.misbehaving(&peer, SCORE_MALFORMED_FRAME, "malformed frame");
+            }
+            PeerEvent::Disconnected(peer) => {
+                self.peers.lock().unwrap().disconnected(&peer);
+                if let PeerId::Tcp(addr) = &peer {
+                    self.host_names.lock().unwrap().remove(addr);
+                }
+                self.downloader.lock().unwrap().peer_disconnected(&peer);
+
+                // Its chunks go to the remaining peers
+                self.request_blocks();
+            }
+        }
+    }
+
+    /// Handle one framed message from any transport
+    ///
+    /// Connections must complete the handshake before anything
+    /// but Hello / HelloAck is accepted. One-way links (satellite,
+    /// BLE, offline imports, ...) may only deliver blocks and
+    /// transactions, which are validated anyway.
+    /// `data` is a block we asked `addr` for (GetBlocks reply)
+    ///
+    /// Such replies must reach us even if the same block was
+    /// just relayed, or the request never completes.
+    pub fn is_requested_block(&self, addr: &PeerId, data: &[u8]) -> bool {
+        match decode_message(data) {
+            Ok(NetworkMessage::Block(block)) => self
+                .downloader
+                .lock()
+                .unwrap()
+                .expects(addr, block.header.height),
+            _ => false,
+        }
     }
 
-    pub fn on_receive(&self, addr: SocketAddr, data: Vec<u8>) {
-        let msg: NetworkMessage = match bincode::deserialize(&data) {
+    pub fn on_receive(&self, addr: PeerId, data: Vec<u8>) {
+        self.router.record_received(&addr, data.len());
+
+        let (is_connection, ready) = {
+            let mut peers = self.peers.lock().unwrap();
+            peers.seen(&addr);
+            (peers.is_connection(&addr), peers.is_ready(&addr))
+        };
+
+        // A banned one-way source keeps delivering; ignore it
+        if !is_connection && self.is_banned(&addr) {
+            return;
+        }
+
+        let msg = match decode_message(&data) {
             Ok(m) => m,
-            Err(_) => return,
+            Err(_) => {
+                self.misbehaving(&addr, SCORE_BAD_PAYLOAD, "undecodable message");
+                return;
+            }
+        };
+
+        let handshakes = addr.kind().handshakes();
+        let allowed = match &msg {
+            NetworkMessage::Hello { .. } | NetworkMessage::HelloAck => {
+                is_connection && handshakes
+            }
+            NetworkMessage::Block(_) | NetworkMessage::Transaction(_) => {
+                ready || !handshakes
+            }
+            _ => ready,
         };
 
+        if !allowed {
+            self.misbehaving(&addr, SCORE_PRE_HANDSHAKE, "message before handshake");
+            return;
+        }
+
         match msg {
-            NetworkMessage::Hello { version, height, .. } => {
-                if version != PROTOCOL_VERSION {
-                    return;
+            NetworkMessage::Hello {
+                version,
+                height,
+                agent,
+                services,
+                nonce,
+                listen_port,
+            } => {
+                let result = self.peers.lock().unwrap().on_hello(
+                    &addr, version, height, agent, services, nonce,
+                );
+
+                match result {
+                    Ok(()) => {}
+                    // Not the peer's fault
+                    Err(HandshakeError::ConnectedToSelf | HandshakeError::ObsoleteVersion) => {
+                        self.router.disconnect(&addr);
+                        return;
+                    }
+                    Err(e) => {
+                        self.misbehaving(&addr, SCORE_HANDSHAKE_VIOLATION, e.reason());
+                        self.router.disconnect(&addr);
+                        return;
+                    }
+                }
+
+                self.send(&addr, &NetworkMessage::HelloAck);
+                self.on_handshake_progress(&addr);
+
+                // An inbound peer that listens is worth telling others about
+                let inbound = self
+                    .peers
+                    .lock()
+                    .unwrap()
+                    .peer(&addr)
+                    .map(|p| p.inbound)
+                    .unwrap_or(false);
+
+                if let (true, Some(ip)) = (inbound && listen_port != 0, addr.ip()) {
+                    let listener = SocketAddr::new(ip, listen_port);
+                    self.addrman
+                        .lock()
+                        .unwrap()
+                        .add_seen(&listener.to_string(), services);
                 }
 
+                // Newer peers are served by the download scheduler
+                // once the handshake completes
                 let local_height = self.chain.lock().unwrap().height();
-                if height > local_height {
-                    self.send(
-                        addr,
-                        &NetworkMessage::SyncRequest {
-                            from_height: local_height,
-                        },
-                    );
+                if height > local_height && version < GETBLOCKS_VERSION {
+                    self.sync_request(&addr, local_height);
+                }
+            }
+
+            NetworkMessage::HelloAck => {
+                let result = self.peers.lock().unwrap().on_ack(&addr);
+                if let Err(e) = result {
+                    self.misbehaving(&addr, SCORE_HANDSHAKE_VIOLATION, e.reason());
+                    self.router.disconnect(&addr);
+                    return;
+                }
+                self.on_handshake_progress(&addr);
+            }
+
+            NetworkMessage::GetAddr => {
+                if !self.peers.lock().unwrap().take_getaddr(&addr) {
+                    return;
                 }
+
+                let addrs = self.addrman.lock().unwrap().get_addr(MAX_ADDR_PER_MSG);
+                self.send(&addr, &NetworkMessage::Addr(addrs));
             }
 
+            NetworkMessage::Addr(addrs) => {
+                if addrs.len() > MAX_ADDR_PER_MSG {
+                    self.misbehaving(&addr, SCORE_OVERSIZED_ADDR, "oversized addr");
+                    return;
+                }
+                self.addrman.lock().unwrap().add(&addrs);
+            }
+
+            // One page; the peer asks again from its new tip
             NetworkMessage::SyncRequest { from_height } => {
-                let c = self.chain.lock().unwrap();
-                for b in c.blocks.iter().skip(from_height as usize) {
-                    self.send(addr, &NetworkMessage::Block(b.clone()));
+                for b in self.blocks_from(from_height, MAX_GETBLOCKS) {
+                    self.send(&addr, &NetworkMessage::Block(b));
+                }
+            }
+
+            NetworkMessage::GetBlocks { from_height, count } => {
+                for b in self.blocks_from(from_height, count.min(MAX_GETBLOCKS)) {
+                    self.send(&addr, &NetworkMessage::Block(b));
                 }
             }
 
             NetworkMessage::Block(block) => {
-                self.chain
-                    .lock()
-                    .unwrap()
-                    .validate_and_add_block(block);
+                let height = block.header.height;
+                self.peers.lock().unwrap().update_height(&addr, height + 1);
+
+                let requested = self.downloader.lock().unwrap().expects(&addr, height);
+                if requested {
+                    self.downloader.lock().unwrap().block_received(addr, block);
+                    self.connect_downloaded();
+                    self.request_blocks();
+                } else {
+                    self.process_block(addr, block);
+                }
             }
 
             NetworkMessage::Transaction(tx) => {
                 let c = self.chain.lock().unwrap();
-                let _ = validate_transaction(&tx, &c.utxos, c.height());
+                let invalid = !validate_transaction(&tx, &c.utxos, c.height())
+                    || tx.inputs.is_empty();
+
+                if invalid {
+                    if tx_is_punishable(&c, &tx) {
+                        drop(c);
+                        self.misbehaving(&addr, SCORE_INVALID_TX, "invalid transaction");
+                    }
+                    return;
+                }
+
+                // Policy rejections (fee, conflicts, already known)
+                // are not the sender's fault; only new entries are
+                // passed on
+                let accepted = self.mempool.lock().unwrap().add_transaction(
+                    tx.clone(),
+                    &c.utxos,
+                    c.height(),
+                );
+                drop(c);
+
+                if accepted {
+                    self.relay(&NetworkMessage::Transaction(tx), Some(&addr));
+                }
             }
 
             NetworkMessage::Ping => {
-                self.send(addr, &NetworkMessage::Pong);
+                self.send(&addr, &NetworkMessage::Pong);
             }
 
             _ => {}
         }
     }
 
-    fn send(&self, addr: SocketAddr, msg: &NetworkMessage) {
-        let data = bincode::serialize(msg).unwrap();
-        self.transport.send(&addr, &data);
+    /// Connect a block from the network, or hold it as an orphan
+    ///
+    /// Orphans waiting on a block that connects are connected
+    /// right after it, recursively; whatever connects is relayed
+    /// (downloaded blocks are not: peers syncing fetch their own).
+    fn process_block(&self, addr: PeerId, block: Block) {
+        let mut c = self.chain.lock().unwrap();
+        let local_height = c.height();
+
+        // Ahead of our tip: the parent (at least) is missing
+        if block.header.height > local_height {
+            drop(c);
+
+            if !self.orphans.lock().unwrap().add(block, addr.clone()) {
+                return;
+            }
+
+            // Missing parents: scheduled across peers that can serve
+            // GetBlocks, otherwise asked of the announcing peer
+            let version = self
+                .peers
+                .lock()
+                .unwrap()
+                .peer(&addr)
+                .map(|p| p.version)
+                .unwrap_or(0);
+
+            if version >= GETBLOCKS_VERSION {
+                self.request_blocks();
+            } else if self.peers.lock().unwrap().take_parent_request(&addr) {
+                self.sync_request(&addr, local_height);
+            }
+            return;
+        }
+
+        let mut offenders = Vec::new();
+        let mut relay = Vec::new();
+        let mut next_page = None;
+        if self.connect_block(&mut c, block, addr.clone(), &mut offenders) {
+            self.mempool.lock().unwrap().update_chain_tip(&c);
+
+            let new_height = c.height() as usize;
+            let from = (local_height as usize).max(new_height.saturating_sub(MAX_RELAY_BLOCKS));
+            relay = c.blocks[from.min(new_height)..new_height].to_vec();
+
+            next_page = self
+                .peers
+                .lock()
+                .unwrap()
+                .next_sync_page(&addr, new_height as u64);
+        }
+        drop(c);
+
+        if let Some(from_height) = next_page {
+            self.send(&addr, &NetworkMessage::SyncRequest { from_height });
+        }
+
+        for from in offenders {
+            self.misbehaving(&from, SCORE_INVALID_BLOCK, "invalid block");
+        }
+
+        for block in relay {
+            self.relay(&NetworkMessage::Block(block), Some(&addr));
+        }
+    }
+
+    /// Ask a peer without GetBlocks for blocks from `from_height`
+    fn sync_request(&self, addr: &PeerId, from_height: u64) {
+        self.peers.lock().unwrap().sync_requested(addr, from_height);
+        self.send(addr, &NetworkMessage::SyncRequest { from_height });
+    }
+
+    /// Up to `count` blocks from `from_height`, copied out so
+    /// they are sent without holding the chain lock
+    fn blocks_from(&self, from_height: u64, count: u64) -> Vec<Block> {
+        let c = self.chain.lock().unwrap();
+        c.blocks
+            .iter()
+            .skip(from_height as usize)
+            .take(count as usize)
+            .cloned()
+            .collect()
+    }
+
+    /// Validate and connect `block`, then the orphans that were
+    /// waiting on it, recursively
+    ///
+    /// Senders of blocks that fail where they should not are
+    /// added to `offenders`.
+    fn connect_block(
+        &self,
+        c: &mut Blockchain,
+        block: Block,
+        from: PeerId,
+        offenders: &mut Vec<PeerId>,
+    ) -> bool {
+        let punishable = block_is_punishable(c, &block);
+        let hash = block.hash.clone();
+
+        if !c.validate_and_add_block(block) {
+            if punishable {
+                offenders.push(from);
+            }
+            return false;
+        }
+
+        let mut connected = vec![hash];
+
+        while let Some(parent) = connected.pop() {
+            let children = self.orphans.lock().unwrap().take_children(&parent);
+
+            for (child, child_from) in children {
+                let punishable = block_is_punishable(c, &child);
+                let child_hash = child.hash.clone();
+
+                if c.validate_and_add_block(child) {
+                    connected.push(child_hash);
+                } else if punishable {
+                    offenders.push(child_from);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Connect downloaded blocks that extend the tip, in order
+    ///
+    /// A peer whose block fails loses everything it delivered;
+    /// those heights are downloaded again from others.
+    fn connect_downloaded(&self) {
+        let mut c = self.chain.lock().unwrap();
+        let mut offenders = Vec::new();
+        let mut connected = false;
+
+        loop {
+            let next = self.downloader.lock().unwrap().take_next(c.height());
+            let Some((block, from)) = next else {
+                break;
+            };
+
+            if self.connect_block(&mut c, block, from.clone(), &mut offenders) {
+                connected = true;
+            } else {
+                self.downloader.lock().unwrap().discard_from(&from);
+            }
+        }
+
+        if connected {
+            self.mempool.lock().unwrap().update_chain_tip(&c);
+        }
+        drop(c);
+
+        for from in offenders {
+            self.misbehaving(&from, SCORE_INVALID_BLOCK, "invalid block");
+        }
+    }
+
+    /// Send GetBlocks for whatever the scheduler assigns now
+    fn request_blocks(&self) {
+        let local_height = self.chain.lock().unwrap().height();
+        let peers = self
+            .peers
+            .lock()
+            .unwrap()
+            .download_peers(GETBLOCKS_VERSION);
+
+        let requests = self
+            .downloader
+            .lock()
+            .unwrap()
+            .schedule(local_height, &peers);
+
+        for (peer, from_height, count) in requests {
+            self.send(&peer, &NetworkMessage::GetBlocks { from_height, count });
+        }
+    }
+
+    /// Blocks waiting for a parent
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.lock().unwrap().len()
+    }
+
+    /// Score a protocol violation; ban and disconnect at the
+    /// threshold. Allowlisted peers and sources that never
+    /// reported a connection are not scored.
+    fn misbehaving(&self, addr: &PeerId, score: u32, reason: &str) {
+        let target = BanTarget::of(addr);
+        if self.is_allowlisted(&target) {
+            return;
+        }
+
+        if !self.peers.lock().unwrap().misbehaving(addr, score) {
+            return;
+        }
+
+        println!("🚫 Banning {} ({})", target, reason);
+        self.set_ban(target, self.ban_time, reason);
+    }
+
+    fn is_allowlisted(&self, target: &BanTarget) -> bool {
+        matches!(target, BanTarget::Ip(ip) if self.allowlist.contains(ip))
+    }
+
+    fn is_banned(&self, peer: &PeerId) -> bool {
+        let target = BanTarget::of(peer);
+        !self.is_allowlisted(&target) && self.banman.lock().unwrap().is_banned(&target)
+    }
+
+    /// Ban `target` for `duration` seconds and drop its connections
+    pub fn set_ban(&self, target: BanTarget, duration: u64, reason: &str) {
+        self.banman.lock().unwrap().ban(target.clone(), duration, reason);
+
+        if self.is_allowlisted(&target) {
+            return;
+        }
+
+        let addrs = match &target {
+            BanTarget::Ip(ip) => self.peers.lock().unwrap().addrs_with_ip(ip),
+            BanTarget::Peer(peer) => vec![peer.clone()],
+        };
+        for addr in addrs {
+            self.drop_peer(&addr);
+        }
+    }
+
+    /// Close a connection; a one-way link cannot be closed, so
+    /// it is forgotten instead (it reappears on its next
+    /// Connected event, unless banned)
+    fn drop_peer(&self, peer: &PeerId) {
+        if peer.kind().handshakes() {
+            self.router.disconnect(peer);
+        } else {
+            self.peers.lock().unwrap().disconnected(peer);
+        }
+    }
+
+    /// Lift a ban; false if `target` was not banned
+    pub fn unban(&self, target: &BanTarget) -> bool {
+        self.banman.lock().unwrap().unban(target)
+    }
+
+    pub fn list_banned(&self) -> Vec<BanEntry> {
+        self.banman.lock().unwrap().list()
+    }
+
+    /// After Hello / HelloAck: once ready, an outbound peer is
+    /// known good and asked for addresses, and any peer can be
+    /// given blocks to download
+    fn on_handshake_progress(&self, addr: &PeerId) {
+        let outbound_ready = {
+            let peers = self.peers.lock().unwrap();
+            peers.is_ready(addr)
+                && peers.peer(addr).map(|p| !p.inbound).unwrap_or(false)
+        };
+
+        if outbound_ready {
+            let name = self.book_name(addr);
+            self.addrman.lock().unwrap().good(&name);
+            self.send(addr, &NetworkMessage::GetAddr);
+        }
+
+        if self.peers.lock().unwrap().is_ready(addr) {
+            self.request_blocks();
+        }
+    }
+
+    fn send_hello(&self, addr: &PeerId) {
+        let height = self.chain.lock().unwrap().height();
+        let nonce = self.peers.lock().unwrap().local_nonce();
+        let listen_port = if self.proxy_only {
+            0
+        } else {
+            self.router.primary().local_addr().map(|a| a.port()).unwrap_or(0)
+        };
+
+        self.send(
+            addr,
+            &NetworkMessage::Hello {
+                version: PROTOCOL_VERSION,
+                height,
+                agent: USER_AGENT.to_string(),
+                services: NODE_NETWORK,
+                nonce,
+                listen_port,
+            },
+        );
+    }
+
+    /// Address book entry for a connection
+    fn book_name(&self, addr: &PeerId) -> String {
+        let name = match addr {
+            PeerId::Tcp(a) => self.host_names.lock().unwrap().get(a).map(|(n, _)| n.clone()),
+            _ => None,
+        };
+        name.unwrap_or_else(|| addr.to_string())
+    }
+
+    fn send(&self, addr: &PeerId, msg: &NetworkMessage) {
+        self.router.send(addr, &encode_message(msg));
+    }
+
+    /// Pass a block or transaction on over every transport,
+    /// except back to `origin`
+    fn relay(&self, msg: &NetworkMessage, origin: Option<&PeerId>) {
+        let class = match msg {
+            NetworkMessage::Block(_) => RelayClass::Block,
+            _ => RelayClass::Transaction,
+        };
+        let ready = self.peers.lock().unwrap().ready_peers();
+        self.router.relay(class, &encode_message(msg), &ready, origin);
+    }
+
+    /// Drop stalled peers, ping quiet ones, top up outbound
+    /// from the address book, refresh and persist it
+    fn maintain(&self) {
+        let (timed_out, ping, needed, mut exclude, ready) = {
+            let mut peers = self.peers.lock().unwrap();
+            (
+                peers.timed_out(),
+                peers.due_for_ping(),
+                peers.outbound_needed(),
+                peers.connected_addrs(),
+                peers.ready_peers(),
+            )
+        };
+
+        {
+            let mut names = self.host_names.lock().unwrap();
+            names.retain(|a, (_, dialled)| {
+                dialled.elapsed() < HOST_DIAL_TIMEOUT || exclude.contains(&a.to_string())
+            });
+            exclude.extend(names.values().map(|(name, _)| name.clone()));
+        }
+        let onion = self.router.primary().reaches_hosts();
+
+        let mut dial = Vec::new();
+        {
+            let mut addrman = self.addrman.lock().unwrap();
+            for _ in 0..needed {
+                let Some(candidate) = addrman.select(&exclude, onion) else {
+                    break;
+                };
+                addrman.attempt(&candidate);
+                exclude.push(candidate.clone());
+
+                if let Ok(a) = PeerAddress::parse(&candidate) {
+                    if !self.is_banned(&PeerId::Tcp(a.socket_addr())) {
+                        dial.push(a);
+                    }
+                }
+            }
+        }
+
+        for addr in timed_out {
+            self.router.disconnect(&addr);
+        }
+
+        for addr in ping {
+            self.send(&addr, &NetworkMessage::Ping);
+        }
+
+        // Handshake starts from the Connected event
+        for addr in dial {
+            self.dial(&addr);
+        }
+
+        self.orphans.lock().unwrap().expire();
+
+        // Stalled downloads are reassigned; repeat stallers dropped
+        let local_height = self.chain.lock().unwrap().height();
+        let stallers = self.downloader.lock().unwrap().check_stalls(local_height);
+        for addr in stallers {
+            println!("🐢 Peer {} stalled block download, disconnecting", addr);
+            self.router.disconnect(&addr);
+        }
+        self.request_blocks();
+
+        // Periodic GetAddr keeps the address book fresh
+        {
+            let mut last = self.last_getaddr.lock().unwrap();
+            if last.elapsed() >= GETADDR_INTERVAL && !ready.is_empty() {
+                *last = Instant::now();
+                let peer = &ready[random::<usize>() % ready.len()];
+                self.send(peer, &NetworkMessage::GetAddr);
+            }
+        }
+
+        let mut last_save = self.last_addrman_save.lock().unwrap();
+        if last_save.elapsed() >= ADDRMAN_SAVE_INTERVAL {
+            *last_save = Instant::now();
+            let mut addrman = self.addrman.lock().unwrap();
+            if addrman.is_dirty() {
+                addrman.save();
+            }
+        }
+    }
+
+    /// Fill the address book from seed entries
+    ///
+    /// A node with a persisted address book tries that first
+    /// and only resolves seeds if it has no peers after
+    /// SEED_DELAY. Runs in the background.
+    pub fn bootstrap(self: &Arc<Self>, seeds: Vec<String>, resolver: Arc<dyn Resolver>) {
+        let p2p = Arc::clone(self);
+
+        thread::spawn(move || {
+            let known = p2p.addrman.lock().unwrap().len();
+            if known > 0 {
+                println!("📒 {} known peer addresses", known);
+                thread::sleep(SEED_DELAY);
+                if p2p.peer_count() > 0 {
+                    return;
+                }
+            }
+
+            // No DNS lookups of our own: the proxy resolves seed names
+            if p2p.proxy_only {
+                for seed in &seeds {
+                    if let Ok((host, port)) = parse_seed(seed, DEFAULT_PORT) {
+                        match host.parse::<IpAddr>() {
+                            Ok(ip) => p2p.add_peer_candidate(SocketAddr::new(ip, port), true),
+                            Err(_) => p2p.dial(&PeerAddress::Host { host, port }),
+                        }
+                    }
+                }
+                println!("🌱 {} seed(s) dialled through the proxy", seeds.len());
+                return;
+            }
+
+            let addrs = resolve_seeds(&seeds, resolver.as_ref(), DEFAULT_PORT);
+            println!("🌱 {} seed address(es) from {} seed(s)", addrs.len(), seeds.len());
+
+            // IP entries are the operator's own; DNS answers are
+            // held to the rules for gossiped addresses
+            for seed in addrs {
+                p2p.add_peer_candidate(seed.addr, seed.literal);
+            }
+        });
+    }
+
+    /// Open an outbound connection (host names via the proxy)
+    fn dial(&self, addr: &PeerAddress) {
+        match addr {
+            PeerAddress::Ip(a) => {
+                self.router.primary().connect(a);
+            }
+            PeerAddress::Host { host, port } => {
+                if let Some(key) = self.router.primary().connect_host(host, *port) {
+                    self.host_names
+                        .lock()
+                        .unwrap()
+                        .insert(key, (addr.to_string(), Instant::now()));
+                }
+            }
+        }
+    }
+
+    /// Address to keep in the address book: from a DNS seed,
+    /// or `manual` (the operator's, private ranges allowed)
+    pub fn add_peer_candidate(&self, addr: SocketAddr, manual: bool) {
+        let mut addrman = self.addrman.lock().unwrap();
+        if manual {
+            addrman.add_local(&addr.to_string(), NODE_NETWORK);
+        } else {
+            addrman.add_seen(&addr.to_string(), NODE_NETWORK);
+        }
+    }
+
+    /// Node announced on the LAN mesh: connect to its TCP port
+    ///
+    /// Only the side with the lower node id dials. LAN
+    /// addresses are private, so they stay out of the address
+    /// book; proxy-only nodes never connect directly.
+    pub fn on_lan_node(&self, node: LanNode) {
+        if !node.initiator || self.proxy_only {
+            return;
+        }
+
+        let peer = PeerId::Tcp(node.addr);
+        if self.is_banned(&peer) || self.peers.lock().unwrap().peer(&peer).is_some() {
+            return;
+        }
+
+        {
+            let mut dials = self.lan_dials.lock().unwrap();
+            dials.retain(|_, at| at.elapsed() < LAN_REDIAL_INTERVAL);
+            if dials.contains_key(&node.addr) {
+                return;
+            }
+            dials.insert(node.addr, Instant::now());
+        }
+
+        println!("🏠 LAN node {} at height {}, connecting", node.addr, node.height);
+        self.router.primary().connect(&node.addr);
+    }
+
+    /// Persist the address book now (shutdown); otherwise it
+    /// is saved every ADDRMAN_SAVE_INTERVAL
+    pub fn save_state(&self) {
+        let mut addrman = self.addrman.lock().unwrap();
+        if addrman.is_dirty() {
+            addrman.save();
+        }
+    }
+
+    /// Validate a locally produced block (miner, API), update the
+    /// mempool and relay it to peers if accepted
+    pub fn submit_block(&self, block: Block) -> bool {
+        let accepted = {
+            let mut c = self.chain.lock().unwrap();
+            let accepted = c.validate_and_add_block(block.clone());
+            if accepted {
+                self.mempool.lock().unwrap().update_chain_tip(&c);
+            }
+            accepted
+        };
+
+        if accepted {
+            self.broadcast_block(&block);
+        }
+
+        accepted
+    }
+
+    /// Apply a sync bundle read from `source` (a file name)
+    ///
+    /// Blocks go through the same validation as from a peer,
+    /// in order; whatever connects is relayed, as are accepted
+    /// transactions. Nothing is held as an orphan: a bundle
+    /// that does not reach down to our chain is rejected.
+    pub fn import_bundle(&self, bundle: Bundle, source: &str) -> ImportReport {
+        let origin = PeerId::Offline(source.to_string());
+        let mut report = ImportReport {
+            signer: bundle.signer.as_ref().map(hex::encode),
+            ..Default::default()
+        };
+
+        let mut c = self.chain.lock().unwrap();
+        let mut connected = Vec::new();
+        let mut offenders = Vec::new();
+
+        for block in bundle.blocks {
+            let height = block.header.height;
+            let id = hex::encode(&block.hash);
+
+            if c.blocks.get(height as usize).map(|b| b.hash == block.hash).unwrap_or(false) {
+                report.blocks_skipped += 1;
+                continue;
+            }
+
+            let reason = if height > c.height() {
+                "missing parent"
+            } else if height < c.height() {
+                "conflicts with our chain"
+            } else if self.connect_block(&mut c, block.clone(), origin.clone(), &mut offenders) {
+                report.blocks_applied += 1;
+                connected.push(block);
+                continue;
+            } else {
+                "invalid"
+            };
+            report.blocks_rejected.push(Rejected { id, reason });
+        }
+
+        if !connected.is_empty() {
+            self.mempool.lock().unwrap().update_chain_tip(&c);
+        }
+
+        let mut relay = Vec::new();
+        for tx in bundle.transactions {
+            let txid = tx.txid();
+            if self.mempool.lock().unwrap().contains(&txid) {
+                report.transactions_skipped += 1;
+                continue;
+            }
+
+            let reason = if !validate_transaction(&tx, &c.utxos, c.height()) || tx.inputs.is_empty() {
+                "invalid or already confirmed"
+            } else if self.mempool.lock().unwrap().add_transaction(tx.clone(), &c.utxos, c.height()) {
+                report.transactions_applied += 1;
+                relay.push(tx);
+                continue;
+            } else {
+                "rejected by mempool policy"
+            };
+            report
+                .transactions_rejected
+                .push(Rejected { id: hex::encode(txid), reason });
+        }
+
+        report.height = c.height();
+        drop(c);
+
+        // Orphans from peers that the imported blocks unblocked
+        // (the import itself is never scored)
+        for from in offenders {
+            self.misbehaving(&from, SCORE_INVALID_BLOCK, "invalid block");
+        }
+
+        let skip = connected.len().saturating_sub(MAX_RELAY_BLOCKS);
+        for block in connected.into_iter().skip(skip) {
+            self.relay(&NetworkMessage::Block(block), Some(&origin));
+        }
+        for tx in relay {
+            self.relay(&NetworkMessage::Transaction(tx), Some(&origin));
+        }
+
+        report
     }
 
     pub fn broadcast_block(&self, block: &Block) {
-        let data =
-            bincode::serialize(&NetworkMessage::Block(block.clone())).unwrap();
-        self.transport.broadcast(&data);
+        self.relay(&NetworkMessage::Block(block.clone()), None);
     }
 
     pub fn broadcast_transaction(&self, tx: &Transaction) {
-        let data = bincode::serialize(
-            &NetworkMessage::Transaction(tx.clone()),
-        )
-        .unwrap();
-        self.transport.broadcast(&data);
+        self.relay(&NetworkMessage::Transaction(tx.clone()), None);
     }
 
+    /// Frames of the latest `blocks` blocks (oldest first, so
+    /// they connect in order) and the best `transactions`
+    /// mempool transactions, for the satellite carousel
+    pub fn carousel(&self, blocks: usize, transactions: usize) -> Vec<Vec<u8>> {
+        let mut frames: Vec<Vec<u8>> = {
+            let chain = self.chain.lock().unwrap();
+            let start = chain.blocks.len().saturating_sub(blocks);
+            chain.blocks[start..]
+                .iter()
+                .map(|b| encode_message(&NetworkMessage::Block(b.clone())))
+                .collect()
+        };
+
+        let mempool = self.mempool.lock().unwrap();
+        frames.extend(
+            mempool
+                .sorted_for_mining()
+                .into_iter()
+                .take(transactions)
+                .map(|tx| encode_message(&NetworkMessage::Transaction(tx))),
+        );
+        frames
+    }
+
+    /// Peers that completed the handshake
     pub fn peer_count(&self) -> usize {
-        self.transport.peers().len()
+        self.peers.lock().unwrap().ready_peers().len()
+    }
+
+    /// State of every connection
+    pub fn peer_info(&self) -> Vec<PeerInfo> {
+        self.peers.lock().unwrap().peers()
     }
+
+    /// Traffic per transport, with its current peer count
+    pub fn transport_stats(&self) -> Vec<TransportStats> {
+        let peers = self.peers.lock().unwrap().peers();
+        let mut stats = self.router.stats();
+        for s in &mut stats {
+            s.peers = peers.iter().filter(|p| p.addr.kind() == s.transport).count();
+        }
+        stats
+    }
+}
+
+/// A block failing validation is the sender's fault only if it
+/// builds on our tip and is not merely ahead of our clock;
+/// stale, duplicate and fork blocks are not scored
+fn block_is_punishable(chain: &Blockchain, block: &Block) -> bool {
+    let on_tip = chain
+        .blocks
+        .last()
+        .map(|tip| tip.hash == block.header.prev_hash)
+        .unwrap_or(false);
+
+    let too_new = block.header.timestamp
+        > OffsetDateTime::now_utc().unix_timestamp() + MAX_FUTURE_DRIFT;
+
+    on_tip && !too_new
+}
+
+/// With an input we do not know, we may just be behind
+fn tx_is_punishable(chain: &Blockchain, tx: &Transaction) -> bool {
+    tx.inputs.iter().all(|i| {
+        let key = format!("{}:{}", hex::encode(&i.txid), i.index);
+        chain.utxos.contains_key(&key)
+    })
 }
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Kind of link a peer is reached over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    Tcp,
+    Geo,
+    Bluetooth,
+    Satellite,
+    Offline,
+}
+
+impl TransportKind {
+    /// Two-way connections that run the version handshake;
+    /// the other kinds are one-way links that only deliver
+    /// blocks and transactions
+    pub fn handshakes(self) -> bool {
+        matches!(self, TransportKind::Tcp)
+    }
+
+    /// Prefix in a PeerId's text form
+    pub fn prefix(self) -> &'static str {
+        match self {
+            TransportKind::Tcp => "tcp",
+            TransportKind::Geo => "geo",
+            TransportKind::Bluetooth => "ble",
+            TransportKind::Satellite => "sat",
+            TransportKind::Offline => "offline",
+        }
+    }
+}
+
+impl fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.prefix())
+    }
+}
+
+/// A peer as seen by the transport that delivered it
+///
+/// Transport kind plus that transport's own address, so peers
+/// without an IP endpoint (a BLE device, a satellite feed, an
+/// imported file) can still be told apart, scored and banned.
+///
+/// Text form: TCP peers are plain "ip:port" (as before), the
+/// others are prefixed, e.g. "ble:<device>", "sat:<feed>".
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PeerId {
+    Tcp(SocketAddr),
+    /// LAN mesh datagram sender
+    Geo(SocketAddr),
+    /// Device id as reported by the Bluetooth adapter
+    Bluetooth(String),
+    /// Downlink feed: decoder address or file / pipe path
+    Satellite(String),
+    /// Imported file
+    Offline(String),
+}
+
+impl PeerId {
+    pub fn kind(&self) -> TransportKind {
+        match self {
+            PeerId::Tcp(_) => TransportKind::Tcp,
+            PeerId::Geo(_) => TransportKind::Geo,
+            PeerId::Bluetooth(_) => TransportKind::Bluetooth,
+            PeerId::Satellite(_) => TransportKind::Satellite,
+            PeerId::Offline(_) => TransportKind::Offline,
+        }
+    }
+
+    /// IP endpoint, for transports that have one
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self {
+            PeerId::Tcp(a) | PeerId::Geo(a) => Some(*a),
+            _ => None,
+        }
+    }
+
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.socket_addr().map(|a| a.ip())
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerId::Tcp(a) => write!(f, "{}", a),
+            PeerId::Geo(a) => write!(f, "geo:{}", a),
+            PeerId::Bluetooth(s) => write!(f, "ble:{}", s),
+            PeerId::Satellite(s) => write!(f, "sat:{}", s),
+            PeerId::Offline(s) => write!(f, "offline:{}", s),
+        }
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = &'static str;
+
+    /// Inverse of Display ("tcp:" is accepted as well)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(a) = s.parse::<SocketAddr>() {
+            return Ok(PeerId::Tcp(a));
+        }
+
+        let (prefix, rest) = s.split_once(':').ok_or("unknown peer id")?;
+        if rest.is_empty() {
+            return Err("empty peer address");
+        }
+
+        match prefix {
+            "tcp" => rest.parse().map(PeerId::Tcp).map_err(|_| "invalid tcp address"),
+            "geo" => rest.parse().map(PeerId::Geo).map_err(|_| "invalid geo address"),
+            "ble" => Ok(PeerId::Bluetooth(rest.to_string())),
+            "sat" => Ok(PeerId::Satellite(rest.to_string())),
+            "offline" => Ok(PeerId::Offline(rest.to_string())),
+            _ => Err("unknown transport"),
+        }
+    }
+}
+
+impl Serialize for PeerId {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PeerId {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
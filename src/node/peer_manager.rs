@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::node::message::{MAX_GETBLOCKS, MIN_PROTOCOL_VERSION};
+use crate::node::peer_id::PeerId;
+
+/// Service bit: serves full blocks
+pub const NODE_NETWORK: u64 = 1;
+
+/// Outbound connections kept open
+pub const TARGET_OUTBOUND: usize = 8;
+
+/// Inbound connections accepted (one-way links not counted)
+pub const MAX_INBOUND: usize = 117;
+
+/// Misbehaviour score at which a peer is banned
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// Handshake must finish within this
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Ping a ready peer that has been quiet this long
+const PING_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Drop a peer silent for this long
+const INACTIVITY_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Answer a peer's GetAddr at most this often
+const GETADDR_ANSWER_INTERVAL: Duration = Duration::from_secs(20 * 60);
+
+/// Ask a peer for an orphan's missing parents at most this often
+const PARENT_REQUEST_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Version handshake progress
+///
+///   HelloSent ──hello──▶ HelloReceived ──ack──▶ Ready
+///       │                                        ▲
+///       └────ack────▶ AckReceived ────hello──────┘
+///
+/// Both sides send Hello on connect and HelloAck on
+/// accepting the other's Hello. Peers of one-way transports
+/// (satellite, BLE, ...) skip the handshake and stay Link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum HandshakeState {
+    /// Our Hello is out, nothing received yet
+    HelloSent,
+    /// Their Hello accepted, waiting for their ack
+    HelloReceived,
+    /// They acked ours, waiting for their Hello
+    AckReceived,
+    /// Handshake complete
+    Ready,
+    /// One-way link: delivers blocks and transactions only
+    Link,
+}
+
+/// Why a Hello or HelloAck ends the connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// Not a tracked connection
+    UnknownPeer,
+    /// Our own nonce came back: we dialled ourselves
+    ConnectedToSelf,
+    /// Below MIN_PROTOCOL_VERSION
+    ObsoleteVersion,
+    DuplicateHello,
+    DuplicateAck,
+}
+
+impl HandshakeError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            HandshakeError::UnknownPeer => "unknown peer",
+            HandshakeError::ConnectedToSelf => "connected to self",
+            HandshakeError::ObsoleteVersion => "obsolete protocol version",
+            HandshakeError::DuplicateHello => "duplicate hello",
+            HandshakeError::DuplicateAck => "duplicate ack",
+        }
+    }
+}
+
+/// Per-connection peer state
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerInfo {
+    pub addr: PeerId,
+    pub inbound: bool,
+    /// Transport encrypts and authenticates traffic
+    pub encrypted: bool,
+    pub state: HandshakeState,
+
+    /// From the peer's Hello
+    pub version: u32,
+    pub agent: String,
+    pub services: u64,
+
+    /// Chain length (blocks) the peer announced in Hello, or
+    /// implied by the highest block it sent
+    pub best_height: u64,
+
+    /// Accumulated misbehaviour score (BAN_THRESHOLD = ban)
+    pub misbehavior: u32,
+
+    #[serde(skip)]
+    pub connected_at: Instant,
+    #[serde(skip)]
+    pub last_seen: Instant,
+    #[serde(skip)]
+    pub last_ping: Option<Instant>,
+
+    /// Last GetAddr we answered for this peer
+    #[serde(skip)]
+    pub addr_sent: Option<Instant>,
+
+    /// Last time we asked it for an orphan's missing parents
+    #[serde(skip)]
+    pub parents_requested: Option<Instant>,
+
+    /// Height after the last SyncRequest page asked of a
+    /// peer without GetBlocks
+    #[serde(skip)]
+    pub sync_page_end: Option<u64>,
+}
+
+/// Connection bookkeeping (POLICY ONLY)
+///
+/// Pure state: the P2P layer feeds it connection events and
+/// handshake messages, and acts on what it reports
+/// (disconnect, ping, dial).
+pub struct PeerManager {
+    peers: HashMap<PeerId, PeerInfo>,
+
+    /// Our Hello nonce, to detect connecting to ourselves
+    local_nonce: u64,
+
+    target_outbound: usize,
+}
+
+impl PeerManager {
+    pub fn new(local_nonce: u64, target_outbound: usize) -> Self {
+        Self {
+            peers: HashMap::new(),
+            local_nonce,
+            target_outbound,
+        }
+    }
+
+    pub fn local_nonce(&self) -> u64 {
+        self.local_nonce
+    }
+
+    /// New connection; false = refuse it (inbound slots full
+    /// or already connected)
+    pub fn connected(&mut self, addr: PeerId, inbound: bool, encrypted: bool) -> bool {
+        if self.peers.contains_key(&addr) {
+            return false;
+        }
+
+        let handshakes = addr.kind().handshakes();
+        if handshakes && inbound && self.inbound_count() >= MAX_INBOUND {
+            return false;
+        }
+
+        let state = if handshakes {
+            HandshakeState::HelloSent
+        } else {
+            HandshakeState::Link
+        };
+
+        let now = Instant::now();
+        self.peers.insert(
+            addr.clone(),
+            PeerInfo {
+                addr,
+                inbound,
+                encrypted,
+                state,
+                version: 0,
+                agent: String::new(),
+                services: 0,
+                best_height: 0,
+                misbehavior: 0,
+                connected_at: now,
+                last_seen: now,
+                last_ping: None,
+                addr_sent: None,
+                parents_requested: None,
+                sync_page_end: None,
+            },
+        );
+
+        true
+    }
+
+    pub fn disconnected(&mut self, addr: &PeerId) {
+        self.peers.remove(addr);
+    }
+
+    /// Peer's Hello; Err = drop the connection
+    pub fn on_hello(
+        &mut self,
+        addr: &PeerId,
+        version: u32,
+        height: u64,
+        agent: String,
+        services: u64,
+        nonce: u64,
+    ) -> Result<(), HandshakeError> {
+        let local_nonce = self.local_nonce;
+        let peer = self.peers.get_mut(addr).ok_or(HandshakeError::UnknownPeer)?;
+
+        if nonce == local_nonce {
+            return Err(HandshakeError::ConnectedToSelf);
+        }
+
+        if version < MIN_PROTOCOL_VERSION {
+            return Err(HandshakeError::ObsoleteVersion);
+        }
+
+        peer.state = match peer.state {
+            HandshakeState::HelloSent => HandshakeState::HelloReceived,
+            HandshakeState::AckReceived => HandshakeState::Ready,
+            _ => return Err(HandshakeError::DuplicateHello),
+        };
+
+        peer.version = version;
+        peer.best_height = height;
+        peer.agent = agent;
+        peer.services = services;
+
+        Ok(())
+    }
+
+    /// Peer acknowledged our Hello; Err = drop the connection
+    pub fn on_ack(&mut self, addr: &PeerId) -> Result<(), HandshakeError> {
+        let peer = self.peers.get_mut(addr).ok_or(HandshakeError::UnknownPeer)?;
+
+        peer.state = match peer.state {
+            HandshakeState::HelloSent => HandshakeState::AckReceived,
+            HandshakeState::HelloReceived => HandshakeState::Ready,
+            _ => return Err(HandshakeError::DuplicateAck),
+        };
+
+        Ok(())
+    }
+
+    /// Address belongs to a tracked connection
+    pub fn is_connection(&self, addr: &PeerId) -> bool {
+        self.peers.contains_key(addr)
+    }
+
+    pub fn peer(&self, addr: &PeerId) -> Option<PeerInfo> {
+        self.peers.get(addr).cloned()
+    }
+
+    pub fn is_ready(&self, addr: &PeerId) -> bool {
+        self.peers
+            .get(addr)
+            .map(|p| p.state == HandshakeState::Ready)
+            .unwrap_or(false)
+    }
+
+    /// Any message from the peer
+    pub fn seen(&mut self, addr: &PeerId) {
+        if let Some(p) = self.peers.get_mut(addr) {
+            p.last_seen = Instant::now();
+            p.last_ping = None;
+        }
+    }
+
+    /// Add to a peer's misbehaviour score; true once it
+    /// reaches BAN_THRESHOLD
+    pub fn misbehaving(&mut self, addr: &PeerId, score: u32) -> bool {
+        match self.peers.get_mut(addr) {
+            Some(p) => {
+                p.misbehavior = p.misbehavior.saturating_add(score);
+                p.misbehavior >= BAN_THRESHOLD
+            }
+            None => false,
+        }
+    }
+
+    /// Connections from / to `ip`
+    pub fn addrs_with_ip(&self, ip: &IpAddr) -> Vec<PeerId> {
+        self.peers
+            .keys()
+            .filter(|a| a.ip() == Some(*ip))
+            .cloned()
+            .collect()
+    }
+
+    pub fn update_height(&mut self, addr: &PeerId, height: u64) {
+        if let Some(p) = self.peers.get_mut(addr) {
+            p.best_height = p.best_height.max(height);
+        }
+    }
+
+    pub fn ready_peers(&self) -> Vec<PeerId> {
+        self.peers
+            .values()
+            .filter(|p| p.state == HandshakeState::Ready)
+            .map(|p| p.addr.clone())
+            .collect()
+    }
+
+    /// Ready peers at `min_version` or newer, with their chain length
+    pub fn download_peers(&self, min_version: u32) -> Vec<(PeerId, u64)> {
+        self.peers
+            .values()
+            .filter(|p| p.state == HandshakeState::Ready && p.version >= min_version)
+            .map(|p| (p.addr.clone(), p.best_height))
+            .collect()
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers.values().cloned().collect()
+    }
+
+    /// Inbound connections (one-way links not counted)
+    pub fn inbound_count(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|p| p.inbound && p.state != HandshakeState::Link)
+            .count()
+    }
+
+    /// Outbound connections (one-way links not counted)
+    pub fn outbound_count(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|p| !p.inbound && p.state != HandshakeState::Link)
+            .count()
+    }
+
+    /// Peers to disconnect: handshake too slow, or silent
+    /// even after a ping. One-way links come and go with
+    /// their transport.
+    pub fn timed_out(&self) -> Vec<PeerId> {
+        self.peers
+            .values()
+            .filter(|p| p.state != HandshakeState::Link)
+            .filter(|p| {
+                (p.state != HandshakeState::Ready
+                    && p.connected_at.elapsed() > HANDSHAKE_TIMEOUT)
+                    || p.last_seen.elapsed() > INACTIVITY_TIMEOUT
+            })
+            .map(|p| p.addr.clone())
+            .collect()
+    }
+
+    /// Ready peers quiet for PING_INTERVAL (marked as pinged)
+    pub fn due_for_ping(&mut self) -> Vec<PeerId> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+
+        for p in self.peers.values_mut() {
+            if p.state == HandshakeState::Ready
+                && p.last_ping.is_none()
+                && p.last_seen.elapsed() > PING_INTERVAL
+            {
+                p.last_ping = Some(now);
+                due.push(p.addr.clone());
+            }
+        }
+
+        due
+    }
+
+    /// Outbound connections missing from the target
+    pub fn outbound_needed(&self) -> usize {
+        self.target_outbound.saturating_sub(self.outbound_count())
+    }
+
+    /// Whether to answer this GetAddr (records the answer)
+    ///
+    /// Rate-limited so a peer cannot scrape the whole address
+    /// book by asking repeatedly.
+    pub fn take_getaddr(&mut self, addr: &PeerId) -> bool {
+        match self.peers.get_mut(addr) {
+            Some(p)
+                if p.addr_sent
+                    .map(|t| t.elapsed() >= GETADDR_ANSWER_INTERVAL)
+                    .unwrap_or(true) =>
+            {
+                p.addr_sent = Some(Instant::now());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether to ask this peer for missing parents now
+    /// (records the request)
+    pub fn take_parent_request(&mut self, addr: &PeerId) -> bool {
+        match self.peers.get_mut(addr) {
+            Some(p)
+                if p.parents_requested
+                    .map(|t| t.elapsed() >= PARENT_REQUEST_INTERVAL)
+                    .unwrap_or(true) =>
+            {
+                p.parents_requested = Some(Instant::now());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Record a SyncRequest: the peer serves at most
+    /// MAX_GETBLOCKS blocks from `from_height`
+    pub fn sync_requested(&mut self, addr: &PeerId, from_height: u64) {
+        if let Some(p) = self.peers.get_mut(addr) {
+            p.sync_page_end = Some(from_height + MAX_GETBLOCKS);
+        }
+    }
+
+    /// Where to ask for the next SyncRequest page, once our
+    /// chain (`height` blocks) reached the end of the last one
+    /// and the peer has more
+    pub fn next_sync_page(&mut self, addr: &PeerId, height: u64) -> Option<u64> {
+        let p = self.peers.get_mut(addr)?;
+        if height < p.sync_page_end? {
+            return None;
+        }
+
+        if p.best_height > height {
+            p.sync_page_end = Some(height + MAX_GETBLOCKS);
+            Some(height)
+        } else {
+            p.sync_page_end = None;
+            None
+        }
+    }
+
+    /// Addresses of all TCP connections, as address book keys
+    pub fn connected_addrs(&self) -> Vec<String> {
+        self.peers
+            .keys()
+            .filter_map(|a| match a {
+                PeerId::Tcp(addr) => Some(addr.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::message::PROTOCOL_VERSION;
+
+    const OUR_NONCE: u64 = 7;
+
+    fn manager(peer: &PeerId) -> PeerManager {
+        let mut peers = PeerManager::new(OUR_NONCE, TARGET_OUTBOUND);
+        assert!(peers.connected(peer.clone(), false, false));
+        peers
+    }
+
+    fn hello(
+        peers: &mut PeerManager,
+        peer: &PeerId,
+        version: u32,
+        nonce: u64,
+    ) -> Result<(), HandshakeError> {
+        peers.on_hello(peer, version, 10, "test".into(), NODE_NETWORK, nonce)
+    }
+
+    fn state(peers: &PeerManager, peer: &PeerId) -> HandshakeState {
+        peers.peer(peer).unwrap().state
+    }
+
+    #[test]
+    fn handshake_completes_in_either_order() {
+        let peer = PeerId::Tcp("1.2.3.4:8333".parse().unwrap());
+
+        let mut peers = manager(&peer);
+        hello(&mut peers, &peer, PROTOCOL_VERSION, 1).unwrap();
+        assert_eq!(state(&peers, &peer), HandshakeState::HelloReceived);
+        peers.on_ack(&peer).unwrap();
+        assert!(peers.is_ready(&peer));
+
+        let mut peers = manager(&peer);
+        peers.on_ack(&peer).unwrap();
+        assert_eq!(state(&peers, &peer), HandshakeState::AckReceived);
+        hello(&mut peers, &peer, MIN_PROTOCOL_VERSION, 1).unwrap();
+        assert!(peers.is_ready(&peer));
+    }
+
+    #[test]
+    fn handshake_errors_are_typed() {
+        let peer = PeerId::Tcp("1.2.3.4:8333".parse().unwrap());
+        let other = PeerId::Tcp("5.6.7.8:8333".parse().unwrap());
+
+        let mut peers = manager(&peer);
+        assert_eq!(
+            hello(&mut peers, &peer, PROTOCOL_VERSION, OUR_NONCE),
+            Err(HandshakeError::ConnectedToSelf)
+        );
+        assert_eq!(
+            hello(&mut peers, &peer, MIN_PROTOCOL_VERSION - 1, 1),
+            Err(HandshakeError::ObsoleteVersion)
+        );
+        assert_eq!(
+            hello(&mut peers, &other, PROTOCOL_VERSION, 1),
+            Err(HandshakeError::UnknownPeer)
+        );
+        assert_eq!(peers.on_ack(&other), Err(HandshakeError::UnknownPeer));
+
+        hello(&mut peers, &peer, PROTOCOL_VERSION, 1).unwrap();
+        assert_eq!(
+            hello(&mut peers, &peer, PROTOCOL_VERSION, 1),
+            Err(HandshakeError::DuplicateHello)
+        );
+        peers.on_ack(&peer).unwrap();
+        assert_eq!(peers.on_ack(&peer), Err(HandshakeError::DuplicateAck));
+    }
+}
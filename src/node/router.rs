@@ -0,0 +1,679 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::peer_id::{PeerId, TransportKind};
+use crate::node::transport::{LinkStats, Transport};
+
+/// How often budgeted queues are drained
+const PUMP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A budget may be saved up for this long (burst size)
+const BURST_SECS: f64 = 2.0;
+
+/// Relays waiting on a budgeted transport; beyond this the
+/// oldest transaction (else the oldest block) is dropped
+pub const RELAY_QUEUE_LEN: usize = 256;
+
+/// What is being relayed; blocks go first when a budget is tight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayClass {
+    Block,
+    Transaction,
+}
+
+/// Relay settings of one transport (POLICY ONLY)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteConfig {
+    /// Transports are handed a relay in descending priority
+    ///
+    /// This only orders the sends of one relay. Each transport
+    /// has its own budget and queue, so a higher priority does
+    /// not take bandwidth from a lower one.
+    pub priority: u8,
+
+    /// Outbound bytes per second for relayed blocks and
+    /// transactions; 0 = unlimited
+    pub budget: u64,
+
+    /// Relay transactions too (blocks always are)
+    pub relay_transactions: bool,
+}
+
+impl Default for RouteConfig {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            budget: 0,
+            relay_transactions: true,
+        }
+    }
+}
+
+/// Traffic over one transport, as reported by `/transportstats`
+#[derive(Debug, Clone, Serialize)]
+pub struct TransportStats {
+    pub transport: TransportKind,
+    /// Sends and relays; false for receive-only transports
+    pub registered: bool,
+    pub priority: u8,
+    pub budget: u64,
+    pub peers: usize,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    pub relayed_blocks: u64,
+    pub relayed_transactions: u64,
+    /// Relays dropped from a full queue
+    pub dropped: u64,
+    /// Relays waiting for budget
+    pub queued: usize,
+    /// Counters of the link itself (satellite uplink)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<LinkStats>,
+}
+
+impl TransportStats {
+    fn new(transport: TransportKind, registered: bool, route: &RouteConfig) -> Self {
+        Self {
+            transport,
+            registered,
+            priority: route.priority,
+            budget: route.budget,
+            peers: 0,
+            messages_sent: 0,
+            bytes_sent: 0,
+            messages_received: 0,
+            bytes_received: 0,
+            relayed_blocks: 0,
+            relayed_transactions: 0,
+            dropped: 0,
+            queued: 0,
+            link: None,
+        }
+    }
+}
+
+/// Relay waiting for budget
+struct Queued {
+    class: RelayClass,
+    data: Arc<Vec<u8>>,
+    to: Vec<PeerId>,
+    /// One `broadcast` instead of a send per peer
+    flood: bool,
+}
+
+impl Queued {
+    /// Bytes sent over the transport
+    fn cost(&self) -> usize {
+        if self.flood {
+            self.data.len()
+        } else {
+            self.data.len() * self.to.len()
+        }
+    }
+
+    fn messages(&self) -> u64 {
+        if self.flood {
+            1
+        } else {
+            self.to.len() as u64
+        }
+    }
+}
+
+/// Budget, queue and counters of one route
+struct RouteState {
+    /// Bytes that may be sent now (negative after a message
+    /// larger than the burst: repaid before the next one)
+    tokens: f64,
+    last_refill: Instant,
+    queue: VecDeque<Queued>,
+    stats: TransportStats,
+}
+
+impl RouteState {
+    fn refill(&mut self, budget: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+
+        let burst = budget as f64 * BURST_SECS;
+        self.tokens = (self.tokens + elapsed * budget as f64).min(burst);
+    }
+
+    /// A relay fits once the bucket holds its cost, or is full
+    /// (so a block larger than the burst still goes out)
+    fn affords(&self, cost: usize, budget: u64) -> bool {
+        let burst = budget as f64 * BURST_SECS;
+        self.tokens >= (cost as f64).min(burst)
+    }
+
+    /// Next message to send: the oldest block, else the oldest
+    /// transaction
+    fn next(&self) -> Option<usize> {
+        self.queue
+            .iter()
+            .position(|q| q.class == RelayClass::Block)
+            .or_else(|| (!self.queue.is_empty()).then_some(0))
+    }
+
+    fn enqueue(&mut self, item: Queued) {
+        if self.queue.len() >= RELAY_QUEUE_LEN {
+            let victim = self
+                .queue
+                .iter()
+                .position(|q| q.class == RelayClass::Transaction)
+                .unwrap_or(0);
+            self.queue.remove(victim);
+            self.stats.dropped += 1;
+        }
+        self.queue.push_back(item);
+    }
+}
+
+/// What the pump thread needs of a budgeted route
+type Budgeted = (Arc<dyn Transport>, u64, Arc<Mutex<RouteState>>);
+
+struct Route {
+    transport: Arc<dyn Transport>,
+    config: RouteConfig,
+    state: Arc<Mutex<RouteState>>,
+}
+
+impl Route {
+    fn new(transport: Arc<dyn Transport>, config: RouteConfig) -> Self {
+        let stats = TransportStats::new(transport.kind(), true, &config);
+        Self {
+            transport,
+            config,
+            state: Arc::new(Mutex::new(RouteState {
+                tokens: 0.0,
+                last_refill: Instant::now(),
+                queue: VecDeque::new(),
+                stats,
+            })),
+        }
+    }
+}
+
+/// Sends over every registered transport
+///
+/// Direct messages go to the transport of the peer's kind.
+/// Relayed blocks and transactions go out over every transport
+/// with peers, highest priority first: to handshaked peers on
+/// two-way transports, to every known peer on one-way links
+/// (never back to where the data came from), or as one flood
+/// on shared media that reach every peer. Transports with a
+/// byte budget queue relays and drain them at that rate.
+///
+/// The first transport registered is the primary one: it
+/// dials peers and its listen address is announced.
+pub struct TransportRouter {
+    primary: Arc<dyn Transport>,
+
+    /// By descending priority
+    routes: Vec<Route>,
+
+    /// Traffic from transports that cannot send (satellite
+    /// downlink, offline imports)
+    receive_only: Mutex<HashMap<TransportKind, TransportStats>>,
+}
+
+impl TransportRouter {
+    pub fn new(primary: Arc<dyn Transport>, config: RouteConfig) -> Self {
+        Self {
+            primary: Arc::clone(&primary),
+            routes: vec![Route::new(primary, config)],
+            receive_only: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add a transport; one per kind (a second replaces the first)
+    pub fn register(&mut self, transport: Arc<dyn Transport>, config: RouteConfig) {
+        let kind = transport.kind();
+        if kind == self.primary.kind() {
+            self.primary = Arc::clone(&transport);
+        }
+        let route = Route::new(transport, config);
+
+        match self.routes.iter().position(|r| r.transport.kind() == kind) {
+            Some(i) => self.routes[i] = route,
+            None => self.routes.push(route),
+        }
+        self.sort();
+    }
+
+    /// Change the relay settings of a registered transport
+    pub fn set_route(&mut self, kind: TransportKind, config: RouteConfig) {
+        if let Some(route) = self.routes.iter_mut().find(|r| r.transport.kind() == kind) {
+            let mut state = route.state.lock().unwrap();
+            state.stats.priority = config.priority;
+            state.stats.budget = config.budget;
+            drop(state);
+            route.config = config;
+        }
+        self.sort();
+    }
+
+    /// Stable: equal priorities keep registration order
+    fn sort(&mut self) {
+        self.routes.sort_by_key(|r| std::cmp::Reverse(r.config.priority));
+    }
+
+    /// Transport that dials and listens
+    pub fn primary(&self) -> &Arc<dyn Transport> {
+        &self.primary
+    }
+
+    fn route(&self, kind: TransportKind) -> Option<&Route> {
+        self.routes.iter().find(|r| r.transport.kind() == kind)
+    }
+
+    /// Start draining budgeted queues
+    pub fn start(&self) {
+        let budgeted: Vec<Budgeted> = self
+            .routes
+            .iter()
+            .filter(|r| r.config.budget > 0)
+            .map(|r| (Arc::clone(&r.transport), r.config.budget, Arc::clone(&r.state)))
+            .collect();
+
+        if budgeted.is_empty() {
+            return;
+        }
+
+        thread::spawn(move || loop {
+            for (transport, budget, state) in &budgeted {
+                pump(transport.as_ref(), *budget, state);
+            }
+            thread::sleep(PUMP_INTERVAL);
+        });
+    }
+
+    /// Send to one peer over its transport (not budgeted)
+    pub fn send(&self, peer: &PeerId, data: &[u8]) {
+        if let Some(route) = self.route(peer.kind()) {
+            route.transport.send(peer, data);
+
+            let mut state = route.state.lock().unwrap();
+            state.stats.messages_sent += 1;
+            state.stats.bytes_sent += data.len() as u64;
+        }
+    }
+
+    /// Close a connection, over whichever transport has it
+    pub fn disconnect(&self, peer: &PeerId) {
+        if let Some(route) = self.route(peer.kind()) {
+            route.transport.disconnect(peer);
+        }
+    }
+
+    /// Count a frame delivered by any transport
+    pub fn record_received(&self, peer: &PeerId, bytes: usize) {
+        let kind = peer.kind();
+
+        if let Some(route) = self.route(kind) {
+            let mut state = route.state.lock().unwrap();
+            state.stats.messages_received += 1;
+            state.stats.bytes_received += bytes as u64;
+            return;
+        }
+
+        let mut receive_only = self.receive_only.lock().unwrap();
+        let stats = receive_only
+            .entry(kind)
+            .or_insert_with(|| TransportStats::new(kind, false, &RouteConfig::default()));
+        stats.messages_received += 1;
+        stats.bytes_received += bytes as u64;
+    }
+
+    /// Relay an accepted block or transaction
+    ///
+    /// `ready`: peers that completed the handshake (the only
+    /// ones relayed to on two-way transports). `origin` is
+    /// skipped.
+    pub fn relay(
+        &self,
+        class: RelayClass,
+        data: &[u8],
+        ready: &[PeerId],
+        origin: Option<&PeerId>,
+    ) {
+        let data = Arc::new(data.to_vec());
+
+        for route in &self.routes {
+            if class == RelayClass::Transaction && !route.config.relay_transactions {
+                continue;
+            }
+
+            let kind = route.transport.kind();
+            let candidates = if kind.handshakes() {
+                ready.iter().filter(|p| p.kind() == kind).cloned().collect()
+            } else {
+                route.transport.peers()
+            };
+
+            let to: Vec<PeerId> = candidates
+                .into_iter()
+                .filter(|p| Some(p) != origin)
+                .collect();
+
+            if to.is_empty() {
+                continue;
+            }
+
+            let item = Queued {
+                class,
+                data: Arc::clone(&data),
+                to,
+                flood: route.transport.floods(),
+            };
+
+            if route.config.budget == 0 {
+                let mut state = route.state.lock().unwrap();
+                deliver(route.transport.as_ref(), &mut state, &item);
+            } else {
+                route.state.lock().unwrap().enqueue(item);
+            }
+        }
+    }
+
+    /// Every registered transport, then receive-only ones that
+    /// delivered anything (`peers` is left for the caller)
+    pub fn stats(&self) -> Vec<TransportStats> {
+        let mut all: Vec<TransportStats> = self
+            .routes
+            .iter()
+            .map(|r| {
+                let state = r.state.lock().unwrap();
+                let mut stats = state.stats.clone();
+                stats.queued = state.queue.len();
+                stats.link = r.transport.link_stats();
+                stats
+            })
+            .collect();
+
+        all.extend(self.receive_only.lock().unwrap().values().cloned());
+        all
+    }
+}
+
+/// Send queued relays the budget allows, blocks first
+fn pump(transport: &dyn Transport, budget: u64, state: &Mutex<RouteState>) {
+    let mut state = state.lock().unwrap();
+    state.refill(budget);
+
+    while let Some(i) = state.next() {
+        if !state.affords(state.queue[i].cost(), budget) {
+            break;
+        }
+
+        let item = state.queue.remove(i).unwrap();
+        state.tokens -= item.cost() as f64;
+        deliver(transport, &mut state, &item);
+    }
+}
+
+fn deliver(transport: &dyn Transport, state: &mut RouteState, item: &Queued) {
+    if item.flood {
+        transport.broadcast(&item.data);
+    } else {
+        for peer in &item.to {
+            transport.send(peer, &item.data);
+        }
+    }
+
+    let stats = &mut state.stats;
+    stats.messages_sent += item.messages();
+    stats.bytes_sent += item.cost() as u64;
+    match item.class {
+        RelayClass::Block => stats.relayed_blocks += 1,
+        RelayClass::Transaction => stats.relayed_transactions += 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records what it is asked to send (None = broadcast)
+    struct MockTransport {
+        kind: TransportKind,
+        peers: Vec<PeerId>,
+        floods: bool,
+        sent: Mutex<Vec<(Option<PeerId>, Vec<u8>)>>,
+    }
+
+    impl MockTransport {
+        fn new(kind: TransportKind, peers: Vec<PeerId>, floods: bool) -> Arc<Self> {
+            Arc::new(Self {
+                kind,
+                peers,
+                floods,
+                sent: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn sent(&self) -> Vec<(Option<PeerId>, Vec<u8>)> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn kind(&self) -> TransportKind {
+            self.kind
+        }
+
+        fn send(&self, peer: &PeerId, data: &[u8]) {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((Some(peer.clone()), data.to_vec()));
+        }
+
+        fn broadcast(&self, data: &[u8]) {
+            self.sent.lock().unwrap().push((None, data.to_vec()));
+        }
+
+        fn peers(&self) -> Vec<PeerId> {
+            self.peers.clone()
+        }
+
+        fn floods(&self) -> bool {
+            self.floods
+        }
+    }
+
+    fn tcp(n: u8) -> PeerId {
+        PeerId::Tcp(format!("10.0.0.{}:8333", n).parse().unwrap())
+    }
+
+    fn geo(n: u8) -> PeerId {
+        PeerId::Geo(format!("192.168.1.{}:8334", n).parse().unwrap())
+    }
+
+    fn state(tokens: f64) -> RouteState {
+        let route = RouteConfig::default();
+        RouteState {
+            tokens,
+            last_refill: Instant::now(),
+            queue: VecDeque::new(),
+            stats: TransportStats::new(TransportKind::Tcp, true, &route),
+        }
+    }
+
+    fn queued(class: RelayClass, data: &[u8]) -> Queued {
+        Queued {
+            class,
+            data: Arc::new(data.to_vec()),
+            to: vec![tcp(1)],
+            flood: false,
+        }
+    }
+
+    #[test]
+    fn bucket_refills_up_to_the_burst_and_repays_debt() {
+        let mut s = state(0.0);
+
+        s.last_refill = Instant::now() - Duration::from_secs(1);
+        s.refill(1000);
+        assert!(s.tokens >= 1000.0 && s.tokens < 1100.0);
+        assert!(s.affords(1000, 1000));
+        assert!(!s.affords(1500, 1000));
+
+        s.last_refill = Instant::now() - Duration::from_secs(60);
+        s.refill(1000);
+        assert_eq!(s.tokens, 1000.0 * BURST_SECS);
+
+        // A full bucket affords more than the burst...
+        assert!(s.affords(10_000, 1000));
+
+        // ...after which the debt is repaid before anything else
+        s.tokens -= 10_000.0;
+        s.last_refill = Instant::now() - Duration::from_secs(3);
+        s.refill(1000);
+        assert!(s.tokens < 0.0);
+        assert!(!s.affords(1, 1000));
+    }
+
+    #[test]
+    fn blocks_leave_the_queue_first() {
+        let mut s = state(0.0);
+        assert_eq!(s.next(), None);
+
+        s.queue.push_back(queued(RelayClass::Transaction, b"tx1"));
+        assert_eq!(s.next(), Some(0));
+
+        s.queue.push_back(queued(RelayClass::Transaction, b"tx2"));
+        s.queue.push_back(queued(RelayClass::Block, b"block1"));
+        s.queue.push_back(queued(RelayClass::Block, b"block2"));
+        assert_eq!(s.next(), Some(2));
+    }
+
+    #[test]
+    fn full_queue_drops_transactions_first() {
+        let mut s = state(0.0);
+        s.enqueue(queued(RelayClass::Block, b"block"));
+        for _ in 1..RELAY_QUEUE_LEN {
+            s.enqueue(queued(RelayClass::Transaction, b"tx"));
+        }
+
+        s.enqueue(queued(RelayClass::Block, b"new block"));
+        assert_eq!(s.queue.len(), RELAY_QUEUE_LEN);
+        assert_eq!(s.stats.dropped, 1);
+        assert_eq!(
+            s.queue
+                .iter()
+                .filter(|q| q.class == RelayClass::Block)
+                .count(),
+            2
+        );
+
+        // Only blocks left: the oldest goes
+        let mut s = state(0.0);
+        for n in 0..RELAY_QUEUE_LEN {
+            s.enqueue(queued(RelayClass::Block, &[n as u8]));
+        }
+        s.enqueue(queued(RelayClass::Block, b"last"));
+        assert_eq!(*s.queue[0].data, vec![1]);
+        assert_eq!(*s.queue.back().unwrap().data, b"last".to_vec());
+    }
+
+    #[test]
+    fn pump_sends_blocks_first_within_the_budget() {
+        let transport = MockTransport::new(TransportKind::Tcp, vec![], false);
+        let s = Mutex::new(state(100.0));
+        {
+            let mut s = s.lock().unwrap();
+            s.queue.push_back(queued(RelayClass::Transaction, &[0; 60]));
+            s.queue.push_back(queued(RelayClass::Block, &[1; 80]));
+        }
+
+        pump(transport.as_ref(), 50, &s);
+
+        // The block went, the transaction waits for the budget
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1, vec![1; 80]);
+
+        let s = s.lock().unwrap();
+        assert_eq!(s.queue.len(), 1);
+        assert_eq!(s.stats.relayed_blocks, 1);
+        assert!(s.tokens < 60.0);
+    }
+
+    #[test]
+    fn relay_skips_the_origin_on_every_transport() {
+        let tcp_link = MockTransport::new(TransportKind::Tcp, vec![], false);
+        let mesh = MockTransport::new(TransportKind::Geo, vec![geo(1), geo(2)], false);
+        let flood = MockTransport::new(TransportKind::Satellite, vec![], true);
+
+        let mut router = TransportRouter::new(tcp_link.clone(), RouteConfig::default());
+        router.register(mesh.clone(), RouteConfig::default());
+        router.register(
+            flood.clone(),
+            RouteConfig {
+                relay_transactions: false,
+                ..RouteConfig::default()
+            },
+        );
+
+        router.relay(
+            RelayClass::Block,
+            b"block",
+            &[tcp(1), tcp(2)],
+            Some(&tcp(1)),
+        );
+        assert_eq!(tcp_link.sent(), vec![(Some(tcp(2)), b"block".to_vec())]);
+        assert_eq!(
+            mesh.sent(),
+            vec![
+                (Some(geo(1)), b"block".to_vec()),
+                (Some(geo(2)), b"block".to_vec())
+            ]
+        );
+        // No peers on the flooding link: nothing to broadcast
+        assert!(flood.sent().is_empty());
+
+        router.relay(RelayClass::Transaction, b"tx", &[tcp(1)], Some(&geo(2)));
+        assert_eq!(mesh.sent().last().unwrap(), &(Some(geo(1)), b"tx".to_vec()));
+        assert_eq!(mesh.sent().len(), 3);
+        assert_eq!(
+            tcp_link.sent().last().unwrap(),
+            &(Some(tcp(1)), b"tx".to_vec())
+        );
+    }
+
+    #[test]
+    fn flooding_transport_broadcasts_once() {
+        let primary = MockTransport::new(TransportKind::Tcp, vec![], false);
+        let flood = MockTransport::new(TransportKind::Geo, vec![geo(1), geo(2)], true);
+
+        let mut router = TransportRouter::new(primary, RouteConfig::default());
+        router.register(
+            flood.clone(),
+            RouteConfig {
+                relay_transactions: false,
+                ..RouteConfig::default()
+            },
+        );
+
+        router.relay(RelayClass::Block, b"block", &[], None);
+        router.relay(RelayClass::Transaction, b"tx", &[], None);
+        assert_eq!(flood.sent(), vec![(None, b"block".to_vec())]);
+
+        let stats = router.stats();
+        let geo_stats = stats
+            .iter()
+            .find(|s| s.transport == TransportKind::Geo)
+            .unwrap();
+        assert_eq!(geo_stats.relayed_blocks, 1);
+        assert_eq!(geo_stats.relayed_transactions, 0);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+/// Port used when a seed entry does not name one
+pub const DEFAULT_PORT: u16 = 8333;
+
+/// Name resolution for seed entries
+///
+/// A DNS seed is a hostname with many A / AAAA records; every
+/// record is a candidate peer.
+pub trait Resolver: Send + Sync {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// Operating system resolver (getaddrinfo)
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
+}
+
+/// Fixed host table, for tests and offline setups
+///
+/// Stands in for DNS: answers only the names it was given.
+#[derive(Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add records for `host` (like A / AAAA records)
+    pub fn insert(&mut self, host: &str, ips: Vec<IpAddr>) {
+        self.hosts
+            .entry(host.to_ascii_lowercase())
+            .or_default()
+            .extend(ips);
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        self.hosts
+            .get(&host.to_ascii_lowercase())
+            .map(|ips| ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown host"))
+    }
+}
+
+/// Split a seed entry into (host, port)
+///
+/// Accepts "host", "host:port", "1.2.3.4", "1.2.3.4:port",
+/// "[::1]" and "[::1]:port".
+pub fn parse_seed(entry: &str, default_port: u16) -> Result<(String, u16), &'static str> {
+    let entry = entry.trim();
+    if entry.is_empty() {
+        return Err("empty seed");
+    }
+
+    // Bracketed IPv6, optionally with port
+    if let Some(rest) = entry.strip_prefix('[') {
+        let (host, tail) = rest.split_once(']').ok_or("unclosed bracket")?;
+        let port = match tail.strip_prefix(':') {
+            Some(p) => p.parse().map_err(|_| "invalid port")?,
+            None if tail.is_empty() => default_port,
+            None => return Err("invalid seed"),
+        };
+        return Ok((host.to_string(), port));
+    }
+
+    // Bare IPv6 (several colons, no port)
+    if entry.parse::<IpAddr>().is_ok() {
+        return Ok((entry.to_string(), default_port));
+    }
+
+    match entry.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port.parse().map_err(|_| "invalid port")?;
+            Ok((host.to_string(), port))
+        }
+        None => Ok((entry.to_string(), default_port)),
+    }
+}
+
+/// Address obtained from a seed entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeedAddr {
+    pub addr: SocketAddr,
+
+    /// Written as an IP literal by the operator (may be a
+    /// private address); false for DNS answers
+    pub literal: bool,
+}
+
+/// Resolve seed entries to peer addresses
+///
+/// IP literals need no lookup; hostnames go to `resolver` and
+/// contribute every record returned. Entries that fail are
+/// reported and skipped. Duplicates are removed.
+pub fn resolve_seeds(
+    entries: &[String],
+    resolver: &dyn Resolver,
+    default_port: u16,
+) -> Vec<SeedAddr> {
+    let mut out: Vec<SeedAddr> = Vec::new();
+
+    for entry in entries {
+        let (host, port) = match parse_seed(entry, default_port) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("⚠️ Seed '{}' ignored: {}", entry, e);
+                continue;
+            }
+        };
+
+        let literal = host.parse::<IpAddr>().ok();
+        let addrs = match literal {
+            Some(ip) => vec![SocketAddr::new(ip, port)],
+            None => match resolver.resolve(&host, port) {
+                Ok(a) => a,
+                Err(e) => {
+                    eprintln!("⚠️ Seed '{}' did not resolve: {}", entry, e);
+                    continue;
+                }
+            },
+        };
+
+        for addr in addrs {
+            match out.iter_mut().find(|s| s.addr == addr) {
+                Some(seen) => seen.literal |= literal.is_some(),
+                None => out.push(SeedAddr {
+                    addr,
+                    literal: literal.is_some(),
+                }),
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SeedConfig;
+    use crate::node::addrman::AddrMan;
+    use crate::node::peer_manager::NODE_NETWORK;
+
+    fn entries(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn addrs(seeds: &[SeedAddr]) -> Vec<String> {
+        seeds.iter().map(|s| s.addr.to_string()).collect()
+    }
+
+    /// What the node does with the result (see P2PNetwork::bootstrap)
+    fn fill(addrman: &mut AddrMan, seeds: &[SeedAddr]) {
+        for s in seeds {
+            if s.literal {
+                addrman.add_local(&s.addr.to_string(), NODE_NETWORK);
+            } else {
+                addrman.add_seen(&s.addr.to_string(), NODE_NETWORK);
+            }
+        }
+    }
+
+    fn resolver() -> StaticResolver {
+        let mut r = StaticResolver::new();
+        r.insert(
+            "seed.example.org",
+            vec!["1.2.3.4".parse().unwrap(), "5.6.7.8".parse().unwrap()],
+        );
+        r.insert("Seed.Example.org", vec!["2001:db8::1".parse().unwrap()]);
+        r.insert("lan.example.org", vec!["192.168.1.5".parse().unwrap()]);
+        r
+    }
+
+    #[test]
+    fn parses_entries() {
+        assert_eq!(
+            parse_seed("seed.example.org", 8333),
+            Ok(("seed.example.org".into(), 8333))
+        );
+        assert_eq!(parse_seed(" host:9000 ", 8333), Ok(("host".into(), 9000)));
+        assert_eq!(
+            parse_seed("1.2.3.4:18333", 8333),
+            Ok(("1.2.3.4".into(), 18333))
+        );
+        assert_eq!(
+            parse_seed("2001:db8::1", 8333),
+            Ok(("2001:db8::1".into(), 8333))
+        );
+        assert_eq!(
+            parse_seed("[2001:db8::1]:9000", 8333),
+            Ok(("2001:db8::1".into(), 9000))
+        );
+        assert_eq!(parse_seed("[::1]", 8333), Ok(("::1".into(), 8333)));
+
+        assert!(parse_seed("", 8333).is_err());
+        assert!(parse_seed("host:port", 8333).is_err());
+        assert!(parse_seed("[::1", 8333).is_err());
+        assert!(parse_seed("[::1]x", 8333).is_err());
+    }
+
+    #[test]
+    fn hostnames_expand_to_every_record() {
+        let seeds = resolve_seeds(
+            &entries(&["seed.example.org:9000"]),
+            &resolver(),
+            DEFAULT_PORT,
+        );
+
+        // A and AAAA records, names case-insensitive
+        assert_eq!(
+            addrs(&seeds),
+            ["1.2.3.4:9000", "5.6.7.8:9000", "[2001:db8::1]:9000"]
+        );
+        assert!(seeds.iter().all(|s| !s.literal));
+    }
+
+    #[test]
+    fn literals_need_no_lookup_and_duplicates_merge() {
+        let seeds = resolve_seeds(
+            &entries(&[
+                "seed.example.org",
+                "unknown.example.org",
+                "bad:port",
+                "1.2.3.4",
+                "10.0.0.7:9000",
+            ]),
+            &resolver(),
+            DEFAULT_PORT,
+        );
+
+        assert_eq!(
+            addrs(&seeds),
+            [
+                "1.2.3.4:8333",
+                "5.6.7.8:8333",
+                "[2001:db8::1]:8333",
+                "10.0.0.7:9000"
+            ]
+        );
+        // Also named as a literal: the operator's own
+        assert!(seeds[0].literal && !seeds[1].literal && seeds[3].literal);
+    }
+
+    #[test]
+    fn seed_list_follows_the_network() {
+        let mut config = SeedConfig::default();
+        config.seeds.insert(
+            "test".into(),
+            entries(&["lan.example.org:18333", "10.0.0.7"]),
+        );
+
+        config.network = "test".into();
+        let seeds = resolve_seeds(&config.active_seeds(), &resolver(), DEFAULT_PORT);
+        assert_eq!(addrs(&seeds), ["192.168.1.5:18333", "10.0.0.7:8333"]);
+
+        // Unknown network: nothing to resolve
+        config.network = "other".into();
+        assert!(config.active_seeds().is_empty());
+    }
+
+    #[test]
+    fn address_book_remains_when_seeds_give_nothing() {
+        let mut addrman = AddrMan::new();
+        addrman.add_seen("9.9.9.9:8333", NODE_NETWORK);
+
+        // Unresolvable names and private DNS answers add nothing
+        let seeds = resolve_seeds(
+            &entries(&["unknown.example.org", "lan.example.org"]),
+            &resolver(),
+            DEFAULT_PORT,
+        );
+        fill(&mut addrman, &seeds);
+        assert_eq!(addrman.len(), 1);
+        assert_eq!(addrman.select(&[], false).as_deref(), Some("9.9.9.9:8333"));
+
+        // Private literals are taken, resolved names as gossip
+        let seeds = resolve_seeds(
+            &entries(&["10.0.0.7", "seed.example.org"]),
+            &resolver(),
+            DEFAULT_PORT,
+        );
+        fill(&mut addrman, &seeds);
+        assert_eq!(addrman.len(), 5);
+    }
+}
@@ -0,0 +1,1095 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use num_bigint::BigUint;
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+
+use crate::block::{Block, BlockHeader};
+use crate::chain::Blockchain;
+use crate::consensus::params::{MAX_FUTURE_DRIFT, MAX_TARGET};
+use crate::consensus::serialize::serialize_transaction;
+use crate::crypto::sha256;
+use crate::node::mempool::Mempool;
+use crate::node::miner::{build_template, BlockTemplate, CoinbaseSpec};
+use crate::node::p2p::P2PNetwork;
+use crate::pow::valid_pow;
+
+/// Bytes of per-connection extranonce assigned by the server
+pub const EXTRANONCE1_SIZE: usize = 4;
+
+/// Bytes of extranonce the worker rolls itself
+pub const EXTRANONCE2_SIZE: usize = 4;
+
+/// Jobs kept for late submissions of the current tip
+const MAX_RECENT_JOBS: usize = 8;
+
+/// New job for mempool changes at most this often
+const JOB_REFRESH: Duration = Duration::from_secs(30);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Messages queued per worker; a worker that lets its queue
+/// fill up is not reading and is dropped instead of waited for
+const WORKER_QUEUE_LEN: usize = 64;
+
+/// A single write taking longer than this drops the worker
+const WRITE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Worker connections served at once unless configured
+pub const DEFAULT_MAX_WORKERS: usize = 32;
+
+// Stratum error codes
+const ERR_OTHER: i64 = 20;
+const ERR_JOB_NOT_FOUND: i64 = 21;
+const ERR_DUPLICATE: i64 = 22;
+const ERR_LOW_DIFFICULTY: i64 = 23;
+const ERR_UNAUTHORIZED: i64 = 24;
+const ERR_NOT_SUBSCRIBED: i64 = 25;
+
+/// Work unit as sent in `mining.notify`
+///
+/// params: [job_id, prev_hash, coinb1, coinb2, merkle_branch,
+///          height, target, ntime, clean_jobs]
+///
+/// Stratum v1 sends version and nbits where this sends height
+/// and the full 32-byte target: the fields of our header.
+/// All byte fields are hex; ntime is a big-endian i64 in hex.
+/// Coinbase = coinb1 || extranonce1 || extranonce2 || coinb2,
+/// its txid is folded with merkle_branch into the merkle root.
+#[derive(Clone, Debug)]
+pub struct StratumJob {
+    pub job_id: String,
+    pub prev_hash: Vec<u8>,
+    pub coinb1: Vec<u8>,
+    pub coinb2: Vec<u8>,
+    pub merkle_branch: Vec<Vec<u8>>,
+    pub height: u64,
+    pub target: [u8; 32],
+    pub ntime: i64,
+    pub clean_jobs: bool,
+}
+
+impl StratumJob {
+    pub fn to_params(&self) -> Value {
+        json!([
+            self.job_id,
+            hex::encode(&self.prev_hash),
+            hex::encode(&self.coinb1),
+            hex::encode(&self.coinb2),
+            self.merkle_branch.iter().map(hex::encode).collect::<Vec<_>>(),
+            self.height,
+            hex::encode(self.target),
+            hex::encode(self.ntime.to_be_bytes()),
+            self.clean_jobs,
+        ])
+    }
+
+    pub fn from_params(params: &Value) -> Option<Self> {
+        let p = params.as_array()?;
+        if p.len() != 9 {
+            return None;
+        }
+
+        let target: [u8; 32] =
+            hex::decode(p[6].as_str()?).ok()?.try_into().ok()?;
+
+        Some(Self {
+            job_id: p[0].as_str()?.to_string(),
+            prev_hash: hex::decode(p[1].as_str()?).ok()?,
+            coinb1: hex::decode(p[2].as_str()?).ok()?,
+            coinb2: hex::decode(p[3].as_str()?).ok()?,
+            merkle_branch: p[4]
+                .as_array()?
+                .iter()
+                .map(|h| h.as_str().and_then(|s| hex::decode(s).ok()))
+                .collect::<Option<Vec<_>>>()?,
+            height: p[5].as_u64()?,
+            target,
+            ntime: i64::from_be_bytes(
+                hex::decode(p[7].as_str()?).ok()?.try_into().ok()?,
+            ),
+            clean_jobs: p[8].as_bool()?,
+        })
+    }
+
+    /// Merkle root for a coinbase carrying extranonce1 || extranonce2
+    pub fn merkle_root(&self, extranonce1: &[u8], extranonce2: &[u8]) -> Vec<u8> {
+        let coinbase =
+            [&self.coinb1[..], extranonce1, extranonce2, &self.coinb2[..]].concat();
+
+        let mut root = sha256(&coinbase);
+        for sibling in &self.merkle_branch {
+            root = sha256(&[root, sibling.clone()].concat());
+        }
+        root
+    }
+
+    /// Block header hash for a candidate solution
+    pub fn header_hash(
+        &self,
+        extranonce1: &[u8],
+        extranonce2: &[u8],
+        ntime: i64,
+        nonce: u64,
+    ) -> Vec<u8> {
+        Block {
+            header: BlockHeader {
+                height: self.height,
+                timestamp: ntime,
+                prev_hash: self.prev_hash.clone(),
+                nonce,
+                target: self.target,
+                merkle_root: self.merkle_root(extranonce1, extranonce2),
+            },
+            transactions: vec![],
+            hash: vec![],
+        }
+        .hash_header()
+    }
+}
+
+/// Share difficulty 1 = MAX_TARGET; difficulty d = MAX_TARGET / d
+pub fn share_target(difficulty: u64) -> [u8; 32] {
+    let max = BigUint::from_bytes_be(&MAX_TARGET);
+    let target = max / BigUint::from(difficulty.max(1));
+
+    let bytes = target.to_bytes_be();
+    let mut out = [0u8; 32];
+    out[32 - bytes.len()..].copy_from_slice(&bytes);
+    out
+}
+
+/// Merkle branch for the coinbase (leaf 0) over the given txids
+///
+/// Mirrors `merkle_root`: odd levels duplicate their last hash.
+fn coinbase_merkle_branch(txids: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut branch = Vec::new();
+
+    // Index 0 is the (unknown) coinbase
+    let mut level = vec![vec![]];
+    level.extend(txids);
+
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level.last().unwrap().clone());
+        }
+
+        branch.push(level[1].clone());
+
+        let mut next = vec![vec![]];
+        next.extend(
+            level[2..]
+                .chunks(2)
+                .map(|pair| sha256(&[pair[0].clone(), pair[1].clone()].concat())),
+        );
+        level = next;
+    }
+
+    branch
+}
+
+/// Extranonce parts → BlockTemplate extra-nonce
+///
+/// The template stores extra data as the little-endian bytes of
+/// a u64, so extranonce1 || extranonce2 maps onto it directly.
+fn combined_extranonce(extranonce1: &[u8], extranonce2: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes[..EXTRANONCE1_SIZE].copy_from_slice(extranonce1);
+    bytes[EXTRANONCE1_SIZE..].copy_from_slice(extranonce2);
+    u64::from_le_bytes(bytes)
+}
+
+struct Job {
+    stratum: StratumJob,
+    template: BlockTemplate,
+    mempool_revision: u64,
+    created: Instant,
+}
+
+/// Stratum server settings
+pub struct StratumOptions {
+    /// Share difficulty (1 = MAX_TARGET)
+    pub share_difficulty: u64,
+
+    /// Worker connections served at once; more are refused
+    pub max_workers: usize,
+
+    /// Password every `mining.authorize` must carry (None: any
+    /// worker name and password are accepted)
+    pub password: Option<String>,
+}
+
+impl Default for StratumOptions {
+    fn default() -> Self {
+        Self {
+            share_difficulty: 1,
+            max_workers: DEFAULT_MAX_WORKERS,
+            password: None,
+        }
+    }
+}
+
+struct Session {
+    /// Messages for the worker's writer thread
+    outbox: SyncSender<Value>,
+    /// Shut down to drop the worker
+    stream: TcpStream,
+    extranonce1: Vec<u8>,
+    subscribed: bool,
+    /// Worker names authorized on this connection
+    authorized: HashSet<String>,
+    /// Shares seen, per job still in `recent`
+    submitted: HashMap<String, HashSet<(Vec<u8>, i64, u64)>>,
+}
+
+impl Session {
+    /// Queue a message; a worker that is not keeping up is
+    /// dropped (its reader and writer both end)
+    fn send(&self, msg: Value) {
+        if let Err(TrySendError::Full(_)) = self.outbox.try_send(msg) {
+            println!("🐌 Stratum worker not reading, dropping");
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+struct Shared {
+    chain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    p2p: Arc<P2PNetwork>,
+    coinbase: Mutex<CoinbaseSpec>,
+    difficulty: u64,
+    share_target: [u8; 32],
+    password: Option<String>,
+
+    /// Connections being served, and the most allowed
+    workers: AtomicUsize,
+    max_workers: usize,
+
+    current: Mutex<Option<Arc<Job>>>,
+    recent: Mutex<HashMap<String, Arc<Job>>>,
+    sessions: Mutex<HashMap<u64, Arc<Mutex<Session>>>>,
+
+    next_job_id: AtomicU64,
+    next_session_id: AtomicU64,
+    next_extranonce1: AtomicU32,
+
+    /// Blocks accepted from workers
+    blocks_found: AtomicU64,
+}
+
+/// Stratum-style pool mining server (POLICY ONLY)
+///
+/// Hands out jobs built from the node's block template, each
+/// connection getting its own extranonce1 in the coinbase.
+/// Shares are checked against the configured share difficulty;
+/// full solutions go through the normal block submission path.
+///
+/// Line-delimited JSON-RPC with the Stratum v1 method names
+/// and message layout:
+/// - mining.subscribe  → [[["mining.notify", id]], extranonce1, extranonce2_size]
+/// - mining.authorize  [worker, password] → true / false
+/// - mining.submit     [worker, job_id, extranonce2, ntime, nonce] → true
+/// - mining.set_difficulty, mining.notify (see `StratumJob`)
+///
+/// Our block header is not Bitcoin's 80 bytes (height, 32-byte
+/// target, u64 nonce; see `BlockHeader`), so off-the-shelf
+/// Stratum miners cannot hash it. Where the fields differ:
+/// - notify carries height and target instead of version and nbits
+/// - ntime and nonce are 8 bytes (big-endian hex), not 4
+/// - the merkle tree hashes with single SHA256
+/// - difficulty 1 is MAX_TARGET, not the Bitcoin difficulty-1 target
+///
+/// Submits are accepted for worker names authorized on the
+/// same connection.
+pub struct StratumServer {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+}
+
+impl StratumServer {
+    pub fn start(
+        bind: &str,
+        chain: Arc<Mutex<Blockchain>>,
+        mempool: Arc<Mutex<Mempool>>,
+        p2p: Arc<P2PNetwork>,
+        coinbase: CoinbaseSpec,
+        options: StratumOptions,
+    ) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(bind)?;
+        let local_addr = listener.local_addr()?;
+
+        let shared = Arc::new(Shared {
+            chain,
+            mempool,
+            p2p,
+            coinbase: Mutex::new(coinbase),
+            difficulty: options.share_difficulty.max(1),
+            share_target: share_target(options.share_difficulty),
+            password: options.password,
+            workers: AtomicUsize::new(0),
+            max_workers: options.max_workers,
+            current: Mutex::new(None),
+            recent: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+            next_job_id: AtomicU64::new(1),
+            next_session_id: AtomicU64::new(1),
+            next_extranonce1: AtomicU32::new(1),
+            blocks_found: AtomicU64::new(0),
+        });
+
+        shared.refresh_job(true);
+
+        println!("⛏ Stratum server listening on {}", local_addr);
+
+        // ───────── Accept thread ─────────
+        let accept_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+
+                // One reader and one writer thread per worker
+                if accept_shared.workers.load(Ordering::Relaxed) >= accept_shared.max_workers {
+                    println!("🚫 Stratum worker limit reached, refusing connection");
+                    let _ = stream.shutdown(Shutdown::Both);
+                    continue;
+                }
+                accept_shared.workers.fetch_add(1, Ordering::Relaxed);
+
+                let shared = Arc::clone(&accept_shared);
+                thread::spawn(move || {
+                    Arc::clone(&shared).serve(stream);
+                    shared.workers.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        });
+
+        // ───────── Job refresh thread ─────────
+        let refresh_shared = Arc::clone(&shared);
+        thread::spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            refresh_shared.poll_for_new_work();
+        });
+
+        Ok(Self { shared, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.shared.sessions.lock().unwrap().len()
+    }
+
+    /// Blocks found by workers so far
+    pub fn blocks_found(&self) -> u64 {
+        self.shared.blocks_found.load(Ordering::Relaxed)
+    }
+
+    /// Change coinbase payouts / tag and send workers a job
+    /// using it (earlier jobs stay valid)
+    pub fn set_coinbase(&self, coinbase: CoinbaseSpec) {
+        *self.shared.coinbase.lock().unwrap() = coinbase;
+        self.shared.refresh_job(false);
+    }
+}
+
+impl Shared {
+    fn poll_for_new_work(&self) {
+        let current = match self.current.lock().unwrap().clone() {
+            Some(j) => j,
+            None => return self.refresh_job(true),
+        };
+
+        let tip_changed = {
+            let c = self.chain.lock().unwrap();
+            c.blocks
+                .last()
+                .map(|b| b.hash != current.stratum.prev_hash)
+                .unwrap_or(false)
+        };
+
+        if tip_changed {
+            self.refresh_job(true);
+            return;
+        }
+
+        let revision = self.mempool.lock().unwrap().revision();
+        if revision != current.mempool_revision
+            && current.created.elapsed() >= JOB_REFRESH
+        {
+            self.refresh_job(false);
+        }
+    }
+
+    /// Build a job from the current template and notify all workers
+    ///
+    /// clean = true invalidates previous jobs (new tip).
+    fn refresh_job(&self, clean: bool) {
+        let coinbase = self.coinbase.lock().unwrap().clone();
+
+        // Lock order: chain → mempool
+        let (template, revision) = {
+            let c = self.chain.lock().unwrap();
+            let m = self.mempool.lock().unwrap();
+            (
+                build_template(
+                    &c.blocks,
+                    &c.utxos,
+                    m.sorted_for_mining(),
+                    &coinbase,
+                ),
+                m.revision(),
+            )
+        };
+
+        // Coinbase ends with the extranonce bytes; split there
+        let marker = serialize_transaction(&template.coinbase(u64::MAX));
+        let coinb1 = marker[..marker.len() - 8].to_vec();
+
+        let job_id = format!(
+            "{:x}",
+            self.next_job_id.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let stratum = StratumJob {
+            job_id: job_id.clone(),
+            prev_hash: template.prev_hash.clone(),
+            coinb1,
+            coinb2: vec![],
+            merkle_branch: coinbase_merkle_branch(
+                template.transactions.iter().map(|tx| tx.txid()).collect(),
+            ),
+            height: template.height,
+            target: template.target,
+            ntime: template.timestamp,
+            clean_jobs: clean,
+        };
+
+        let job = Arc::new(Job {
+            stratum,
+            template,
+            mempool_revision: revision,
+            created: Instant::now(),
+        });
+
+        let live: HashSet<String> = {
+            let mut recent = self.recent.lock().unwrap();
+            if clean {
+                recent.clear();
+            }
+            if recent.len() >= MAX_RECENT_JOBS {
+                let oldest = recent
+                    .iter()
+                    .min_by_key(|(_, j)| j.created)
+                    .map(|(id, _)| id.clone());
+                if let Some(id) = oldest {
+                    recent.remove(&id);
+                }
+            }
+            recent.insert(job_id, Arc::clone(&job));
+            recent.keys().cloned().collect()
+        };
+
+        *self.current.lock().unwrap() = Some(Arc::clone(&job));
+
+        let notify = notification("mining.notify", job.stratum.to_params());
+        let sessions: Vec<_> =
+            self.sessions.lock().unwrap().values().cloned().collect();
+
+        // Queued, so a stalled worker holds up nobody
+        for session in sessions {
+            let mut s = session.lock().unwrap();
+            s.submitted.retain(|job_id, _| live.contains(job_id));
+            if s.subscribed {
+                s.send(notify.clone());
+            }
+        }
+    }
+
+    /// Per-connection request loop
+    fn serve(self: Arc<Self>, stream: TcpStream) {
+        let (reader, writer) = match (stream.try_clone(), stream.try_clone()) {
+            (Ok(r), Ok(w)) => (BufReader::new(r), w),
+            _ => return,
+        };
+        if writer.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+            return;
+        }
+
+        let (outbox, queue) = sync_channel(WORKER_QUEUE_LEN);
+        thread::spawn(move || write_loop(writer, queue));
+
+        let id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        let extranonce1 = self
+            .next_extranonce1
+            .fetch_add(1, Ordering::Relaxed)
+            .to_le_bytes()
+            .to_vec();
+
+        let session = Arc::new(Mutex::new(Session {
+            outbox,
+            stream,
+            extranonce1,
+            subscribed: false,
+            authorized: HashSet::new(),
+            submitted: HashMap::new(),
+        }));
+
+        self.sessions.lock().unwrap().insert(id, Arc::clone(&session));
+
+        for line in reader.lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break,
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let request: Value = match serde_json::from_str(&line) {
+                Ok(v) => v,
+                Err(_) => break,
+            };
+
+            let replies = self.handle_request(&session, &request);
+
+            let s = session.lock().unwrap();
+            for reply in replies {
+                s.send(reply);
+            }
+        }
+
+        // Dropping the session closes the queue, ending the writer
+        self.sessions.lock().unwrap().remove(&id);
+    }
+
+    fn handle_request(
+        &self,
+        session: &Arc<Mutex<Session>>,
+        request: &Value,
+    ) -> Vec<Value> {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let params = request.get("params").cloned().unwrap_or(json!([]));
+
+        match request.get("method").and_then(|m| m.as_str()) {
+            Some("mining.subscribe") => {
+                let extranonce1 = {
+                    let mut s = session.lock().unwrap();
+                    s.subscribed = true;
+                    s.extranonce1.clone()
+                };
+
+                let mut replies = vec![response(
+                    id,
+                    json!([
+                        [["mining.notify", hex::encode(&extranonce1)]],
+                        hex::encode(&extranonce1),
+                        EXTRANONCE2_SIZE,
+                    ]),
+                )];
+
+                replies.push(notification(
+                    "mining.set_difficulty",
+                    json!([self.difficulty]),
+                ));
+
+                let current = self.current.lock().unwrap().clone();
+                if let Some(job) = current {
+                    let mut params = job.stratum.clone();
+                    params.clean_jobs = true;
+                    replies.push(notification("mining.notify", params.to_params()));
+                }
+
+                replies
+            }
+
+            Some("mining.authorize") => {
+                let worker = params.get(0).and_then(|w| w.as_str());
+                let password = params.get(1).and_then(|p| p.as_str()).unwrap_or("");
+
+                let accepted = match (worker, &self.password) {
+                    (None, _) => false,
+                    (Some(_), Some(required)) => password == required,
+                    (Some(_), None) => true,
+                };
+
+                if let (true, Some(worker)) = (accepted, worker) {
+                    session.lock().unwrap().authorized.insert(worker.to_string());
+                }
+                vec![response(id, json!(accepted))]
+            }
+
+            Some("mining.submit") => match self.submit_share(session, &params) {
+                Ok(()) => vec![response(id, json!(true))],
+                Err((code, msg)) => vec![error_response(id, code, msg)],
+            },
+
+            _ => vec![error_response(id, ERR_OTHER, "unknown method")],
+        }
+    }
+
+    fn submit_share(
+        &self,
+        session: &Arc<Mutex<Session>>,
+        params: &Value,
+    ) -> Result<(), (i64, &'static str)> {
+        let bad = (ERR_OTHER, "malformed submit");
+
+        let p = params.as_array().ok_or(bad)?;
+        if p.len() != 5 {
+            return Err(bad);
+        }
+
+        let worker = p[0].as_str().ok_or(bad)?;
+        let job_id = p[1].as_str().ok_or(bad)?;
+        let extranonce2 = hex::decode(p[2].as_str().ok_or(bad)?).map_err(|_| bad)?;
+        let ntime_bytes: [u8; 8] = hex::decode(p[3].as_str().ok_or(bad)?)
+            .map_err(|_| bad)?
+            .try_into()
+            .map_err(|_| bad)?;
+        let nonce_bytes: [u8; 8] = hex::decode(p[4].as_str().ok_or(bad)?)
+            .map_err(|_| bad)?
+            .try_into()
+            .map_err(|_| bad)?;
+
+        let ntime = i64::from_be_bytes(ntime_bytes);
+        let nonce = u64::from_be_bytes(nonce_bytes);
+
+        if extranonce2.len() != EXTRANONCE2_SIZE {
+            return Err((ERR_OTHER, "bad extranonce2 size"));
+        }
+
+        let job = self.recent.lock().unwrap().get(job_id).cloned();
+
+        let (job, extranonce1) = {
+            let mut s = session.lock().unwrap();
+            if !s.subscribed {
+                return Err((ERR_NOT_SUBSCRIBED, "not subscribed"));
+            }
+            if !s.authorized.contains(worker) {
+                return Err((ERR_UNAUTHORIZED, "unauthorized worker"));
+            }
+            let job = job.ok_or((ERR_JOB_NOT_FOUND, "job not found"))?;
+
+            // Only live jobs get an entry (pruned as jobs expire)
+            if !s
+                .submitted
+                .entry(job_id.to_string())
+                .or_default()
+                .insert((extranonce2.clone(), ntime, nonce))
+            {
+                return Err((ERR_DUPLICATE, "duplicate share"));
+            }
+            (job, s.extranonce1.clone())
+        };
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if ntime < job.template.min_timestamp || ntime > now + MAX_FUTURE_DRIFT {
+            return Err((ERR_OTHER, "ntime out of range"));
+        }
+
+        let hash = job.stratum.header_hash(&extranonce1, &extranonce2, ntime, nonce);
+
+        if !valid_pow(&hash, &self.share_target)
+            && !valid_pow(&hash, &job.stratum.target)
+        {
+            return Err((ERR_LOW_DIFFICULTY, "low difficulty share"));
+        }
+
+        if valid_pow(&hash, &job.stratum.target) {
+            let mut block = job
+                .template
+                .assemble(combined_extranonce(&extranonce1, &extranonce2));
+            block.header.timestamp = ntime;
+            block.header.nonce = nonce;
+            block.hash = block.hash_header();
+
+            if self.p2p.submit_block(block) {
+                self.blocks_found.fetch_add(1, Ordering::Relaxed);
+                println!(
+                    "⛏ Stratum block found at height {}: {}",
+                    job.stratum.height,
+                    hex::encode(&hash)
+                );
+                self.refresh_job(true);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Minimal Stratum client
+///
+/// Stand-in for external mining software when exercising the
+/// server locally (no real hashing hardware required).
+pub struct StratumClient {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    next_id: u64,
+    /// Notifications received while waiting for a response
+    pending: Vec<Value>,
+
+    pub extranonce1: Vec<u8>,
+    pub extranonce2_size: usize,
+    pub difficulty: u64,
+}
+
+impl StratumClient {
+    pub fn connect(addr: SocketAddr) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        let reader = BufReader::new(stream.try_clone()?);
+
+        Ok(Self {
+            stream,
+            reader,
+            next_id: 1,
+            pending: Vec::new(),
+            extranonce1: vec![],
+            extranonce2_size: EXTRANONCE2_SIZE,
+            difficulty: 1,
+        })
+    }
+
+    pub fn subscribe(&mut self) -> Result<(), String> {
+        let result = self.call("mining.subscribe", json!([]))?;
+
+        self.extranonce1 = result
+            .get(1)
+            .and_then(|v| v.as_str())
+            .and_then(|s| hex::decode(s).ok())
+            .ok_or("bad subscribe result")?;
+        self.extranonce2_size = result
+            .get(2)
+            .and_then(|v| v.as_u64())
+            .ok_or("bad subscribe result")? as usize;
+
+        Ok(())
+    }
+
+    pub fn authorize(&mut self, worker: &str, password: &str) -> Result<bool, String> {
+        let result = self.call("mining.authorize", json!([worker, password]))?;
+        Ok(result.as_bool().unwrap_or(false))
+    }
+
+    /// Wait for the next job (applies difficulty updates on the way)
+    pub fn next_job(&mut self) -> Result<StratumJob, String> {
+        loop {
+            let msg = if self.pending.is_empty() {
+                self.read_message()?
+            } else {
+                self.pending.remove(0)
+            };
+
+            match msg.get("method").and_then(|m| m.as_str()) {
+                Some("mining.set_difficulty") => {
+                    if let Some(d) = msg["params"][0].as_u64() {
+                        self.difficulty = d;
+                    }
+                }
+                Some("mining.notify") => {
+                    return StratumJob::from_params(&msg["params"])
+                        .ok_or_else(|| "bad job".to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Submit a share; Ok(false) carries a rejection
+    pub fn submit(
+        &mut self,
+        worker: &str,
+        job: &StratumJob,
+        extranonce2: &[u8],
+        ntime: i64,
+        nonce: u64,
+    ) -> Result<bool, String> {
+        match self.call(
+            "mining.submit",
+            json!([
+                worker,
+                job.job_id,
+                hex::encode(extranonce2),
+                hex::encode(ntime.to_be_bytes()),
+                hex::encode(nonce.to_be_bytes()),
+            ]),
+        ) {
+            Ok(v) => Ok(v.as_bool().unwrap_or(false)),
+            Err(e) if e.starts_with("rejected") => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Grind nonces until a share at the current difficulty is found
+    pub fn find_share(&self, job: &StratumJob, extranonce2: &[u8]) -> u64 {
+        let target = share_target(self.difficulty);
+        let mut nonce = 0u64;
+
+        loop {
+            let hash = job.header_hash(&self.extranonce1, extranonce2, job.ntime, nonce);
+            if valid_pow(&hash, &target) || valid_pow(&hash, &job.target) {
+                return nonce;
+            }
+            nonce += 1;
+        }
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        write_line(
+            &mut self.stream,
+            &json!({ "id": id, "method": method, "params": params }),
+        )
+        .map_err(|e| e.to_string())?;
+
+        loop {
+            let msg = self.read_message()?;
+
+            if msg.get("id").and_then(|v| v.as_u64()) != Some(id) {
+                self.pending.push(msg);
+                continue;
+            }
+
+            if !msg["error"].is_null() {
+                return Err(format!("rejected: {}", msg["error"]));
+            }
+
+            return Ok(msg["result"].clone());
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Value, String> {
+        let mut line = String::new();
+        let n = self.reader.read_line(&mut line).map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Err("connection closed".into());
+        }
+        serde_json::from_str(&line).map_err(|e| e.to_string())
+    }
+}
+
+fn response(id: Value, result: Value) -> Value {
+    json!({ "id": id, "result": result, "error": null })
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "id": id, "result": null, "error": [code, message, null] })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "id": null, "method": method, "params": params })
+}
+
+/// Writer thread of one worker: drains its queue until the
+/// session is gone or a write fails
+fn write_loop(mut stream: TcpStream, queue: Receiver<Value>) {
+    for msg in queue {
+        if write_line(&mut stream, &msg).is_err() {
+            // Timed out or gone: ends the reader as well
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+    }
+}
+
+fn write_line(stream: &mut TcpStream, msg: &Value) -> std::io::Result<()> {
+    let mut line = msg.to_string();
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::transport::tcp::TcpTransport;
+
+    /// Server over a chain of just a genesis block whose target
+    /// any hash meets
+    fn server() -> (StratumServer, Arc<Mutex<Blockchain>>) {
+        server_with(StratumOptions::default())
+    }
+
+    fn server_with(options: StratumOptions) -> (StratumServer, Arc<Mutex<Blockchain>>) {
+        let mut genesis = Block {
+            header: BlockHeader {
+                height: 0,
+                timestamp: 1_730_000_000,
+                prev_hash: vec![0; 32],
+                nonce: 0,
+                target: [0xff; 32],
+                merkle_root: vec![0; 32],
+            },
+            transactions: vec![],
+            hash: vec![],
+        };
+        genesis.hash = genesis.hash_header();
+
+        let mut chain = Blockchain::new();
+        chain.blocks.push(genesis);
+        let chain = Arc::new(Mutex::new(chain));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+
+        let transport = TcpTransport::new("127.0.0.1:0", Arc::new(|_, _| {}), Arc::new(|_| {}));
+        let p2p = Arc::new(P2PNetwork::new(
+            transport,
+            Arc::clone(&chain),
+            Arc::clone(&mempool),
+        ));
+
+        let server = StratumServer::start(
+            "127.0.0.1:0",
+            Arc::clone(&chain),
+            mempool,
+            p2p,
+            CoinbaseSpec::single(vec![7; 20]),
+            options,
+        )
+        .unwrap();
+        (server, chain)
+    }
+
+    #[test]
+    fn worker_mines_through_the_server() {
+        let (server, chain) = server();
+        let mut client = StratumClient::connect(server.local_addr()).unwrap();
+
+        // Jobs wait for a subscription; submits for authorization
+        client.subscribe().unwrap();
+        assert_eq!(client.extranonce1.len(), EXTRANONCE1_SIZE);
+        assert_eq!(client.extranonce2_size, EXTRANONCE2_SIZE);
+
+        let job = client.next_job().unwrap();
+        assert_eq!(client.difficulty, 1);
+        assert_eq!(job.height, 1);
+        assert!(job.clean_jobs);
+
+        let extranonce2 = [0, 0, 0, 1];
+        let nonce = client.find_share(&job, &extranonce2);
+        assert_eq!(
+            client.submit("w", &job, &extranonce2, job.ntime, nonce),
+            Ok(false)
+        );
+
+        assert_eq!(client.authorize("w", ""), Ok(true));
+        assert_eq!(
+            client.submit("w", &job, &extranonce2, job.ntime, nonce),
+            Ok(true)
+        );
+
+        // Every share here is a block: the tip moves and a clean
+        // job for the next height follows
+        let next = client.next_job().unwrap();
+        assert_eq!(chain.lock().unwrap().height(), 2);
+        assert_eq!(next.height, 2);
+        assert!(next.clean_jobs);
+        assert_eq!(next.prev_hash, chain.lock().unwrap().blocks[1].hash);
+
+        // The old job went with the old tip
+        assert_eq!(
+            client.submit("w", &job, &extranonce2, job.ntime, nonce),
+            Ok(false)
+        );
+
+        let nonce = client.find_share(&next, &extranonce2);
+        assert_eq!(
+            client.submit("w", &next, &extranonce2, next.ntime, nonce),
+            Ok(true)
+        );
+        assert_eq!(chain.lock().unwrap().height(), 3);
+    }
+
+    #[test]
+    fn rejects_malformed_and_unknown() {
+        let (server, _) = server();
+        let mut client = StratumClient::connect(server.local_addr()).unwrap();
+
+        assert!(client
+            .call("mining.nothing", json!([]))
+            .unwrap_err()
+            .starts_with("rejected"));
+        assert!(client
+            .call("mining.submit", json!(["w"]))
+            .unwrap_err()
+            .starts_with("rejected"));
+
+        client.subscribe().unwrap();
+        client.authorize("w", "").unwrap();
+        let job = client.next_job().unwrap();
+
+        let mut unknown = job.clone();
+        unknown.job_id = "ffff".into();
+        assert_eq!(
+            client.submit("w", &unknown, &[0; 4], job.ntime, 0),
+            Ok(false)
+        );
+        assert_eq!(server.worker_count(), 1);
+    }
+
+    #[test]
+    fn checks_worker_credentials() {
+        let (server, _) = server_with(StratumOptions {
+            password: Some("secret".into()),
+            ..StratumOptions::default()
+        });
+        let mut client = StratumClient::connect(server.local_addr()).unwrap();
+        client.subscribe().unwrap();
+        let job = client.next_job().unwrap();
+        let nonce = client.find_share(&job, &[0; 4]);
+
+        assert_eq!(client.authorize("w", "guess"), Ok(false));
+        assert_eq!(
+            client.submit("w", &job, &[0; 4], job.ntime, nonce),
+            Ok(false)
+        );
+
+        // Submits count only for the names that were authorized
+        assert_eq!(client.authorize("w", "secret"), Ok(true));
+        assert_eq!(
+            client.submit("other", &job, &[0; 4], job.ntime, nonce),
+            Ok(false)
+        );
+        assert_eq!(
+            client.submit("w", &job, &[0; 4], job.ntime, nonce),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn refuses_workers_over_the_limit() {
+        let (server, _) = server_with(StratumOptions {
+            max_workers: 1,
+            ..StratumOptions::default()
+        });
+        let mut first = StratumClient::connect(server.local_addr()).unwrap();
+        first.subscribe().unwrap();
+
+        let mut second = StratumClient::connect(server.local_addr()).unwrap();
+        assert!(second.subscribe().is_err());
+
+        // The slot frees up when the first worker leaves
+        drop(first);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.shared.workers.load(Ordering::Relaxed) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        let mut third = StratumClient::connect(server.local_addr()).unwrap();
+        assert!(third.subscribe().is_ok());
+    }
+}
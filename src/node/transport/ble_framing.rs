@@ -0,0 +1,412 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::node::wire::{HEADER_SIZE, MAX_MESSAGE_SIZE};
+
+/// First byte of every chunk (never the first byte of a wire
+/// frame, so unchunked frames from older peers still pass)
+pub const CHUNK_TAG: u8 = 0xb7;
+
+/// tag | message id | index u16
+const CHUNK_HEADER_SIZE: usize = 4;
+
+/// First chunk only, after the header: total length u32
+const LENGTH_SIZE: usize = 4;
+
+/// ATT header in every write / notification
+const ATT_OVERHEAD: usize = 3;
+
+/// ATT MTU every device supports before negotiation
+pub const DEFAULT_ATT_MTU: usize = 23;
+
+/// A message whose next chunk is this late is dropped
+const REASSEMBLY_IDLE: Duration = Duration::from_secs(30);
+
+/// Cuts frames into chunks that fit one GATT write
+///
+/// Chunk layout (integers big-endian):
+/// tag | message id | index u16 | [total length u32, first
+/// chunk only] | payload
+///
+/// Chunks of a message must arrive in order and unmixed with
+/// other messages (GATT keeps order on a link); the index
+/// wraps, so messages longer than 65536 chunks are fine.
+pub struct BleFramer {
+    /// Value bytes per chunk, header included
+    chunk_size: usize,
+    next_id: u8,
+}
+
+impl BleFramer {
+    /// Chunks for a link with ATT MTU `mtu`
+    pub fn new(mtu: usize) -> Result<Self, &'static str> {
+        let chunk_size = mtu.saturating_sub(ATT_OVERHEAD);
+        if chunk_size <= CHUNK_HEADER_SIZE + LENGTH_SIZE {
+            return Err("ATT MTU too small");
+        }
+        Ok(Self { chunk_size, next_id: 0 })
+    }
+
+    pub fn fragment(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let mut chunks = Vec::new();
+        let mut rest = frame;
+        let mut index: u16 = 0;
+
+        loop {
+            let mut chunk = Vec::with_capacity(self.chunk_size);
+            chunk.push(CHUNK_TAG);
+            chunk.push(id);
+            chunk.extend_from_slice(&index.to_be_bytes());
+            if chunks.is_empty() {
+                chunk.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            }
+
+            let take = (self.chunk_size - chunk.len()).min(rest.len());
+            chunk.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            chunks.push(chunk);
+
+            if rest.is_empty() {
+                return chunks;
+            }
+            index = index.wrapping_add(1);
+        }
+    }
+}
+
+/// Message being reassembled
+struct Partial {
+    id: u8,
+    total: usize,
+    next: u16,
+    data: Vec<u8>,
+    last_chunk: Instant,
+}
+
+/// Rebuilds frames from the chunks of one peripheral
+///
+/// Holds at most one message. A gap, a chunk of another
+/// message or a stall drops it; the next first chunk starts
+/// over. Values without the chunk tag are whole frames from
+/// peers that do not chunk, and pass through unchanged.
+pub struct BleReassembler {
+    partial: Option<Partial>,
+}
+
+impl Default for BleReassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BleReassembler {
+    pub fn new() -> Self {
+        Self { partial: None }
+    }
+
+    /// Feed one notification value; a complete frame, if any
+    pub fn push(&mut self, value: &[u8]) -> Result<Option<Vec<u8>>, &'static str> {
+        if value.first() != Some(&CHUNK_TAG) {
+            return Ok(Some(value.to_vec()));
+        }
+        if value.len() < CHUNK_HEADER_SIZE {
+            return Err("short chunk");
+        }
+
+        let id = value[1];
+        let index = u16::from_be_bytes([value[2], value[3]]);
+        let first = index == 0 && value.len() >= CHUNK_HEADER_SIZE + LENGTH_SIZE;
+
+        let stale = self
+            .partial
+            .as_ref()
+            .map(|p| p.last_chunk.elapsed() > REASSEMBLY_IDLE)
+            .unwrap_or(false);
+        if stale {
+            self.partial = None;
+        }
+
+        // Anything but the next chunk of the open message must
+        // start a new one (the sender gave up on the open one);
+        // index 0 after a wrap is still a continuation
+        let continues = self
+            .partial
+            .as_ref()
+            .map(|p| p.id == id && p.next == index)
+            .unwrap_or(false);
+
+        let payload = if continues {
+            &value[CHUNK_HEADER_SIZE..]
+        } else if first {
+            let total = u32::from_be_bytes(
+                value[CHUNK_HEADER_SIZE..CHUNK_HEADER_SIZE + LENGTH_SIZE].try_into().unwrap(),
+            ) as usize;
+            if total == 0 || total > HEADER_SIZE + MAX_MESSAGE_SIZE {
+                self.partial = None;
+                return Err("bad message length");
+            }
+
+            self.partial = Some(Partial {
+                id,
+                total,
+                next: 0,
+                data: Vec::with_capacity(total.min(64 * 1024)),
+                last_chunk: Instant::now(),
+            });
+            &value[CHUNK_HEADER_SIZE + LENGTH_SIZE..]
+        } else {
+            let lost = self.partial.take().is_some();
+            return Err(if lost { "chunk out of order" } else { "chunk without a start" });
+        };
+
+        let partial = self.partial.as_mut().unwrap();
+        partial.data.extend_from_slice(payload);
+        partial.next = index.wrapping_add(1);
+        partial.last_chunk = Instant::now();
+
+        if partial.data.len() > partial.total {
+            self.partial = None;
+            return Err("message longer than announced");
+        }
+        if partial.data.len() == partial.total {
+            return Ok(self.partial.take().map(|p| p.data));
+        }
+        Ok(None)
+    }
+
+    /// Bytes of the message being rebuilt
+    pub fn pending(&self) -> usize {
+        self.partial.as_ref().map(|p| p.data.len()).unwrap_or(0)
+    }
+}
+
+/// In-memory GATT characteristic, for tests and development
+///
+/// Stands in for a device's Bitcoin characteristic: a write
+/// longer than one ATT value fails as it would over the air,
+/// and each accepted write becomes one notification on the
+/// other side, in order. `lose_next` drops a notification,
+/// like a full controller buffer under write-without-response.
+pub struct MockCharacteristic {
+    max_value: usize,
+    state: Mutex<MockState>,
+}
+
+struct MockState {
+    notifications: VecDeque<Vec<u8>>,
+    lose: usize,
+    written: u64,
+}
+
+impl MockCharacteristic {
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            max_value: mtu.saturating_sub(ATT_OVERHEAD),
+            state: Mutex::new(MockState {
+                notifications: VecDeque::new(),
+                lose: 0,
+                written: 0,
+            }),
+        }
+    }
+
+    pub fn write(&self, value: &[u8]) -> Result<(), &'static str> {
+        if value.len() > self.max_value {
+            return Err("value longer than ATT MTU");
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.written += 1;
+        if state.lose > 0 {
+            state.lose -= 1;
+        } else {
+            state.notifications.push_back(value.to_vec());
+        }
+        Ok(())
+    }
+
+    /// The next `count` writes are never notified
+    pub fn lose_next(&self, count: usize) {
+        self.state.lock().unwrap().lose += count;
+    }
+
+    /// Notifications not yet read, oldest first
+    pub fn notifications(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().notifications.drain(..).collect()
+    }
+
+    /// Writes accepted, lost ones included
+    pub fn written(&self) -> u64 {
+        self.state.lock().unwrap().written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: usize) -> Vec<u8> {
+        let mut f: Vec<u8> = (0..len).map(|i| (i * 7 + i / 256) as u8).collect();
+        // Wire frames start with the network magic
+        f[..4].copy_from_slice(b"REV3");
+        f
+    }
+
+    /// Write every chunk of `frames` through `link`
+    fn send(framer: &mut BleFramer, link: &MockCharacteristic, frames: &[Vec<u8>]) {
+        for f in frames {
+            for chunk in framer.fragment(f) {
+                link.write(&chunk).unwrap();
+            }
+        }
+    }
+
+    /// Feed notifications; the frames rebuilt and the errors
+    fn receive(
+        r: &mut BleReassembler,
+        link: &MockCharacteristic,
+    ) -> (Vec<Vec<u8>>, Vec<&'static str>) {
+        let (mut frames, mut errors) = (Vec::new(), Vec::new());
+        for value in link.notifications() {
+            match r.push(&value) {
+                Ok(Some(f)) => frames.push(f),
+                Ok(None) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+        (frames, errors)
+    }
+
+    #[test]
+    fn round_trip_at_default_and_large_mtu() {
+        for mtu in [DEFAULT_ATT_MTU, 247] {
+            let mut framer = BleFramer::new(mtu).unwrap();
+            let link = MockCharacteristic::new(mtu);
+            let mut r = BleReassembler::new();
+
+            let frames = vec![frame(5), frame(13), frame(1000), frame(70_000)];
+            send(&mut framer, &link, &frames);
+
+            assert_eq!(receive(&mut r, &link), (frames, vec![]), "mtu {}", mtu);
+            assert_eq!(r.pending(), 0);
+        }
+    }
+
+    #[test]
+    fn chunks_fit_the_att_value() {
+        let mut framer = BleFramer::new(DEFAULT_ATT_MTU).unwrap();
+        let chunks = framer.fragment(&frame(100));
+        assert!(chunks
+            .iter()
+            .all(|c| c.len() <= DEFAULT_ATT_MTU - ATT_OVERHEAD));
+
+        let link = MockCharacteristic::new(DEFAULT_ATT_MTU);
+        assert_eq!(
+            link.write(&[CHUNK_TAG; 21]),
+            Err("value longer than ATT MTU")
+        );
+        assert!(BleFramer::new(11).is_err());
+    }
+
+    #[test]
+    fn lost_chunk_drops_only_its_message() {
+        let mut framer = BleFramer::new(DEFAULT_ATT_MTU).unwrap();
+        let link = MockCharacteristic::new(DEFAULT_ATT_MTU);
+        let mut r = BleReassembler::new();
+
+        // 200 bytes: 13 chunks; the fourth is lost
+        let chunks = framer.fragment(&frame(200));
+        for (i, chunk) in chunks.iter().enumerate() {
+            if i == 3 {
+                link.lose_next(1);
+            }
+            link.write(chunk).unwrap();
+        }
+        assert_eq!(link.written(), chunks.len() as u64);
+
+        let (frames, errors) = receive(&mut r, &link);
+        assert!(frames.is_empty());
+        assert_eq!(errors[0], "chunk out of order");
+        assert!(errors[1..].iter().all(|&e| e == "chunk without a start"));
+        assert_eq!(r.pending(), 0);
+
+        // The next message goes through
+        let next = vec![frame(300)];
+        send(&mut framer, &link, &next);
+        assert_eq!(receive(&mut r, &link), (next, vec![]));
+    }
+
+    #[test]
+    fn interrupted_message_is_replaced_by_the_next() {
+        let mut framer = BleFramer::new(DEFAULT_ATT_MTU).unwrap();
+        let mut r = BleReassembler::new();
+
+        let first = framer.fragment(&frame(100));
+        assert_eq!(r.push(&first[0]), Ok(None));
+        assert!(r.pending() > 0);
+
+        let second = frame(50);
+        let mut out = None;
+        for chunk in framer.fragment(&second) {
+            if let Some(f) = r.push(&chunk).unwrap() {
+                out = Some(f);
+            }
+        }
+        assert_eq!(out, Some(second));
+    }
+
+    #[test]
+    fn chunk_index_wraps() {
+        let mut framer = BleFramer::new(DEFAULT_ATT_MTU).unwrap();
+        let link = MockCharacteristic::new(DEFAULT_ATT_MTU);
+        let mut r = BleReassembler::new();
+
+        // 16 payload bytes per chunk: past 65536 chunks
+        let big = frame(1_500_000);
+        let chunks = framer.fragment(&big);
+        assert!(chunks.len() > 65_536);
+        assert_eq!(&chunks[65_536][2..4], &[0, 0]);
+
+        for chunk in &chunks {
+            link.write(chunk).unwrap();
+        }
+        assert_eq!(receive(&mut r, &link), (vec![big], vec![]));
+    }
+
+    #[test]
+    fn untagged_values_pass_through() {
+        let mut framer = BleFramer::new(DEFAULT_ATT_MTU).unwrap();
+        let mut r = BleReassembler::new();
+        let legacy = frame(18);
+
+        assert_eq!(r.push(&legacy), Ok(Some(legacy.clone())));
+
+        // Between the chunks of a message, too
+        let chunked = frame(40);
+        let chunks = framer.fragment(&chunked);
+        assert_eq!(r.push(&chunks[0]), Ok(None));
+        assert_eq!(r.push(&legacy), Ok(Some(legacy.clone())));
+        for chunk in &chunks[1..chunks.len() - 1] {
+            assert_eq!(r.push(chunk), Ok(None));
+        }
+        assert_eq!(r.push(&chunks[chunks.len() - 1]), Ok(Some(chunked)));
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        let mut r = BleReassembler::new();
+        assert_eq!(r.push(&[CHUNK_TAG, 0]), Err("short chunk"));
+        assert_eq!(
+            r.push(&[CHUNK_TAG, 0, 0, 0, 0, 0, 0, 0]),
+            Err("bad message length")
+        );
+        assert_eq!(
+            r.push(&[CHUNK_TAG, 0, 0, 0, 0, 0, 0, 2, 1, 2, 3]),
+            Err("message longer than announced")
+        );
+    }
+}
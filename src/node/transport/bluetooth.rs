@@ -1,14 +1,22 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
-use std::net::SocketAddr;
 
 use btleplug::api::{
-    Central, Manager as _, Peripheral as _, ScanFilter, CharPropFlags,
+    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, CharPropFlags,
+    WriteType,
 };
-use btleplug::platform::Manager;
+use btleplug::platform::{Manager, Peripheral};
 use futures::stream::StreamExt;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use crate::node::peer_id::{PeerId, TransportKind};
+use crate::node::transport::ble_framing::{BleFramer, BleReassembler, DEFAULT_ATT_MTU};
+use crate::node::transport::{EventFn, PeerEvent, ReceiveFn, Transport};
+
 /// BLE service & characteristic UUIDs
 /// These MUST stay constant for network compatibility
 const BITCOIN_BLE_SERVICE: Uuid =
@@ -16,17 +24,60 @@ const BITCOIN_BLE_SERVICE: Uuid =
 const BITCOIN_BLE_CHAR: Uuid =
     Uuid::from_u128(0xffffffff_1111_2222_3333_444444444444);
 
-/// Real Bluetooth Low Energy transport (receive-first)
-pub struct BluetoothTransport;
+/// Messages waiting to be written to one device; beyond this
+/// new ones are dropped
+const LINK_QUEUE_LEN: usize = 64;
+
+/// Real Bluetooth Low Energy transport
+///
+/// Receives through notifications on the Bitcoin
+/// characteristic; sends by writing to it (devices whose
+/// characteristic is not writable are receive-only). Frames
+/// are cut into chunks that fit one ATT value and rebuilt per
+/// device (see `ble_framing`); each device has one writer, so
+/// chunks of different frames never mix.
+///
+/// Outgoing chunks always fit the default ATT MTU (23 bytes):
+/// btleplug 0.11 neither negotiates nor reports the MTU of a
+/// connection, so nothing larger can be relied on. Incoming
+/// chunks of any size are accepted, so peers that negotiate
+/// more still send in larger chunks.
+pub struct BluetoothTransport {
+    /// Devices with a live subscription (not reconnected)
+    links: Mutex<HashMap<PeerId, Link>>,
+    framer: Mutex<BleFramer>,
+}
+
+struct Link {
+    /// Chunks of one frame per entry; None if the
+    /// characteristic does not accept writes
+    outbox: Option<mpsc::Sender<Vec<Vec<u8>>>>,
+}
 
 impl BluetoothTransport {
-    /// Start BLE listener
+    /// Start BLE listener on its own runtime thread
     ///
     /// Receives raw NetworkMessage bytes over BLE and injects
-    /// them into the normal P2P pipeline via on_receive.
-    pub async fn start(
-        on_receive: Arc<dyn Fn(SocketAddr, Vec<u8>) + Send + Sync>,
-    ) {
+    /// them into the normal P2P pipeline via on_receive. Each
+    /// subscribed device is a peer (`ble:<device id>`), reported
+    /// connected until its notification stream ends.
+    pub fn start(on_receive: ReceiveFn, on_event: EventFn) -> Arc<Self> {
+        let runtime = Runtime::new().expect("BLE runtime failed");
+
+        let transport = Arc::new(Self {
+            links: Mutex::new(HashMap::new()),
+            framer: Mutex::new(BleFramer::new(DEFAULT_ATT_MTU).expect("ATT MTU")),
+        });
+
+        let scanner = Arc::clone(&transport);
+        thread::spawn(move || {
+            runtime.block_on(scanner.scan(on_receive, on_event));
+        });
+
+        transport
+    }
+
+    async fn scan(self: Arc<Self>, on_receive: ReceiveFn, on_event: EventFn) {
         let manager = Manager::new().await
             .expect("BLE manager failed");
 
@@ -52,6 +103,11 @@ impl BluetoothTransport {
                 .unwrap_or_default();
 
             for peripheral in peripherals {
+                let peer = PeerId::Bluetooth(peripheral.id().to_string());
+                if self.links.lock().unwrap().contains_key(&peer) {
+                    continue;
+                }
+
                 if let Ok(Some(props)) = peripheral.properties().await {
                     // ✅ FIX: services is Vec<Uuid>, not Option
                     if props.services.contains(&BITCOIN_BLE_SERVICE) {
@@ -70,18 +126,39 @@ impl BluetoothTransport {
                                     let mut notifications =
                                         peripheral.notifications().await.unwrap();
 
+                                    let outbox = write_mode(&characteristic).map(|mode| {
+                                        spawn_writer(peripheral.clone(), characteristic.clone(), mode)
+                                    });
+
                                     let on_receive = Arc::clone(&on_receive);
+                                    let on_event = Arc::clone(&on_event);
+                                    let transport = Arc::clone(&self);
+                                    let peer = peer.clone();
+
+                                    self.links
+                                        .lock()
+                                        .unwrap()
+                                        .insert(peer.clone(), Link { outbox });
+                                    (on_event)(PeerEvent::Connected {
+                                        peer: peer.clone(),
+                                        inbound: false,
+                                        encrypted: false,
+                                    });
 
                                     tokio::spawn(async move {
+                                        let mut reassembler = BleReassembler::new();
                                         while let Some(data) =
                                             notifications.next().await
                                         {
-                                            // Dummy address for BLE source
-                                            let addr: SocketAddr =
-                                                "0.0.0.0:0".parse().unwrap();
-
-                                            (on_receive)(addr, data.value);
+                                            // Broken chunk sequences are radio
+                                            // trouble, not misbehaviour: dropped
+                                            if let Ok(Some(frame)) = reassembler.push(&data.value) {
+                                                (on_receive)(peer.clone(), frame);
+                                            }
                                         }
+
+                                        transport.links.lock().unwrap().remove(&peer);
+                                        (on_event)(PeerEvent::Disconnected(peer));
                                     });
                                 }
                             }
@@ -94,3 +171,72 @@ impl BluetoothTransport {
         }
     }
 }
+
+/// Write queued frames to the device, chunk by chunk, until
+/// the link is dropped; the rest of a frame whose write fails
+/// is skipped (the receiver discards it)
+fn spawn_writer(
+    peripheral: Peripheral,
+    characteristic: Characteristic,
+    mode: WriteType,
+) -> mpsc::Sender<Vec<Vec<u8>>> {
+    let (tx, mut rx) = mpsc::channel::<Vec<Vec<u8>>>(LINK_QUEUE_LEN);
+
+    tokio::spawn(async move {
+        while let Some(chunks) = rx.recv().await {
+            for chunk in chunks {
+                if peripheral.write(&characteristic, &chunk, mode).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    tx
+}
+
+/// Prefer writes without response (no round trip per chunk)
+fn write_mode(characteristic: &Characteristic) -> Option<WriteType> {
+    let props = characteristic.properties;
+    if props.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE) {
+        Some(WriteType::WithoutResponse)
+    } else if props.contains(CharPropFlags::WRITE) {
+        Some(WriteType::WithResponse)
+    } else {
+        None
+    }
+}
+
+impl Transport for BluetoothTransport {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Bluetooth
+    }
+
+    fn send(&self, peer: &PeerId, data: &[u8]) {
+        let links = self.links.lock().unwrap();
+        let Some(Link { outbox: Some(outbox) }) = links.get(peer) else {
+            return;
+        };
+
+        // Full queue: the device cannot keep up, drop
+        let chunks = self.framer.lock().unwrap().fragment(data);
+        let _ = outbox.try_send(chunks);
+    }
+
+    fn broadcast(&self, data: &[u8]) {
+        for peer in self.peers() {
+            self.send(&peer, data);
+        }
+    }
+
+    /// Subscribed devices we can write to
+    fn peers(&self) -> Vec<PeerId> {
+        self.links
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, link)| link.outbox.is_some())
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+}
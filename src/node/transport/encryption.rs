@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+
+use aes_gcm::{
+    Aes256Gcm,
+    aead::{Aead, KeyInit, Payload},
+};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aes::Aes256;
+use aes_gcm::aes::cipher::BlockEncrypt;
+use rand::{rngs::OsRng, RngCore};
+use secp256k1::ellswift::{ElligatorSwift, ElligatorSwiftParty};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use sha2::{Sha256, Digest};
+
+use crate::crypto::{public_key, sign, verify_signature};
+use crate::node::wire::{encode_frame, HEADER_SIZE, MAX_MESSAGE_SIZE, NETWORK_MAGIC};
+
+const NODE_KEY_FILE: &str = "data/node_key.dat";
+
+/// Ephemeral key as sent at connection start: its 64-byte
+/// ElligatorSwift encoding, raw (no frame around it)
+pub const KEY_EXCHANGE_SIZE: usize = 64;
+
+/// First encrypted frame each side sends: static key + signature
+pub const ENC_AUTH: &str = "encauth";
+
+/// AES-GCM tag
+const TAG_SIZE: usize = 16;
+
+/// Encrypted length in front of every packet
+const LENGTH_SIZE: usize = 4;
+
+/// Largest encrypted packet body (a full frame plus tag)
+pub const MAX_PACKET_SIZE: usize = HEADER_SIZE + MAX_MESSAGE_SIZE + TAG_SIZE;
+
+/// Long-lived node identity, used only to authenticate
+/// encrypted sessions (never for coins)
+pub struct NodeKey {
+    secret: SecretKey,
+    pub public: PublicKey,
+}
+
+impl NodeKey {
+    /// Load data/node_key.dat, creating it on first run
+    /// (readable by the owner only)
+    pub fn load_or_create() -> Self {
+        if let Ok(data) = fs::read(NODE_KEY_FILE) {
+            if let Ok(secret) = SecretKey::from_slice(&data) {
+                // Files from older versions were world-readable
+                let _ = make_private(NODE_KEY_FILE);
+                return Self::from_secret(secret);
+            }
+        }
+
+        let key = Self::generate();
+        if fs::create_dir_all("data").is_ok() {
+            let _ = write_private(NODE_KEY_FILE, &key.secret.secret_bytes());
+        }
+        key
+    }
+
+    /// Fresh key, not persisted
+    pub fn generate() -> Self {
+        Self::from_secret(random_secret())
+    }
+
+    /// Sign with the node key (e.g. sync bundles)
+    pub fn sign(&self, msg: &[u8]) -> Vec<u8> {
+        sign(msg, &self.secret)
+    }
+
+    fn from_secret(secret: SecretKey) -> Self {
+        Self {
+            public: public_key(&secret),
+            secret,
+        }
+    }
+}
+
+/// Encryption settings for a stream transport (POLICY ONLY)
+pub struct EncryptionPolicy {
+    pub node_key: NodeKey,
+
+    /// Refuse peers that cannot encrypt
+    pub require: bool,
+
+    /// Expected static keys of peers we dial; such peers must
+    /// encrypt and prove the key, or the connection is dropped
+    pub peer_keys: HashMap<SocketAddr, PublicKey>,
+}
+
+impl EncryptionPolicy {
+    pub fn new(node_key: NodeKey) -> Self {
+        Self {
+            node_key,
+            require: false,
+            peer_keys: HashMap::new(),
+        }
+    }
+}
+
+/// One side of the ephemeral key exchange
+///
+/// Keys travel ElligatorSwift-encoded, as in BIP324: 64 bytes
+/// that cannot be told from random, so nothing on the wire
+/// marks the connection as this protocol.
+pub struct Handshake {
+    secret: SecretKey,
+    ours: ElligatorSwift,
+    initiator: bool,
+}
+
+impl Handshake {
+    pub fn new(initiator: bool) -> Self {
+        let secp = Secp256k1::new();
+
+        loop {
+            let secret = random_secret();
+            let mut aux = [0u8; 32];
+            OsRng.fill_bytes(&mut aux);
+            let ours = ElligatorSwift::from_seckey(&secp, secret, Some(aux));
+
+            // Would be taken for a plaintext frame (one in 2^32)
+            if ours.to_array()[..NETWORK_MAGIC.len()] != NETWORK_MAGIC {
+                return Self {
+                    secret,
+                    ours,
+                    initiator,
+                };
+            }
+        }
+    }
+
+    /// Our key, to be sent as is
+    pub fn key_bytes(&self) -> [u8; KEY_EXCHANGE_SIZE] {
+        self.ours.to_array()
+    }
+
+    /// Derive the session from the peer's key (any 64 bytes
+    /// decode to a key)
+    pub fn complete(self, theirs: [u8; KEY_EXCHANGE_SIZE]) -> Session {
+        let theirs = ElligatorSwift::from_array(theirs);
+        let (init, resp, party) = if self.initiator {
+            (self.ours, theirs, ElligatorSwiftParty::A)
+        } else {
+            (theirs, self.ours, ElligatorSwiftParty::B)
+        };
+
+        // Hashed with both encodings (BIP324 x-only ECDH)
+        let shared = ElligatorSwift::shared_secret(init, resp, self.secret, party, None);
+
+        let derive = |label: &[u8]| -> [u8; 32] {
+            let mut h = Sha256::new();
+            h.update(label);
+            h.update(shared.as_secret_bytes());
+            h.finalize().into()
+        };
+
+        let i2r = (derive(b"revelation/enc/i2r"), derive(b"revelation/enc/i2r/len"));
+        let r2i = (derive(b"revelation/enc/r2i"), derive(b"revelation/enc/r2i/len"));
+        let (send, recv) = if self.initiator { (i2r, r2i) } else { (r2i, i2r) };
+
+        Session {
+            id: derive(b"revelation/enc/session"),
+            initiator: self.initiator,
+            send: SendCipher(Cipher::new(&send.0, &send.1)),
+            recv: RecvCipher(Cipher::new(&recv.0, &recv.1)),
+        }
+    }
+}
+
+/// Established session, split into halves for the writer and
+/// the reader
+pub struct Session {
+    /// Identifies the session to both sides (signed for auth)
+    pub id: [u8; 32],
+    initiator: bool,
+    pub send: SendCipher,
+    pub recv: RecvCipher,
+}
+
+impl Session {
+    /// Our ENC_AUTH frame (to be sent encrypted)
+    pub fn auth_frame(&self, key: &NodeKey) -> Vec<u8> {
+        let mut payload = key.public.serialize().to_vec();
+        payload.extend(sign(&auth_message(&self.id, self.initiator), &key.secret));
+        encode_frame(ENC_AUTH, &payload)
+    }
+
+    /// Check the peer's ENC_AUTH payload; returns its static key
+    pub fn verify_auth(&self, payload: &[u8]) -> Result<PublicKey, &'static str> {
+        if payload.len() != 33 + 64 {
+            return Err("bad auth");
+        }
+
+        let (key, sig) = payload.split_at(33);
+        if !verify_signature(&auth_message(&self.id, !self.initiator), sig, key) {
+            return Err("bad auth signature");
+        }
+
+        PublicKey::from_slice(key).map_err(|_| "bad auth key")
+    }
+}
+
+/// Signed by each side; the role byte stops a peer from
+/// reflecting our own proof back at us
+fn auth_message(session_id: &[u8; 32], initiator: bool) -> Vec<u8> {
+    let mut msg = session_id.to_vec();
+    msg.push(initiator as u8);
+    msg
+}
+
+/// AES-256-GCM with a per-direction counter nonce; packet
+/// lengths are masked with AES-256 of the same counter under
+/// a key of their own
+struct Cipher {
+    aead: Aes256Gcm,
+    length: Aes256,
+    counter: u64,
+}
+
+impl Cipher {
+    fn new(key: &[u8; 32], length_key: &[u8; 32]) -> Self {
+        Self {
+            aead: Aes256Gcm::new(GenericArray::from_slice(key)),
+            length: Aes256::new(GenericArray::from_slice(length_key)),
+            counter: 0,
+        }
+    }
+
+    /// Mask for the length of the current packet
+    fn length_mask(&self) -> u32 {
+        let mut block = GenericArray::from([0u8; 16]);
+        block[..8].copy_from_slice(&self.counter.to_le_bytes());
+        self.length.encrypt_block(&mut block);
+        u32::from_le_bytes([block[0], block[1], block[2], block[3]])
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter += 1;
+        nonce
+    }
+}
+
+pub struct SendCipher(Cipher);
+
+impl SendCipher {
+    /// Encrypt one frame: masked length u32 LE | ciphertext +
+    /// tag (the masked length is authenticated with it)
+    pub fn seal(&mut self, frame: &[u8]) -> Vec<u8> {
+        let len = (frame.len() + TAG_SIZE) as u32;
+        let header = (len ^ self.0.length_mask()).to_le_bytes();
+
+        let nonce = self.0.next_nonce();
+        let ct = self
+            .0
+            .aead
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: frame,
+                    aad: &header,
+                },
+            )
+            .expect("aes-gcm encrypt");
+
+        let mut out = Vec::with_capacity(LENGTH_SIZE + ct.len());
+        out.extend_from_slice(&header);
+        out.extend(ct);
+        out
+    }
+}
+
+pub struct RecvCipher(Cipher);
+
+impl RecvCipher {
+    /// Body length announced by a packet header
+    fn packet_len(&self, header: &[u8]) -> usize {
+        let masked = u32::from_le_bytes(header[..LENGTH_SIZE].try_into().unwrap());
+        (masked ^ self.0.length_mask()) as usize
+    }
+
+    /// Decrypt one packet; any failure means tampering or a
+    /// desynchronised stream
+    fn open(&mut self, header: &[u8], body: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let nonce = self.0.next_nonce();
+        self.0
+            .aead
+            .decrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: body,
+                    aad: header,
+                },
+            )
+            .map_err(|_| "decryption failed")
+    }
+}
+
+/// Splits an encrypted stream into decrypted frames
+#[derive(Default)]
+pub struct PacketDecoder {
+    buf: Vec<u8>,
+}
+
+impl PacketDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Next frame, None if more data is needed
+    pub fn next_packet(&mut self, cipher: &mut RecvCipher) -> Result<Option<Vec<u8>>, &'static str> {
+        if self.buf.len() < LENGTH_SIZE {
+            return Ok(None);
+        }
+
+        let len = cipher.packet_len(&self.buf);
+        if !(TAG_SIZE..=MAX_PACKET_SIZE).contains(&len) {
+            return Err("bad packet length");
+        }
+
+        if self.buf.len() < LENGTH_SIZE + len {
+            return Ok(None);
+        }
+
+        let frame = cipher.open(&self.buf[..LENGTH_SIZE], &self.buf[LENGTH_SIZE..LENGTH_SIZE + len])?;
+        self.buf.drain(..LENGTH_SIZE + len);
+        Ok(Some(frame))
+    }
+
+    /// Remove and return the buffered bytes
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+}
+
+/// Write a file only its owner can read (Unix)
+fn write_private(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(data)
+}
+
+/// Restrict an existing file to its owner (Unix)
+fn make_private(path: &str) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+fn random_secret() -> SecretKey {
+    loop {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        if let Ok(sk) = SecretKey::from_slice(&bytes) {
+            return sk;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::wire::decode_frame;
+
+    fn pair() -> (Session, Session) {
+        let initiator = Handshake::new(true);
+        let responder = Handshake::new(false);
+        let (i_key, r_key) = (initiator.key_bytes(), responder.key_bytes());
+        (initiator.complete(r_key), responder.complete(i_key))
+    }
+
+    fn open_one(session: &mut Session, packet: &[u8]) -> Result<Option<Vec<u8>>, &'static str> {
+        let mut decoder = PacketDecoder::new();
+        decoder.feed(packet);
+        decoder.next_packet(&mut session.recv)
+    }
+
+    #[test]
+    fn sessions_agree_and_carry_frames_both_ways() {
+        let (mut a, mut b) = pair();
+        assert_eq!(a.id, b.id);
+
+        let mut decoder = PacketDecoder::new();
+        for i in 0..3u8 {
+            let frame = encode_frame("ping", &[i; 8]);
+            decoder.feed(&a.send.seal(&frame));
+            // Split packets are reassembled
+            let reply = b.send.seal(&frame);
+            let (head, tail) = reply.split_at(5);
+            assert_eq!(open_one(&mut a, head).unwrap(), None);
+            let mut whole = PacketDecoder::new();
+            whole.feed(head);
+            whole.feed(tail);
+            assert_eq!(whole.next_packet(&mut a.recv).unwrap(), Some(frame.clone()));
+        }
+        for i in 0..3u8 {
+            let frame = decoder.next_packet(&mut b.recv).unwrap().unwrap();
+            assert_eq!(decode_frame(&frame).unwrap().payload, vec![i; 8]);
+        }
+        assert_eq!(decoder.next_packet(&mut b.recv).unwrap(), None);
+    }
+
+    #[test]
+    fn key_exchange_and_lengths_look_random() {
+        let handshake = Handshake::new(true);
+        assert_ne!(handshake.key_bytes()[..NETWORK_MAGIC.len()], NETWORK_MAGIC);
+
+        // The same frame twice gives different length fields
+        let (mut a, _) = pair();
+        let frame = encode_frame("ping", &[0; 8]);
+        let first = a.send.seal(&frame);
+        let second = a.send.seal(&frame);
+        assert_eq!(first.len(), second.len());
+        assert_ne!(first[..LENGTH_SIZE], second[..LENGTH_SIZE]);
+        assert_ne!(
+            u32::from_le_bytes(first[..LENGTH_SIZE].try_into().unwrap()) as usize,
+            first.len() - LENGTH_SIZE
+        );
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let frame = encode_frame("ping", &[7; 8]);
+
+        // Body
+        let (mut a, mut b) = pair();
+        let mut packet = a.send.seal(&frame);
+        *packet.last_mut().unwrap() ^= 1;
+        assert!(open_one(&mut b, &packet).is_err());
+
+        // Length field
+        let (mut a, mut b) = pair();
+        let mut packet = a.send.seal(&frame);
+        packet[3] ^= 0x80;
+        assert!(open_one(&mut b, &packet).is_err());
+
+        // Reordered packets, or ones from another session
+        let (mut a, mut b) = pair();
+        let first = a.send.seal(&frame);
+        let second = a.send.seal(&frame);
+        assert!(open_one(&mut b, &second).is_err());
+        let (_, mut b) = pair();
+        assert!(open_one(&mut b, &first).is_err());
+    }
+
+    #[test]
+    fn auth_proves_the_node_key_once_per_role() {
+        let (a, b) = pair();
+        let key = NodeKey::generate();
+
+        let frame = a.auth_frame(&key);
+        let payload = decode_frame(&frame).unwrap().payload;
+        assert_eq!(b.verify_auth(&payload).unwrap(), key.public);
+
+        // Our own proof reflected back is refused
+        assert!(a.verify_auth(&payload).is_err());
+
+        // So is a proof from another session
+        let (_, other) = pair();
+        assert!(other.verify_auth(&payload).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_file_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("node_key_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("node_key.dat");
+        let path = path.to_str().unwrap();
+
+        write_private(path, &[1; 32]).unwrap();
+        assert_eq!(
+            fs::metadata(path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+        make_private(path).unwrap();
+        assert_eq!(
+            fs::metadata(path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
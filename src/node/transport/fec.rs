@@ -0,0 +1,288 @@
+/// Shards per code word (field size - 1)
+pub const MAX_SHARDS: usize = 255;
+
+/// x^8 + x^4 + x^3 + x^2 + 1
+const FIELD_POLY: u16 = 0x11d;
+
+struct Tables {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+const fn build_tables() -> Tables {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= FIELD_POLY;
+        }
+        i += 1;
+    }
+
+    // Doubled so a sum of two logs needs no reduction
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+
+    Tables { exp, log }
+}
+
+static TABLES: Tables = build_tables();
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    TABLES.exp[TABLES.log[a as usize] as usize + TABLES.log[b as usize] as usize]
+}
+
+fn inv(a: u8) -> u8 {
+    debug_assert!(a != 0);
+    TABLES.exp[255 - TABLES.log[a as usize] as usize]
+}
+
+/// out += coef * src
+fn mul_add(out: &mut [u8], src: &[u8], coef: u8) {
+    if coef == 0 {
+        return;
+    }
+    let log_c = TABLES.log[coef as usize] as usize;
+    for (o, s) in out.iter_mut().zip(src) {
+        if *s != 0 {
+            *o ^= TABLES.exp[log_c + TABLES.log[*s as usize] as usize];
+        }
+    }
+}
+
+/// Reed-Solomon erasure code over GF(2^8)
+///
+/// Systematic: the data shards are sent as they are, followed
+/// by parity shards. Any `data` of the `data + parity` shards
+/// rebuild the data, so up to `parity` lost shards per group
+/// are repaired without asking the sender for anything.
+///
+/// Parity rows come from a Cauchy matrix, which keeps every
+/// square selection of rows invertible.
+pub struct ReedSolomon {
+    data: usize,
+    parity: usize,
+}
+
+impl ReedSolomon {
+    pub fn new(data: usize, parity: usize) -> Result<Self, &'static str> {
+        if data == 0 {
+            return Err("no data shards");
+        }
+        if data + parity > MAX_SHARDS {
+            return Err("too many shards");
+        }
+        Ok(Self { data, parity })
+    }
+
+    pub fn data_shards(&self) -> usize {
+        self.data
+    }
+
+    pub fn parity_shards(&self) -> usize {
+        self.parity
+    }
+
+    /// Coefficient of data shard `col` in shard `row`
+    fn coef(&self, row: usize, col: usize) -> u8 {
+        if row < self.data {
+            return (row == col) as u8;
+        }
+        // Cauchy: 1 / (x_i + y_j), x_i = row, y_j = col (distinct)
+        inv((row as u8) ^ (col as u8))
+    }
+
+    /// Parity shards for `shards` (the data shards, equal length)
+    pub fn encode(&self, shards: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, &'static str> {
+        if shards.len() != self.data {
+            return Err("wrong number of data shards");
+        }
+        let len = shards[0].len();
+        if shards.iter().any(|s| s.len() != len) {
+            return Err("shards differ in length");
+        }
+
+        let parity = (self.data..self.data + self.parity)
+            .map(|row| {
+                let mut out = vec![0u8; len];
+                for (col, shard) in shards.iter().enumerate() {
+                    mul_add(&mut out, shard, self.coef(row, col));
+                }
+                out
+            })
+            .collect();
+
+        Ok(parity)
+    }
+
+    /// The data shards, from any `data` of all the shards
+    ///
+    /// `shards` is indexed like the code word: data shards,
+    /// then parity; None for every shard that was lost.
+    pub fn reconstruct(&self, shards: &[Option<Vec<u8>>]) -> Result<Vec<Vec<u8>>, &'static str> {
+        if shards.len() != self.data + self.parity {
+            return Err("wrong number of shards");
+        }
+
+        // Nothing lost among the data shards
+        if shards[..self.data].iter().all(Option::is_some) {
+            return Ok(shards[..self.data].iter().flatten().cloned().collect());
+        }
+
+        let present: Vec<usize> = (0..shards.len())
+            .filter(|&i| shards[i].is_some())
+            .take(self.data)
+            .collect();
+        if present.len() < self.data {
+            return Err("not enough shards");
+        }
+
+        let len = shards[present[0]].as_ref().map(Vec::len).unwrap_or(0);
+        if present.iter().any(|&i| shards[i].as_ref().map(Vec::len) != Some(len)) {
+            return Err("shards differ in length");
+        }
+
+        // Rows of the shards we have; inverted, they map what we
+        // have back to the data
+        let matrix: Vec<Vec<u8>> = present
+            .iter()
+            .map(|&row| (0..self.data).map(|col| self.coef(row, col)).collect())
+            .collect();
+        let decode = invert(matrix)?;
+
+        let data = decode
+            .iter()
+            .map(|coefs| {
+                let mut out = vec![0u8; len];
+                for (&coef, &i) in coefs.iter().zip(&present) {
+                    if let Some(shard) = &shards[i] {
+                        mul_add(&mut out, shard, coef);
+                    }
+                }
+                out
+            })
+            .collect();
+
+        Ok(data)
+    }
+}
+
+/// Gauss-Jordan inverse of a square matrix
+fn invert(mut m: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, &'static str> {
+    let n = m.len();
+    let mut out: Vec<Vec<u8>> = (0..n)
+        .map(|i| (0..n).map(|j| (i == j) as u8).collect())
+        .collect();
+
+    for col in 0..n {
+        let pivot = (col..n).find(|&r| m[r][col] != 0).ok_or("singular matrix")?;
+        m.swap(col, pivot);
+        out.swap(col, pivot);
+
+        let scale = inv(m[col][col]);
+        for j in 0..n {
+            m[col][j] = mul(m[col][j], scale);
+            out[col][j] = mul(out[col][j], scale);
+        }
+
+        for r in 0..n {
+            let factor = m[r][col];
+            if r == col || factor == 0 {
+                continue;
+            }
+            for j in 0..n {
+                m[r][j] ^= mul(factor, m[col][j]);
+                out[r][j] ^= mul(factor, out[col][j]);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_shards(count: usize, len: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| (0..len).map(|j| (i * 31 + j * 7) as u8).collect())
+            .collect()
+    }
+
+    fn code_word(code: &ReedSolomon, data: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+        let parity = code.encode(data).unwrap();
+        data.iter().cloned().chain(parity).map(Some).collect()
+    }
+
+    #[test]
+    fn rebuilds_with_any_parity_shards_lost() {
+        let (data, parity) = (4, 3);
+        let code = ReedSolomon::new(data, parity).unwrap();
+        let shards = data_shards(data, 16);
+        let word = code_word(&code, &shards);
+
+        // Every loss pattern of up to `parity` shards
+        for lost in 0u32..1 << (data + parity) {
+            if lost.count_ones() as usize > parity {
+                continue;
+            }
+            let mut received = word.clone();
+            for (i, shard) in received.iter_mut().enumerate() {
+                if lost & (1 << i) != 0 {
+                    *shard = None;
+                }
+            }
+            assert_eq!(
+                code.reconstruct(&received).unwrap(),
+                shards,
+                "lost {:07b}",
+                lost
+            );
+        }
+    }
+
+    #[test]
+    fn rebuilds_full_group() {
+        let code = ReedSolomon::new(128, 64).unwrap();
+        let shards = data_shards(128, 32);
+        let mut word = code_word(&code, &shards);
+
+        // Lose a data-heavy third: every other shard from the start
+        for i in (0..128).step_by(2) {
+            word[i] = None;
+        }
+        assert_eq!(code.reconstruct(&word).unwrap(), shards);
+    }
+
+    #[test]
+    fn refuses_more_loss_than_parity() {
+        let code = ReedSolomon::new(4, 2).unwrap();
+        let mut word = code_word(&code, &data_shards(4, 8));
+        for shard in &mut word[..3] {
+            *shard = None;
+        }
+        assert_eq!(code.reconstruct(&word), Err("not enough shards"));
+    }
+
+    #[test]
+    fn rejects_bad_shapes() {
+        assert!(ReedSolomon::new(0, 4).is_err());
+        assert!(ReedSolomon::new(200, 56).is_err());
+
+        let code = ReedSolomon::new(2, 1).unwrap();
+        assert!(code.encode(&data_shards(3, 4)).is_err());
+        assert!(code.encode(&[vec![0; 4], vec![0; 5]]).is_err());
+    }
+}
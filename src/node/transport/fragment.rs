@@ -0,0 +1,664 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::node::transport::fec::{ReedSolomon, MAX_SHARDS};
+use crate::node::wire::MAX_MESSAGE_SIZE;
+
+/// Starts every fragment
+pub const FRAGMENT_TAG: [u8; 4] = *b"SATF";
+const FRAGMENT_VERSION: u8 = 1;
+
+/// tag | version | message id | frame length | groups | group |
+/// data shards | parity shards | index | shard length | checksum
+pub const FRAGMENT_HEADER_SIZE: usize = 4 + 1 + 8 + 4 + 2 + 2 + 1 + 1 + 1 + 2 + 4;
+
+/// Data shards per group; larger frames are split into groups,
+/// each with its own parity
+pub const MAX_GROUP_SHARDS: usize = 128;
+
+/// Largest shard accepted (one fragment stays one datagram)
+pub const MAX_SHARD_SIZE: usize = 8 * 1024;
+
+/// Incomplete messages are given up after this long
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Buffered shards of incomplete messages, at most; the oldest
+/// message goes first
+const MAX_REASSEMBLY_BYTES: usize = 32 * 1024 * 1024;
+
+/// Completed messages are remembered this long, so a repeat
+/// broadcast of them is ignored
+const COMPLETED_MEMORY: Duration = Duration::from_secs(30 * 60);
+
+/// How frames are cut up and protected (POLICY ONLY, sender side;
+/// receivers read everything from the fragment headers)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FecParams {
+    /// Payload bytes per fragment; 1024 keeps a fragment
+    /// inside one Ethernet-sized datagram
+    pub shard_size: usize,
+
+    /// Parity shards per 100 data shards (50: any third of a
+    /// group may be lost)
+    pub parity_percent: usize,
+}
+
+impl Default for FecParams {
+    fn default() -> Self {
+        Self {
+            shard_size: 1024,
+            parity_percent: 50,
+        }
+    }
+}
+
+impl FecParams {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.shard_size == 0 || self.shard_size > MAX_SHARD_SIZE {
+            return Err("shard_size out of range");
+        }
+        Ok(())
+    }
+
+    /// Parity shards for a group of `data` shards
+    fn parity_for(&self, data: usize) -> usize {
+        (data * self.parity_percent).div_ceil(100).min(MAX_SHARDS - data)
+    }
+}
+
+/// Message id: first 8 bytes of the frame's SHA-256
+pub fn message_id(frame: &[u8]) -> [u8; 8] {
+    let hash = Sha256::digest(frame);
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&hash[..8]);
+    id
+}
+
+/// One shard of one group of a fragmented frame
+///
+/// Integers big-endian. The checksum (first 4 bytes of the
+/// SHA-256 of header and shard) catches corruption that a
+/// radio link or decoder lets through; an erasure code can
+/// only repair shards that are missing, not wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub message_id: [u8; 8],
+    /// Length of the whole frame
+    pub frame_len: u32,
+    pub groups: u16,
+    pub group: u16,
+    pub data_shards: u8,
+    pub parity_shards: u8,
+    /// Position in the group's code word (data, then parity)
+    pub index: u8,
+    pub shard: Vec<u8>,
+}
+
+impl Fragment {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(FRAGMENT_HEADER_SIZE + self.shard.len());
+        out.extend_from_slice(&FRAGMENT_TAG);
+        out.push(FRAGMENT_VERSION);
+        out.extend_from_slice(&self.message_id);
+        out.extend_from_slice(&self.frame_len.to_be_bytes());
+        out.extend_from_slice(&self.groups.to_be_bytes());
+        out.extend_from_slice(&self.group.to_be_bytes());
+        out.push(self.data_shards);
+        out.push(self.parity_shards);
+        out.push(self.index);
+        out.extend_from_slice(&(self.shard.len() as u16).to_be_bytes());
+
+        let sum = fragment_checksum(&out, &self.shard);
+        out.extend_from_slice(&sum);
+        out.extend_from_slice(&self.shard);
+        out
+    }
+
+    /// One complete fragment, checksum verified
+    pub fn decode(data: &[u8]) -> Result<Self, &'static str> {
+        if data.len() < FRAGMENT_HEADER_SIZE || data[..4] != FRAGMENT_TAG {
+            return Err("not a fragment");
+        }
+        if data[4] != FRAGMENT_VERSION {
+            return Err("unknown fragment version");
+        }
+
+        let shard_len = fragment_len(data).ok_or("bad shard length")? - FRAGMENT_HEADER_SIZE;
+        if data.len() != FRAGMENT_HEADER_SIZE + shard_len {
+            return Err("fragment length mismatch");
+        }
+
+        let (header, shard) = data.split_at(FRAGMENT_HEADER_SIZE);
+        let sum_at = FRAGMENT_HEADER_SIZE - 4;
+        if fragment_checksum(&header[..sum_at], shard) != header[sum_at..] {
+            return Err("bad fragment checksum");
+        }
+
+        Ok(Self {
+            message_id: header[5..13].try_into().unwrap(),
+            frame_len: u32::from_be_bytes(header[13..17].try_into().unwrap()),
+            groups: u16::from_be_bytes(header[17..19].try_into().unwrap()),
+            group: u16::from_be_bytes(header[19..21].try_into().unwrap()),
+            data_shards: header[21],
+            parity_shards: header[22],
+            index: header[23],
+            shard: shard.to_vec(),
+        })
+    }
+}
+
+fn fragment_checksum(header: &[u8], shard: &[u8]) -> [u8; 4] {
+    let mut hasher = Sha256::new();
+    hasher.update(header);
+    hasher.update(shard);
+    let hash = hasher.finalize();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Total length of the fragment whose header starts `data`
+fn fragment_len(data: &[u8]) -> Option<usize> {
+    let at = FRAGMENT_HEADER_SIZE - 6;
+    let shard_len = u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?) as usize;
+    if shard_len == 0 || shard_len > MAX_SHARD_SIZE {
+        return None;
+    }
+    Some(FRAGMENT_HEADER_SIZE + shard_len)
+}
+
+/// Data shards in each group of a frame of `len` bytes
+fn group_sizes(len: usize, shard_size: usize) -> Vec<usize> {
+    let shards = len.div_ceil(shard_size).max(1);
+    let groups = shards.div_ceil(MAX_GROUP_SHARDS);
+    (0..groups)
+        .map(|g| (shards - g * MAX_GROUP_SHARDS).min(MAX_GROUP_SHARDS))
+        .collect()
+}
+
+/// Cut a frame into encoded fragments, parity included
+///
+/// Fragments are interleaved across groups (shard 0 of every
+/// group, then shard 1, ...), so a burst of loss costs each
+/// group a little instead of one group a lot.
+pub fn fragment(frame: &[u8], params: &FecParams) -> Result<Vec<Vec<u8>>, &'static str> {
+    params.validate()?;
+    if frame.len() > MAX_MESSAGE_SIZE {
+        return Err("frame too large");
+    }
+
+    let id = message_id(frame);
+    let size = params.shard_size;
+    let sizes = group_sizes(frame.len(), size);
+
+    let mut words: Vec<Vec<Fragment>> = Vec::with_capacity(sizes.len());
+    let mut offset = 0;
+
+    for (group, &data) in sizes.iter().enumerate() {
+        let shards: Vec<Vec<u8>> = (0..data)
+            .map(|i| {
+                let start = (offset + i * size).min(frame.len());
+                let end = (start + size).min(frame.len());
+                let mut shard = frame[start..end].to_vec();
+                shard.resize(size, 0);
+                shard
+            })
+            .collect();
+        offset += data * size;
+
+        let parity = params.parity_for(data);
+        let code = ReedSolomon::new(data, parity)?;
+        let parity_shards = code.encode(&shards)?;
+
+        let word = shards
+            .into_iter()
+            .chain(parity_shards)
+            .enumerate()
+            .map(|(index, shard)| Fragment {
+                message_id: id,
+                frame_len: frame.len() as u32,
+                groups: sizes.len() as u16,
+                group: group as u16,
+                data_shards: data as u8,
+                parity_shards: parity as u8,
+                index: index as u8,
+                shard,
+            })
+            .collect();
+        words.push(word);
+    }
+
+    let longest = words.iter().map(Vec::len).max().unwrap_or(0);
+    let mut out = Vec::new();
+    for i in 0..longest {
+        for word in &words {
+            if let Some(f) = word.get(i) {
+                out.push(f.encode());
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Shards received so far for one group
+struct Group {
+    data: usize,
+    parity: usize,
+    shards: Vec<Option<Vec<u8>>>,
+    received: usize,
+    /// Decoded data shards
+    done: Option<Vec<Vec<u8>>>,
+}
+
+/// A frame being reassembled
+struct Partial {
+    frame_len: usize,
+    shard_len: usize,
+    groups: Vec<Group>,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Rebuilds frames from fragments, in any order, with up to
+/// each group's parity count of fragments missing
+///
+/// Fragments of several frames may be interleaved. Fragments
+/// that contradict what was received before are rejected.
+#[derive(Default)]
+pub struct Reassembler {
+    partial: HashMap<[u8; 8], Partial>,
+    completed: HashMap<[u8; 8], Instant>,
+    bytes: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one fragment; the whole frame once it can be rebuilt
+    pub fn push(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, &'static str> {
+        let f = Fragment::decode(data)?;
+        let id = f.message_id;
+
+        if self.completed.contains_key(&id) {
+            return Ok(None);
+        }
+
+        let frame_len = f.frame_len as usize;
+        let shard_len = f.shard.len();
+        if frame_len > MAX_MESSAGE_SIZE {
+            return Err("frame too large");
+        }
+
+        let sizes = group_sizes(frame_len, shard_len);
+        let group = f.group as usize;
+        let (data_shards, parity) = (f.data_shards as usize, f.parity_shards as usize);
+
+        if f.groups as usize != sizes.len()
+            || group >= sizes.len()
+            || data_shards != sizes[group]
+            || data_shards + parity > MAX_SHARDS
+            || f.index as usize >= data_shards + parity
+        {
+            return Err("inconsistent fragment");
+        }
+
+        if !self.partial.contains_key(&id) {
+            self.make_room(shard_len);
+            self.partial.insert(
+                id,
+                Partial {
+                    frame_len,
+                    shard_len,
+                    groups: sizes
+                        .iter()
+                        .map(|_| Group {
+                            data: 0,
+                            parity: 0,
+                            shards: Vec::new(),
+                            received: 0,
+                            done: None,
+                        })
+                        .collect(),
+                    bytes: 0,
+                    started: Instant::now(),
+                },
+            );
+        }
+
+        let p = self.partial.get_mut(&id).unwrap();
+        if p.frame_len != frame_len || p.shard_len != shard_len {
+            return Err("inconsistent fragment");
+        }
+
+        let g = &mut p.groups[group];
+        if g.done.is_none() && g.shards.is_empty() {
+            g.data = data_shards;
+            g.parity = parity;
+            g.shards = vec![None; data_shards + parity];
+        } else if g.data != data_shards || g.parity != parity {
+            return Err("inconsistent fragment");
+        }
+
+        // Decoded already, or a repeat
+        if g.done.is_some() || g.shards[f.index as usize].is_some() {
+            return Ok(None);
+        }
+
+        g.shards[f.index as usize] = Some(f.shard);
+        g.received += 1;
+        p.bytes += shard_len;
+        self.bytes += shard_len;
+
+        if g.received >= g.data {
+            let code = ReedSolomon::new(g.data, g.parity)?;
+            g.done = Some(code.reconstruct(&g.shards)?);
+
+            // Shards no longer needed
+            let freed = g.received * shard_len;
+            g.shards = Vec::new();
+            p.bytes -= freed;
+            self.bytes -= freed;
+        }
+
+        if p.groups.iter().any(|g| g.done.is_none()) {
+            return Ok(None);
+        }
+
+        let p = self.partial.remove(&id).unwrap();
+        self.bytes -= p.bytes;
+
+        let mut frame: Vec<u8> = p
+            .groups
+            .into_iter()
+            .flat_map(|g| g.done.unwrap_or_default())
+            .flatten()
+            .collect();
+        frame.truncate(p.frame_len);
+
+        if message_id(&frame) != id {
+            return Err("message id mismatch");
+        }
+
+        self.completed.insert(id, Instant::now());
+        Ok(Some(frame))
+    }
+
+    /// Drop messages that stalled and forget old completions
+    pub fn expire(&mut self) {
+        let mut freed = 0;
+        self.partial.retain(|_, p| {
+            let keep = p.started.elapsed() < REASSEMBLY_TIMEOUT;
+            if !keep {
+                freed += p.bytes;
+            }
+            keep
+        });
+        self.bytes -= freed;
+
+        self.completed.retain(|_, at| at.elapsed() < COMPLETED_MEMORY);
+    }
+
+    /// Frames still incomplete
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+
+    /// Evict the oldest messages until `incoming` more bytes fit
+    fn make_room(&mut self, incoming: usize) {
+        while self.bytes + incoming > MAX_REASSEMBLY_BYTES {
+            let oldest = self
+                .partial
+                .iter()
+                .min_by_key(|(_, p)| p.started)
+                .map(|(id, _)| *id);
+            let Some(oldest) = oldest else {
+                break;
+            };
+            if let Some(p) = self.partial.remove(&oldest) {
+                self.bytes -= p.bytes;
+            }
+        }
+    }
+}
+
+/// Splits a byte stream (file, pipe) into fragments
+///
+/// Garbage between fragments, and fragments that fail their
+/// checksum, are skipped by searching for the next tag.
+#[derive(Default)]
+pub struct FragmentStream {
+    buf: Vec<u8>,
+}
+
+impl FragmentStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Next fragment that passes its checksum, None if more
+    /// data is needed
+    pub fn next_fragment(&mut self) -> Option<Vec<u8>> {
+        loop {
+            // Resynchronise on the tag (keep a partial tag at the end)
+            match self.buf.windows(4).position(|w| w == FRAGMENT_TAG) {
+                Some(0) => {}
+                Some(at) => {
+                    self.buf.drain(..at);
+                }
+                None => {
+                    let keep = self.buf.len().min(3);
+                    self.buf.drain(..self.buf.len() - keep);
+                    return None;
+                }
+            }
+
+            if self.buf.len() < FRAGMENT_HEADER_SIZE {
+                return None;
+            }
+
+            let Some(len) = fragment_len(&self.buf) else {
+                self.buf.drain(..1);
+                continue;
+            };
+            if self.buf.len() < len {
+                return None;
+            }
+
+            if Fragment::decode(&self.buf[..len]).is_ok() {
+                return Some(self.buf.drain(..len).collect());
+            }
+            self.buf.drain(..1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 13 + i / 251) as u8).collect()
+    }
+
+    fn small_shards() -> FecParams {
+        FecParams {
+            shard_size: 64,
+            parity_percent: 50,
+        }
+    }
+
+    /// Push fragments until one completes the frame
+    fn reassemble<'a>(fragments: impl IntoIterator<Item = &'a Vec<u8>>) -> Option<Vec<u8>> {
+        let mut r = Reassembler::new();
+        let mut out = None;
+        for f in fragments {
+            if let Some(frame) = r.push(f).unwrap() {
+                assert!(out.is_none(), "frame completed twice");
+                out = Some(frame);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn round_trip_in_any_order() {
+        let data = frame(5000);
+        let mut fragments = fragment(&data, &FecParams::default()).unwrap();
+        fragments.reverse();
+        assert_eq!(reassemble(&fragments), Some(data));
+    }
+
+    #[test]
+    fn groups_are_interleaved() {
+        // 300 shards: groups of 128, 128 and 44 data shards
+        let data = frame(300 * 64);
+        let fragments = fragment(&data, &small_shards()).unwrap();
+        let decoded: Vec<Fragment> = fragments
+            .iter()
+            .map(|f| Fragment::decode(f).unwrap())
+            .collect();
+
+        assert!(decoded.iter().all(|f| f.groups == 3));
+        let first: Vec<(u16, u8)> = decoded[..6].iter().map(|f| (f.group, f.index)).collect();
+        assert_eq!(first, [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+
+        // The short group runs out first; the rest carry on
+        let tail: Vec<u16> = decoded[decoded.len() - 4..]
+            .iter()
+            .map(|f| f.group)
+            .collect();
+        assert_eq!(tail, [0, 1, 0, 1]);
+
+        assert_eq!(reassemble(&fragments), Some(data));
+    }
+
+    #[test]
+    fn rebuilds_with_parity_lost_in_every_group() {
+        let data = frame(300 * 64);
+        let fragments = fragment(&data, &small_shards()).unwrap();
+        let decoded: Vec<Fragment> = fragments
+            .iter()
+            .map(|f| Fragment::decode(f).unwrap())
+            .collect();
+
+        // Each group loses exactly its parity count, data shards first
+        let mut lost = [0usize; 3];
+        let kept: Vec<&Vec<u8>> = fragments
+            .iter()
+            .zip(&decoded)
+            .filter(|(_, f)| {
+                let g = f.group as usize;
+                if lost[g] < f.parity_shards as usize {
+                    lost[g] += 1;
+                    false
+                } else {
+                    true
+                }
+            })
+            .map(|(bytes, _)| bytes)
+            .collect();
+        assert_eq!(lost, [64, 64, 22]);
+        assert_eq!(reassemble(kept), Some(data.clone()));
+
+        // One more in any group is too many
+        let mut r = Reassembler::new();
+        for f in fragments
+            .iter()
+            .zip(&decoded)
+            .filter(|(_, f)| f.group != 2 || f.index > 22)
+        {
+            assert_eq!(r.push(f.0).unwrap(), None);
+        }
+        assert_eq!(r.pending(), 1);
+    }
+
+    #[test]
+    fn corrupted_fragments_are_skipped() {
+        let data = frame(2000);
+        let mut fragments = fragment(&data, &small_shards()).unwrap();
+        let last = fragments[4].len() - 1;
+        fragments[4][last] ^= 0x01;
+        fragments[9][FRAGMENT_HEADER_SIZE] ^= 0x80;
+
+        let mut r = Reassembler::new();
+        let mut out = None;
+        for f in &fragments {
+            match r.push(f) {
+                Ok(Some(frame)) => out = Some(frame),
+                Ok(None) => {}
+                Err(e) => assert_eq!(e, "bad fragment checksum"),
+            }
+        }
+        assert_eq!(out, Some(data));
+    }
+
+    #[test]
+    fn repeats_are_ignored_once_complete() {
+        let data = frame(700);
+        let fragments = fragment(&data, &FecParams::default()).unwrap();
+
+        let mut r = Reassembler::new();
+        let completed: usize = fragments
+            .iter()
+            .chain(&fragments)
+            .filter(|f| r.push(f).unwrap().is_some())
+            .count();
+        assert_eq!(completed, 1);
+        assert_eq!(r.pending(), 0);
+    }
+
+    #[test]
+    fn stream_resyncs_on_garbage() {
+        let data = frame(3000);
+        let mut fragments = fragment(&data, &small_shards()).unwrap();
+        // A damaged fragment in the stream is passed over too
+        fragments[2][FRAGMENT_HEADER_SIZE + 1] ^= 0xff;
+
+        let mut bytes = b"noise SAT SATF\x01 more noise".to_vec();
+        for (i, f) in fragments.iter().enumerate() {
+            bytes.extend_from_slice(f);
+            if i % 3 == 0 {
+                bytes.extend_from_slice(&[0x53, 0x41, 0x54, 0x00, 0xff]);
+            }
+        }
+        bytes.extend_from_slice(b"SA");
+
+        // Fed in awkward pieces
+        let mut stream = FragmentStream::new();
+        let mut out = Vec::new();
+        for piece in bytes.chunks(37) {
+            stream.feed(piece);
+            while let Some(f) = stream.next_fragment() {
+                out.push(f);
+            }
+        }
+
+        let expected: Vec<Vec<u8>> = fragments
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 2)
+            .map(|(_, f)| f.clone())
+            .collect();
+        assert_eq!(out, expected);
+        assert_eq!(reassemble(&out), Some(data));
+    }
+
+    #[test]
+    fn fragment_checks_params() {
+        let bad = FecParams {
+            shard_size: MAX_SHARD_SIZE + 1,
+            parity_percent: 50,
+        };
+        assert!(fragment(b"frame", &bad).is_err());
+        assert!(fragment(&frame(MAX_MESSAGE_SIZE + 1), &FecParams::default()).is_err());
+    }
+}
@@ -1,7 +1,236 @@
-use std::net::{UdpSocket, SocketAddr};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use rand::random;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::node::peer_id::{PeerId, TransportKind};
+use crate::node::transport::{EventFn, ReceiveFn, SourceTracker, Transport};
+use crate::node::wire::NETWORK_MAGIC;
+
+/// Starts every announcement datagram (P2P frames start with
+/// the network magic, so the two never collide)
+const ANNOUNCE_TAG: &[u8; 6] = b"GEOANN";
+const ANNOUNCE_VERSION: u8 = 1;
+/// tag | version | magic | node id | tcp port | height
+const ANNOUNCE_LEN: usize = 6 + 1 + 4 + 8 + 2 + 8;
+
+/// Presence announcement of older nodes; still filtered
+const LEGACY_ANNOUNCEMENT: &[u8] = b"GEO_BITCOIN_NODE";
+
+/// How often we announce ourselves
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Starts every flooded datagram
+const FLOOD_TAG: &[u8; 6] = b"GEOFLD";
+const FLOOD_VERSION: u8 = 1;
+
+/// Hops a flood travels unless configured
+pub const DEFAULT_HOP_LIMIT: u8 = 4;
+
+/// A forwarded flood waits up to this long
+const MAX_FORWARD_JITTER_MS: u64 = 250;
+
+/// Flood message ids are remembered this long, and at most
+/// this many
+const FLOOD_MEMORY: Duration = Duration::from_secs(10 * 60);
+const MAX_FLOOD_IDS: usize = 10_000;
+
+/// A LAN node silent this long (announcements included) is
+/// reported disconnected
+const NODE_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Current best height, for announcements
+pub type HeightFn = Arc<dyn Fn() -> u64 + Send + Sync>;
+/// Another node announced itself on the LAN
+pub type DiscoverFn = Arc<dyn Fn(LanNode) + Send + Sync>;
+
+/// LAN discovery message, broadcast every ANNOUNCE_INTERVAL
+///
+/// Fixed 29 bytes, integers big-endian. Never passed to
+/// `on_receive`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    /// Random per run; tells our own broadcasts apart
+    pub node_id: u64,
+    /// Network the node is on (`wire::NETWORK_MAGIC`)
+    pub magic: [u8; 4],
+    /// TCP listen port, 0 if the node takes no connections
+    pub tcp_port: u16,
+    pub height: u64,
+}
+
+impl Announcement {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(ANNOUNCE_LEN);
+        out.extend_from_slice(ANNOUNCE_TAG);
+        out.push(ANNOUNCE_VERSION);
+        out.extend_from_slice(&self.magic);
+        out.extend_from_slice(&self.node_id.to_be_bytes());
+        out.extend_from_slice(&self.tcp_port.to_be_bytes());
+        out.extend_from_slice(&self.height.to_be_bytes());
+        out
+    }
+
+    /// None unless a complete announcement of our version
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != ANNOUNCE_LEN
+            || &data[..6] != ANNOUNCE_TAG
+            || data[6] != ANNOUNCE_VERSION
+        {
+            return None;
+        }
+
+        Some(Self {
+            magic: data[7..11].try_into().ok()?,
+            node_id: u64::from_be_bytes(data[11..19].try_into().ok()?),
+            tcp_port: u16::from_be_bytes(data[19..21].try_into().ok()?),
+            height: u64::from_be_bytes(data[21..29].try_into().ok()?),
+        })
+    }
+}
+
+/// Discovery datagram, well-formed or not; never a P2P frame
+fn is_announcement(data: &[u8]) -> bool {
+    data.starts_with(ANNOUNCE_TAG) || data == LEGACY_ANNOUNCEMENT
+}
+
+/// A node on our network found through its announcement
+#[derive(Debug, Clone)]
+pub struct LanNode {
+    pub node_id: u64,
+    /// Its TCP listener (sender IP + announced port)
+    pub addr: SocketAddr,
+    pub height: u64,
+    /// Our node id is the lower one: we dial, it waits, so a
+    /// pair of LAN nodes opens a single connection
+    pub initiator: bool,
+}
+
+/// What we announce about ourselves
+pub struct LocalNode {
+    /// 0: take no connections (e.g. proxy-only nodes)
+    pub tcp_port: u16,
+    pub height: HeightFn,
+}
+
+/// Mesh settings (POLICY ONLY, `data/geo_config.json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeoOptions {
+    pub bind: String,
+
+    /// Where broadcasts go: one address per LAN segment (a
+    /// multi-homed node lists each interface's broadcast
+    /// address, e.g. "192.168.1.255:9333"). Empty: the limited
+    /// broadcast 255.255.255.255 on our port.
+    pub broadcast: Vec<SocketAddr>,
+
+    /// LAN hops a flooded block or transaction travels
+    pub hop_limit: u8,
+}
+
+impl Default for GeoOptions {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:9333".to_string(),
+            broadcast: vec![],
+            hop_limit: DEFAULT_HOP_LIMIT,
+        }
+    }
+}
+
+/// Flood envelope: tag | version | hops left | frame
+struct Flood<'a> {
+    hops: u8,
+    frame: &'a [u8],
+}
+
+impl<'a> Flood<'a> {
+    fn encode(hops: u8, frame: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(FLOOD_TAG.len() + 2 + frame.len());
+        out.extend_from_slice(FLOOD_TAG);
+        out.push(FLOOD_VERSION);
+        out.push(hops);
+        out.extend_from_slice(frame);
+        out
+    }
+
+    fn decode(data: &'a [u8]) -> Option<Self> {
+        let header = FLOOD_TAG.len() + 2;
+        if data.len() <= header
+            || !data.starts_with(FLOOD_TAG)
+            || data[FLOOD_TAG.len()] != FLOOD_VERSION
+        {
+            return None;
+        }
+
+        Some(Self {
+            hops: data[FLOOD_TAG.len() + 1],
+            frame: &data[header..],
+        })
+    }
+}
+
+/// Message id of a flooded frame: first 16 bytes of its SHA-256
+fn flood_id(frame: &[u8]) -> [u8; 16] {
+    let hash = Sha256::digest(frame);
+    let mut id = [0u8; 16];
+    id.copy_from_slice(&hash[..16]);
+    id
+}
+
+/// A flooded message we have seen
+struct SeenFlood {
+    at: Instant,
+    /// Hops it may still travel from here
+    hops_left: u8,
+    /// Already sent on by us (or originated here)
+    forwarded: bool,
+}
+
+/// Message ids seen recently, bounded in time and count
+#[derive(Default)]
+struct FloodMemory {
+    seen: HashMap<[u8; 16], SeenFlood>,
+}
+
+impl FloodMemory {
+    /// Record an id arriving with `hops_left`; false if known
+    fn arrived(&mut self, id: [u8; 16], hops_left: u8) -> bool {
+        if self.seen.contains_key(&id) {
+            return false;
+        }
+        self.insert(id, hops_left, false);
+        true
+    }
+
+    fn insert(&mut self, id: [u8; 16], hops_left: u8, forwarded: bool) {
+        if self.seen.len() >= MAX_FLOOD_IDS {
+            self.seen.retain(|_, s| s.at.elapsed() < FLOOD_MEMORY);
+        }
+        if self.seen.len() >= MAX_FLOOD_IDS {
+            let oldest = self.seen.iter().min_by_key(|(_, s)| s.at).map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        self.seen.insert(
+            id,
+            SeenFlood {
+                at: Instant::now(),
+                hops_left,
+                forwarded,
+            },
+        );
+    }
+}
 
 /// GEO / Mesh transport (LAN-based)
 ///
@@ -13,20 +242,57 @@ use std::time::Duration;
 /// - Internet-independent
 /// - Consensus-agnostic
 /// - Store-and-forward friendly
-pub struct GeoTransport;
+///
+/// Relayed blocks and transactions are flooded: broadcast with
+/// a hop limit, and passed on by every node that accepts them
+/// with one hop less, after a random delay (so nodes on the same
+/// segment do not all transmit at once). Message ids stop a
+/// node from handling or forwarding the same data twice, so
+/// data crosses segments joined by multi-homed nodes.
+///
+/// One datagram carries one frame, so frames over the UDP
+/// payload limit (~64 KiB) cannot be sent.
+pub struct GeoTransport {
+    node_id: u64,
+    socket: UdpSocket,
+    /// One per LAN segment
+    broadcast_addrs: Vec<SocketAddr>,
+    hop_limit: u8,
+    nodes: Arc<Mutex<SourceTracker>>,
+    floods: Arc<Mutex<FloodMemory>>,
+    /// Forwarded floods, sent once their delay is up
+    forwards: Sender<(Instant, Vec<u8>)>,
+}
 
 impl GeoTransport {
     /// Start GEO / LAN mesh transport
     ///
-    /// Example bind:
-    /// - "0.0.0.0:9333"
+    /// Every sender on the LAN is a peer (`geo:<ip:port>`).
+    /// Nodes announcing our network magic are handed to
+    /// `on_discover` (each announcement, so about every 5s).
     pub fn start(
-        bind: &str,
-        on_receive: Arc<dyn Fn(SocketAddr, Vec<u8>) + Send + Sync>,
-    ) {
-        let socket = UdpSocket::bind(bind)
+        options: GeoOptions,
+        local: LocalNode,
+        on_receive: ReceiveFn,
+        on_event: EventFn,
+        on_discover: DiscoverFn,
+    ) -> Arc<Self> {
+        let socket = UdpSocket::bind(&options.bind)
             .expect("GEO UDP bind failed");
 
+        println!("🌍 GEO mesh listening on {}", options.bind);
+
+        Self::run(socket, options, local, on_receive, on_event, on_discover)
+    }
+
+    fn run(
+        socket: UdpSocket,
+        options: GeoOptions,
+        local: LocalNode,
+        on_receive: ReceiveFn,
+        on_event: EventFn,
+        on_discover: DiscoverFn,
+    ) -> Arc<Self> {
         socket
             .set_broadcast(true)
             .expect("Failed to enable broadcast");
@@ -35,20 +301,86 @@ impl GeoTransport {
             .set_read_timeout(Some(Duration::from_secs(2)))
             .ok();
 
-        println!("🌍 GEO mesh listening on {}", bind);
+        let mut broadcast_addrs = options.broadcast;
+        if broadcast_addrs.is_empty() {
+            let port = socket.local_addr().map(|a| a.port()).unwrap_or(9333);
+            broadcast_addrs.push(SocketAddr::new(Ipv4Addr::BROADCAST.into(), port));
+        }
+
+        // ───────── Forwarding thread ─────────
+        let (forwards, queued) = channel();
+        let forward_socket = socket.try_clone().unwrap();
+        let forward_addrs = broadcast_addrs.clone();
+        thread::spawn(move || forward_floods(forward_socket, forward_addrs, queued));
+
+        let transport = Arc::new(Self {
+            node_id: random(),
+            socket: socket.try_clone().unwrap(),
+            broadcast_addrs,
+            hop_limit: options.hop_limit.max(1),
+            nodes: Arc::new(Mutex::new(SourceTracker::new(NODE_IDLE_TIMEOUT, on_event))),
+            floods: Arc::new(Mutex::new(FloodMemory::default())),
+            forwards,
+        });
 
         // ───────── Receiver thread ─────────
-        let recv_socket = socket.try_clone().unwrap();
-        let on_receive_recv = Arc::clone(&on_receive);
+        let recv_socket = socket;
+        let nodes = Arc::clone(&transport.nodes);
+        let floods = Arc::clone(&transport.floods);
+        let node_id = transport.node_id;
 
         thread::spawn(move || {
             let mut buf = vec![0u8; 1024 * 1024];
 
             loop {
+                nodes.lock().unwrap().expire();
+
                 match recv_socket.recv_from(&mut buf) {
                     Ok((n, src)) => {
-                        // Feed bytes directly into P2P
-                        (on_receive_recv)(src, buf[..n].to_vec());
+                        let data = &buf[..n];
+                        let peer = PeerId::Geo(src);
+
+                        if let Some(flood) = Flood::decode(data) {
+                            // Seen before (our own broadcasts included):
+                            // dropped without a trace
+                            let id = flood_id(flood.frame);
+                            let hops_left = flood.hops.saturating_sub(1);
+                            if !floods.lock().unwrap().arrived(id, hops_left) {
+                                continue;
+                            }
+
+                            nodes.lock().unwrap().seen(&peer);
+                            (on_receive)(peer, flood.frame.to_vec());
+                            continue;
+                        }
+
+                        if !is_announcement(data) {
+                            // One datagram = one frame
+                            nodes.lock().unwrap().seen(&peer);
+                            (on_receive)(peer, data.to_vec());
+                            continue;
+                        }
+
+                        // Legacy or unknown version: present, nothing more
+                        let Some(ann) = Announcement::decode(data) else {
+                            nodes.lock().unwrap().seen(&peer);
+                            continue;
+                        };
+
+                        // Our own broadcast, or another network
+                        if ann.node_id == node_id || ann.magic != NETWORK_MAGIC {
+                            continue;
+                        }
+
+                        nodes.lock().unwrap().seen(&peer);
+                        if ann.tcp_port != 0 {
+                            (on_discover)(LanNode {
+                                node_id: ann.node_id,
+                                addr: SocketAddr::new(src.ip(), ann.tcp_port),
+                                height: ann.height,
+                                initiator: node_id < ann.node_id,
+                            });
+                        }
                     }
                     Err(_) => {
                         // Timeout or temporary error
@@ -59,18 +391,288 @@ impl GeoTransport {
         });
 
         // ───────── Announce thread ─────────
-        thread::spawn(move || {
-            let broadcast_addr: SocketAddr =
-                "255.255.255.255:9333".parse().unwrap();
+        let announcer = Arc::clone(&transport);
+        thread::spawn(move || loop {
+            let ann = Announcement {
+                node_id: announcer.node_id,
+                magic: NETWORK_MAGIC,
+                tcp_port: local.tcp_port,
+                height: (local.height)(),
+            };
+            announcer.send_all(&ann.encode());
 
-            loop {
-                // Minimal presence announcement
-                let announce = b"GEO_BITCOIN_NODE";
+            thread::sleep(ANNOUNCE_INTERVAL);
+        });
+
+        transport
+    }
+
+    pub fn node_id(&self) -> u64 {
+        self.node_id
+    }
+
+    /// One datagram to every segment
+    fn send_all(&self, data: &[u8]) {
+        for addr in &self.broadcast_addrs {
+            let _ = self.socket.send_to(data, addr);
+        }
+    }
+}
+
+impl Transport for GeoTransport {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Geo
+    }
+
+    fn send(&self, peer: &PeerId, data: &[u8]) {
+        if let PeerId::Geo(addr) = peer {
+            let _ = self.socket.send_to(data, addr);
+        }
+    }
+
+    /// Flood a frame to every segment
+    ///
+    /// A frame that arrived by flooding is passed on with the
+    /// hops it has left (none: not at all), after a random
+    /// delay; anything else starts a new flood with the full
+    /// hop limit, at once. Each frame goes out once.
+    fn broadcast(&self, data: &[u8]) {
+        let id = flood_id(data);
+
+        let hops = {
+            let mut floods = self.floods.lock().unwrap();
+            match floods.seen.get_mut(&id) {
+                Some(seen) if seen.forwarded || seen.hops_left == 0 => return,
+                Some(seen) => {
+                    seen.forwarded = true;
+                    Some(seen.hops_left)
+                }
+                None => {
+                    floods.insert(id, self.hop_limit, true);
+                    None
+                }
+            }
+        };
+
+        match hops {
+            None => self.send_all(&Flood::encode(self.hop_limit, data)),
+            Some(hops) => {
+                let delay = Duration::from_millis(random::<u64>() % (MAX_FORWARD_JITTER_MS + 1));
+                let _ = self
+                    .forwards
+                    .send((Instant::now() + delay, Flood::encode(hops, data)));
+            }
+        }
+    }
+
+    /// Relays go out as one flood, not a datagram per node
+    fn floods(&self) -> bool {
+        true
+    }
+
+    /// LAN nodes heard from recently
+    fn peers(&self) -> Vec<PeerId> {
+        self.nodes.lock().unwrap().peers()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+}
+
+/// Send forwarded floods to every segment when they fall due
+///
+/// Runs until the transport (the sending side) is dropped.
+fn forward_floods(
+    socket: UdpSocket,
+    addrs: Vec<SocketAddr>,
+    queued: Receiver<(Instant, Vec<u8>)>,
+) {
+    let mut pending: Vec<(Instant, Vec<u8>)> = Vec::new();
+
+    loop {
+        let next_due = pending.iter().map(|(due, _)| *due).min();
+        let received = match next_due {
+            Some(due) => queued.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => queued.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
 
-                let _ = socket.send_to(announce, broadcast_addr);
+        match received {
+            Ok(forward) => pending.push(forward),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
 
-                thread::sleep(Duration::from_secs(5));
+        let now = Instant::now();
+        pending.retain(|(due, datagram)| {
+            if *due > now {
+                return true;
             }
+            for addr in &addrs {
+                let _ = socket.send_to(datagram, addr);
+            }
+            false
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::OnceLock;
+
+    /// In-process LAN segments over loopback
+    ///
+    /// Every node is a GeoTransport on 127.0.0.1 whose broadcasts
+    /// reach only the nodes sharing one of its segments; a node in
+    /// two segments is the multi-homed bridge between them. Each
+    /// node accepts every frame it receives (as a node accepts a
+    /// valid block) and relays it, so floods travel as they would
+    /// across real segments.
+    struct MeshLab {
+        nodes: Vec<Arc<GeoTransport>>,
+        received: Vec<Arc<Mutex<Vec<Vec<u8>>>>>,
+    }
+
+    impl MeshLab {
+        /// `segments`: node indexes on each segment, e.g.
+        /// `[[0, 1], [1, 2]]` is two segments bridged by node 1
+        fn start(segments: &[Vec<usize>], hop_limit: u8) -> io::Result<Self> {
+            let count = segments.iter().flatten().max().map(|n| n + 1).unwrap_or(0);
+
+            let mut sockets = Vec::with_capacity(count);
+            for _ in 0..count {
+                sockets.push(UdpSocket::bind("127.0.0.1:0")?);
+            }
+            let addrs: Vec<SocketAddr> = sockets
+                .iter()
+                .map(|s| s.local_addr())
+                .collect::<io::Result<_>>()?;
+
+            let mut nodes = Vec::with_capacity(count);
+            let mut received = Vec::with_capacity(count);
+
+            for (i, socket) in sockets.into_iter().enumerate() {
+                // Everyone sharing a segment with node i
+                let mut broadcast: Vec<SocketAddr> = segments
+                    .iter()
+                    .filter(|seg| seg.contains(&i))
+                    .flatten()
+                    .filter(|&&n| n != i)
+                    .map(|&n| addrs[n])
+                    .collect();
+                broadcast.sort();
+                broadcast.dedup();
+
+                let log: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+                let this: Arc<OnceLock<Arc<GeoTransport>>> = Arc::new(OnceLock::new());
+
+                let on_receive: ReceiveFn = {
+                    let (log, this) = (Arc::clone(&log), Arc::clone(&this));
+                    Arc::new(move |_, frame: Vec<u8>| {
+                        if let Some(node) = this.get() {
+                            node.broadcast(&frame);
+                        }
+                        log.lock().unwrap().push(frame);
+                    })
+                };
+
+                let options = GeoOptions {
+                    bind: addrs[i].to_string(),
+                    broadcast,
+                    hop_limit,
+                };
+                let local = LocalNode {
+                    tcp_port: 0,
+                    height: Arc::new(|| 0),
+                };
+
+                let node = GeoTransport::run(
+                    socket,
+                    options,
+                    local,
+                    on_receive,
+                    Arc::new(|_| {}),
+                    Arc::new(|_| {}),
+                );
+                let _ = this.set(Arc::clone(&node));
+
+                nodes.push(node);
+                received.push(log);
+            }
+
+            Ok(Self { nodes, received })
+        }
+
+        /// Start a flood at node `from`
+        fn flood(&self, from: usize, frame: &[u8]) {
+            self.nodes[from].broadcast(frame);
+        }
+
+        /// Frames node `i` has received, in order
+        fn received(&self, i: usize) -> Vec<Vec<u8>> {
+            self.received[i].lock().unwrap().clone()
+        }
+
+        fn counts(&self) -> Vec<usize> {
+            self.received.iter().map(|r| r.lock().unwrap().len()).collect()
+        }
+
+        /// Wait until something arrived and no node's count
+        /// changed for longer than a forward can be delayed
+        fn settle(&self) {
+            let quiet_for = Duration::from_millis(4 * MAX_FORWARD_JITTER_MS);
+            let deadline = Instant::now() + Duration::from_secs(30);
+
+            let mut counts = self.counts();
+            let mut changed = Instant::now();
+
+            while Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(20));
+
+                let now = self.counts();
+                if now != counts {
+                    counts = now;
+                    changed = Instant::now();
+                } else if counts.iter().any(|&n| n > 0) && changed.elapsed() >= quiet_for {
+                    return;
+                }
+            }
+        }
+    }
+
+    const FRAME: &[u8] = b"REV3 mesh test frame";
+
+    /// Flood from node 0 along the chain 0-1-2-3; what each
+    /// node received once things settled
+    fn flood_chain(hop_limit: u8) -> Vec<Vec<Vec<u8>>> {
+        let segments = [vec![0, 1], vec![1, 2], vec![2, 3]];
+        let lab = MeshLab::start(&segments, hop_limit).unwrap();
+
+        lab.flood(0, FRAME);
+        lab.settle();
+
+        (0..4).map(|i| lab.received(i)).collect()
+    }
+
+    #[test]
+    fn flood_crosses_bridges_within_hop_limit() {
+        let received = flood_chain(3);
+
+        assert!(received[0].is_empty(), "origin got its own flood back");
+        for node in &received[1..] {
+            assert_eq!(node, &vec![FRAME.to_vec()]);
+        }
+    }
+
+    #[test]
+    fn flood_stops_at_hop_limit() {
+        let received = flood_chain(2);
+
+        assert!(received[0].is_empty());
+        assert_eq!(received[1], vec![FRAME.to_vec()]);
+        assert_eq!(received[2], vec![FRAME.to_vec()]);
+        assert!(received[3].is_empty(), "flood went past its hop limit");
+    }
+}
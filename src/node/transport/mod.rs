@@ -1,15 +1,154 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::node::peer_id::{PeerId, TransportKind};
 
 // ───────── Transport implementations ─────────
 pub mod tcp;
+pub mod encryption;
+pub mod socks5;
 pub mod bluetooth;
+pub mod ble_framing;
 pub mod satellite;
+pub mod fec;
+pub mod fragment;
 pub mod geo;
 pub mod offline;
 
+// ───────── Callbacks ─────────
+/// One complete frame from a peer
+pub type ReceiveFn = Arc<dyn Fn(PeerId, Vec<u8>) + Send + Sync>;
+pub type EventFn = Arc<dyn Fn(PeerEvent) + Send + Sync>;
+
+// ───────── Connection events ─────────
+/// Reported by every transport; one-way transports report a
+/// source when it first delivers data and when it goes away
+#[derive(Debug, Clone)]
+pub enum PeerEvent {
+    /// `encrypted`: traffic is encrypted and authenticated
+    Connected { peer: PeerId, inbound: bool, encrypted: bool },
+    /// Peer sent bytes that are not a valid frame (the
+    /// connection is closed right after)
+    Malformed(PeerId),
+    Disconnected(PeerId),
+}
+
+// ───────── Connectionless sources ─────────
+/// Connected / Disconnected events for datagram senders
+///
+/// A sender counts as connected from its first datagram until
+/// it has been silent for `idle`.
+pub struct SourceTracker {
+    last_seen: HashMap<PeerId, Instant>,
+    idle: Duration,
+    on_event: EventFn,
+}
+
+impl SourceTracker {
+    pub fn new(idle: Duration, on_event: EventFn) -> Self {
+        Self {
+            last_seen: HashMap::new(),
+            idle,
+            on_event,
+        }
+    }
+
+    /// Datagram from `peer`; reported Connected if new
+    pub fn seen(&mut self, peer: &PeerId) {
+        let new = self.last_seen.insert(peer.clone(), Instant::now()).is_none();
+        if new {
+            (self.on_event)(PeerEvent::Connected {
+                peer: peer.clone(),
+                inbound: true,
+                encrypted: false,
+            });
+        }
+    }
+
+    /// Senders currently counted as connected
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.last_seen.keys().cloned().collect()
+    }
+
+    /// Report senders silent for `idle` as Disconnected
+    pub fn expire(&mut self) {
+        let idle = self.idle;
+        let mut gone = Vec::new();
+        self.last_seen.retain(|peer, seen| {
+            let keep = seen.elapsed() < idle;
+            if !keep {
+                gone.push(peer.clone());
+            }
+            keep
+        });
+
+        for peer in gone {
+            (self.on_event)(PeerEvent::Disconnected(peer));
+        }
+    }
+}
+
+/// Counters below the message level (`/transportstats`)
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LinkStats {
+    /// Fragments transmitted, data and parity
+    pub fragments_sent: u64,
+    /// Completed passes over the carousel
+    pub carousel_cycles: u64,
+}
+
 // ───────── Transport trait ─────────
+///
+/// `data` is always one complete wire frame (see `node::wire`);
+/// stream transports must write it in one piece.
 pub trait Transport: Send + Sync {
-    fn send(&self, addr: &SocketAddr, data: &[u8]);
+    /// Kind of every PeerId this transport reports
+    fn kind(&self) -> TransportKind;
+
+    /// Peers of other transports are ignored
+    fn send(&self, peer: &PeerId, data: &[u8]);
     fn broadcast(&self, data: &[u8]);
-    fn peers(&self) -> Vec<SocketAddr>;
+    fn peers(&self) -> Vec<PeerId>;
+
+    /// Relays are handed to `broadcast` once (a shared medium
+    /// that reaches every peer) instead of sent to each peer
+    fn floods(&self) -> bool {
+        false
+    }
+
+    /// Open an outbound connection (connectionless transports: false)
+    fn connect(&self, _addr: &SocketAddr) -> bool {
+        false
+    }
+
+    /// Open an outbound connection to a host name (onion service)
+    ///
+    /// Returns the address the connection is reported under
+    /// (PeerId::Tcp of `netaddr::host_socket_addr`), None if
+    /// host names cannot be reached.
+    fn connect_host(&self, _host: &str, _port: u16) -> Option<SocketAddr> {
+        None
+    }
+
+    /// `connect_host` works (a proxy is configured)
+    fn reaches_hosts(&self) -> bool {
+        false
+    }
+
+    /// Close a connection
+    fn disconnect(&self, _peer: &PeerId) {}
+
+    /// Address we accept connections on, if any
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Link-level counters, for transports that keep them
+    fn link_stats(&self) -> Option<LinkStats> {
+        None
+    }
 }
@@ -1,9 +1,13 @@
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::net::SocketAddr;
-use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::node::bundle::{Bundle, BUNDLE_TAG};
+use crate::node::message::NetworkMessage;
+use crate::node::peer_id::PeerId;
+use crate::node::transport::{EventFn, PeerEvent, ReceiveFn};
+use crate::node::wire::encode_message;
+
 /// Offline / Store-and-Forward transport
 ///
 /// Allows Bitcoin messages to be exported to disk and later
@@ -13,6 +17,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 /// - USB / SD card transfer
 /// - Air-gapped systems
 /// - Delayed connectivity
+///
+/// For syncing a node, prefer a sync bundle (`node::bundle`):
+/// checksummed, optionally signed, and applied with a report.
 pub struct OfflineTransport;
 
 impl OfflineTransport {
@@ -41,20 +48,62 @@ impl OfflineTransport {
 
     /// Import offline messages from a file
     ///
+    /// The file is one peer, `offline:<path>`, connected for the
+    /// duration of the import. It is either a sync bundle,
+    /// delivered whole once verified, or exported messages.
+    /// Returns how many messages were delivered; a truncated
+    /// file is an error, after its complete messages.
+    ///
     /// Example:
-    /// OfflineTransport::import("offline.msg", on_receive)
-    pub fn import(
-        path: &str,
-        on_receive: Arc<dyn Fn(SocketAddr, Vec<u8>) + Send + Sync>,
-    ) {
-        let mut file = File::open(path)
-            .expect("Offline import open failed");
-
+    /// OfflineTransport::import("offline.msg", on_receive, on_event)
+    pub fn import(path: &str, on_receive: ReceiveFn, on_event: EventFn) -> Result<usize, &'static str> {
         let mut buf = Vec::new();
-        file.read_to_end(&mut buf).unwrap();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut buf))
+            .map_err(|_| "cannot read offline file")?;
+
+        // A bundle is verified before anything is delivered
+        let bundle: Option<Vec<Vec<u8>>> = if buf.starts_with(&BUNDLE_TAG) {
+            let bundle = Bundle::decode(&buf)?;
+            let blocks = bundle.blocks.into_iter().map(NetworkMessage::Block);
+            let txs = bundle.transactions.into_iter().map(NetworkMessage::Transaction);
+            Some(blocks.chain(txs).map(|m| encode_message(&m)).collect())
+        } else {
+            None
+        };
+
+        let peer = PeerId::Offline(path.to_string());
+        (on_event)(PeerEvent::Connected {
+            peer: peer.clone(),
+            inbound: true,
+            encrypted: false,
+        });
+
+        let result = match bundle {
+            Some(messages) => {
+                let count = messages.len();
+                for data in messages {
+                    (on_receive)(peer.clone(), data);
+                }
+                Ok(count)
+            }
+            None => Self::deliver_exported(&buf, &peer, &on_receive),
+        };
+
+        (on_event)(PeerEvent::Disconnected(peer));
+        result
+    }
 
+    /// Messages written by `export`: [timestamp u64][len u32][data]
+    fn deliver_exported(buf: &[u8], peer: &PeerId, on_receive: &ReceiveFn) -> Result<usize, &'static str> {
         let mut i = 0;
-        while i + 12 <= buf.len() {
+        let mut count = 0;
+
+        while i < buf.len() {
+            if i + 12 > buf.len() {
+                return Err("offline file truncated");
+            }
+
             let _timestamp =
                 u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
             i += 8;
@@ -64,15 +113,16 @@ impl OfflineTransport {
             i += 4;
 
             if i + len > buf.len() {
-                break;
+                return Err("offline file truncated");
             }
 
             let data = buf[i..i + len].to_vec();
             i += len;
 
-            // Dummy address for offline source
-            let addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
-            (on_receive)(addr, data);
+            (on_receive)(peer.clone(), data);
+            count += 1;
         }
+
+        Ok(count)
     }
 }
@@ -1,16 +1,46 @@
-use std::net::{UdpSocket, SocketAddr};
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::io::{Read};
-use std::fs::File;
+use std::io::{self, Read, Write};
+use std::fs::{File, OpenOptions};
 use std::time::Duration;
 
+use rand::random;
+use serde::{Deserialize, Serialize};
+
+use crate::node::peer_id::{PeerId, TransportKind};
+use crate::node::transport::fragment::{
+    fragment, message_id, FecParams, FragmentStream, Reassembler, FRAGMENT_TAG,
+};
+use crate::node::transport::{EventFn, LinkStats, PeerEvent, ReceiveFn, SourceTracker, Transport};
+use crate::node::wire::{FrameDecoder, NETWORK_MAGIC};
+
+/// A decoder silent this long is reported disconnected
+const FEED_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Fragments of live relays waiting to go up; beyond this the
+/// oldest are dropped (the carousel repeats blocks anyway)
+const MAX_LIVE_FRAGMENTS: usize = 8192;
+
+/// Pause between carousel cycles when there is nothing to send
+const CAROUSEL_IDLE: Duration = Duration::from_secs(1);
+
+/// Frames the carousel repeats, fetched again every cycle
+pub type CarouselFn = Arc<dyn Fn() -> Vec<Vec<u8>> + Send + Sync>;
+
 /// Receive-only satellite transport
 ///
-/// This transport ingests raw NetworkMessage bytes from an
+/// This transport ingests framed NetworkMessages from an
 /// external satellite decoder (UDP or file pipe) and injects
 /// them into the normal P2P message handler.
 ///
+/// Broadcasts are fragmented with erasure coding (see
+/// `fragment`): frames of any size are rebuilt from whatever
+/// fragments arrive, as long as no group lost more than its
+/// parity. Unfragmented frames (one per datagram, or a plain
+/// frame stream) are still accepted.
+///
 /// Consensus rules are NOT bypassed.
 /// Validation remains identical to TCP/Bluetooth/etc.
 pub struct SatelliteTransport {
@@ -20,12 +50,13 @@ pub struct SatelliteTransport {
 impl SatelliteTransport {
     /// Start satellite ingestion from a UDP socket
     ///
+    /// Each sending decoder is a peer (`sat:<ip:port>`).
+    /// Fragments from all decoders are pooled, so two dishes
+    /// receiving the same broadcast make up for each other.
+    ///
     /// Example use:
-    /// SatelliteTransport::listen_udp("0.0.0.0:9999", on_receive)
-    pub fn listen_udp(
-        bind: &str,
-        on_receive: Arc<dyn Fn(SocketAddr, Vec<u8>) + Send + Sync>,
-    ) {
+    /// SatelliteTransport::listen_udp("0.0.0.0:9999", on_receive, on_event)
+    pub fn listen_udp(bind: &str, on_receive: ReceiveFn, on_event: EventFn) {
         let socket = UdpSocket::bind(bind)
             .expect("Satellite UDP bind failed");
 
@@ -37,12 +68,30 @@ impl SatelliteTransport {
 
         thread::spawn(move || {
             let mut buf = vec![0u8; 1024 * 1024];
+            let mut feeds = SourceTracker::new(FEED_IDLE_TIMEOUT, on_event);
+            let mut reassembler = Reassembler::new();
 
             loop {
+                feeds.expire();
+                reassembler.expire();
+
                 match socket.recv_from(&mut buf) {
                     Ok((n, src)) => {
-                        // Inject bytes directly into P2P
-                        (on_receive)(src, buf[..n].to_vec());
+                        let data = &buf[..n];
+                        let peer = PeerId::Satellite(src.to_string());
+                        feeds.seen(&peer);
+
+                        if !data.starts_with(&FRAGMENT_TAG) {
+                            // One datagram = one frame; inject into P2P
+                            (on_receive)(peer, data.to_vec());
+                            continue;
+                        }
+
+                        // Damaged or inconsistent fragments are
+                        // line noise, not misbehaviour: dropped
+                        if let Ok(Some(frame)) = reassembler.push(data) {
+                            (on_receive)(peer, frame);
+                        }
                     }
                     Err(_) => {
                         // Timeout or temporary error
@@ -57,19 +106,28 @@ impl SatelliteTransport {
     ///
     /// This is ideal for SDR decoders that write to stdout or FIFO.
     ///
+    /// The feed is a single peer, `sat:<path>`. It carries either
+    /// fragments or plain frames, told apart by its first bytes.
+    ///
     /// Example:
-    /// SatelliteTransport::listen_file("/tmp/bitcoin.sat", on_receive)
-    pub fn listen_file(
-        path: &str,
-        on_receive: Arc<dyn Fn(SocketAddr, Vec<u8>) + Send + Sync>,
-    ) {
+    /// SatelliteTransport::listen_file("/tmp/bitcoin.sat", on_receive, on_event)
+    pub fn listen_file(path: &str, on_receive: ReceiveFn, on_event: EventFn) {
         println!("🛰 Satellite file ingest from {}", path);
 
         let mut file = File::open(path)
             .expect("Satellite file open failed");
+        let path = path.to_string();
 
         thread::spawn(move || {
             let mut buf = vec![0u8; 1024 * 1024];
+            let mut format = FeedFormat::Unknown(Vec::new());
+
+            let feed = PeerId::Satellite(path);
+            (on_event)(PeerEvent::Connected {
+                peer: feed.clone(),
+                inbound: true,
+                encrypted: false,
+            });
 
             loop {
                 match file.read(&mut buf) {
@@ -78,9 +136,10 @@ impl SatelliteTransport {
                         thread::sleep(Duration::from_millis(200));
                     }
                     Ok(n) => {
-                        // Use a dummy address to represent satellite source
-                        let sat_addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
-                        (on_receive)(sat_addr, buf[..n].to_vec());
+                        // A pipe is a byte stream: reassemble frames
+                        for frame in format.feed(&buf[..n]) {
+                            (on_receive)(feed.clone(), frame);
+                        }
                     }
                     Err(_) => {
                         thread::sleep(Duration::from_millis(500));
@@ -90,3 +149,403 @@ impl SatelliteTransport {
         });
     }
 }
+
+/// What a file / pipe feed carries
+enum FeedFormat {
+    /// Fewer than 4 bytes so far
+    Unknown(Vec<u8>),
+    Frames(FrameDecoder),
+    Fragments(FragmentStream, Reassembler),
+}
+
+impl FeedFormat {
+    /// Complete frames in the stream so far
+    fn feed(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        if let FeedFormat::Unknown(head) = self {
+            head.extend_from_slice(data);
+            if head.len() < NETWORK_MAGIC.len() {
+                return Vec::new();
+            }
+
+            let head = std::mem::take(head);
+            *self = if head.starts_with(&NETWORK_MAGIC) {
+                FeedFormat::Frames(FrameDecoder::new())
+            } else {
+                FeedFormat::Fragments(FragmentStream::new(), Reassembler::new())
+            };
+            return self.feed(&head);
+        }
+
+        let mut frames = Vec::new();
+        match self {
+            FeedFormat::Frames(decoder) => {
+                decoder.feed(data);
+                loop {
+                    match decoder.next_frame() {
+                        Ok(Some(frame)) => frames.push(frame),
+                        Ok(None) => break,
+                        Err(_) => {
+                            // Lost sync (decoder glitch): start over
+                            *decoder = FrameDecoder::new();
+                            break;
+                        }
+                    }
+                }
+            }
+            FeedFormat::Fragments(stream, reassembler) => {
+                stream.feed(data);
+                reassembler.expire();
+                while let Some(fragment) = stream.next_fragment() {
+                    if let Ok(Some(frame)) = reassembler.push(&fragment) {
+                        frames.push(frame);
+                    }
+                }
+            }
+            FeedFormat::Unknown(_) => {}
+        }
+        frames
+    }
+}
+
+/// Lossy one-way UDP link, for tests and development
+///
+/// Stands in for a satellite downlink: datagrams sent to
+/// `addr()` are forwarded to `target`, except that each one
+/// starts a loss burst with probability `loss`, dropping it
+/// and the next `burst - 1` as well.
+pub struct LossyLink {
+    addr: SocketAddr,
+    counts: Arc<Mutex<(u64, u64)>>,
+}
+
+impl LossyLink {
+    pub fn start(target: SocketAddr, loss: f64, burst: usize) -> io::Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let addr = socket.local_addr()?;
+        let counts = Arc::new(Mutex::new((0, 0)));
+
+        let tally = Arc::clone(&counts);
+        thread::spawn(move || {
+            let mut buf = vec![0u8; 64 * 1024];
+            let mut dropping = 0;
+
+            while let Ok((n, _)) = socket.recv_from(&mut buf) {
+                if dropping == 0 && random::<f64>() < loss {
+                    dropping = burst.max(1);
+                }
+
+                let mut tally = tally.lock().unwrap();
+                if dropping > 0 {
+                    dropping -= 1;
+                    tally.1 += 1;
+                } else {
+                    let _ = socket.send_to(&buf[..n], target);
+                    tally.0 += 1;
+                }
+            }
+        });
+
+        Ok(Self { addr, counts })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Datagrams passed on
+    pub fn forwarded(&self) -> u64 {
+        self.counts.lock().unwrap().0
+    }
+
+    /// Datagrams lost
+    pub fn dropped(&self) -> u64 {
+        self.counts.lock().unwrap().1
+    }
+}
+
+/// Uplink settings (POLICY ONLY, `data/uplink_config.json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UplinkOptions {
+    pub enabled: bool,
+
+    /// Send fragments as UDP datagrams here (a modulator, or
+    /// another node's satellite listener)
+    pub udp: Option<SocketAddr>,
+
+    /// Or write them back to back to this file / FIFO
+    pub file: Option<String>,
+
+    /// Latest blocks the carousel repeats
+    pub carousel_blocks: usize,
+
+    /// Best mempool transactions the carousel repeats
+    pub carousel_transactions: usize,
+
+    /// Link rate in bytes per second; 0 = as fast as possible
+    pub rate: u64,
+
+    pub fec: FecParams,
+}
+
+impl Default for UplinkOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            udp: None,
+            file: None,
+            carousel_blocks: 6,
+            carousel_transactions: 100,
+            rate: 16 * 1024,
+            fec: FecParams::default(),
+        }
+    }
+}
+
+/// Where fragments go
+enum UplinkOutput {
+    Udp(UdpSocket, SocketAddr),
+    /// Opened on first write (a FIFO blocks until read)
+    File(String, Option<File>),
+}
+
+impl UplinkOutput {
+    fn write(&mut self, fragment: &[u8]) -> io::Result<()> {
+        match self {
+            UplinkOutput::Udp(socket, target) => socket.send_to(fragment, *target).map(|_| ()),
+            UplinkOutput::File(path, file) => {
+                if file.is_none() {
+                    *file = Some(OpenOptions::new().create(true).append(true).open(&*path)?);
+                }
+                let result = file.as_mut().map(|f| f.write_all(fragment)).unwrap_or(Ok(()));
+                if result.is_err() {
+                    // Reader went away: reopen next time
+                    *file = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+/// Satellite uplink: the sending side of `SatelliteTransport`
+///
+/// Loops a carousel of recent blocks and transactions, so a
+/// receiver that tunes in at any time (or lost part of a
+/// pass) picks them up on a later one, and sends relayed
+/// blocks and transactions as soon as they arrive, ahead of
+/// the carousel. Everything is fragmented with erasure coding
+/// in the framing `listen_udp` / `listen_file` decode, and
+/// paced to the link rate.
+///
+/// Registered with the router as a flooding transport with a
+/// single peer, `sat:uplink:<target>`; it never receives.
+pub struct SatelliteUplink {
+    peer: PeerId,
+    fec: FecParams,
+    live: Mutex<VecDeque<Vec<u8>>>,
+    stats: Mutex<LinkStats>,
+}
+
+impl SatelliteUplink {
+    pub fn start(options: UplinkOptions, carousel: CarouselFn) -> Result<Arc<Self>, &'static str> {
+        options.fec.validate()?;
+
+        let (output, target) = match (options.udp, &options.file) {
+            (Some(addr), None) => {
+                let bind = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(bind).map_err(|_| "uplink socket bind failed")?;
+                (UplinkOutput::Udp(socket, addr), addr.to_string())
+            }
+            (None, Some(path)) => (UplinkOutput::File(path.clone(), None), path.clone()),
+            _ => return Err("uplink needs exactly one of udp and file"),
+        };
+
+        println!("🛰 Satellite uplink to {}", target);
+
+        let uplink = Arc::new(Self {
+            peer: PeerId::Satellite(format!("uplink:{}", target)),
+            fec: options.fec,
+            live: Mutex::new(VecDeque::new()),
+            stats: Mutex::new(LinkStats::default()),
+        });
+
+        let sender = Arc::clone(&uplink);
+        thread::spawn(move || sender.run(output, carousel, options.rate));
+
+        Ok(uplink)
+    }
+
+    fn run(&self, mut output: UplinkOutput, carousel: CarouselFn, rate: u64) {
+        // Fragments of the current carousel, by message id:
+        // frames that stay in it are not encoded again
+        let mut encoded: HashMap<[u8; 8], Arc<Vec<Vec<u8>>>> = HashMap::new();
+
+        loop {
+            let mut next = HashMap::new();
+
+            for frame in carousel() {
+                let id = message_id(&frame);
+                let fragments = match encoded.remove(&id) {
+                    Some(f) => f,
+                    None => match fragment(&frame, &self.fec) {
+                        Ok(f) => Arc::new(f),
+                        Err(_) => continue,
+                    },
+                };
+
+                for f in fragments.iter() {
+                    self.send_live(&mut output, rate);
+                    self.transmit(&mut output, f, rate);
+                }
+                next.insert(id, fragments);
+            }
+
+            let idle = next.is_empty();
+            encoded = next;
+            self.stats.lock().unwrap().carousel_cycles += 1;
+
+            self.send_live(&mut output, rate);
+            if idle {
+                thread::sleep(CAROUSEL_IDLE);
+            }
+        }
+    }
+
+    /// Everything queued by relays
+    fn send_live(&self, output: &mut UplinkOutput, rate: u64) {
+        loop {
+            let fragment = self.live.lock().unwrap().pop_front();
+            match fragment {
+                Some(f) => self.transmit(output, &f, rate),
+                None => return,
+            }
+        }
+    }
+
+    /// Write one fragment, then wait out its time on the link
+    fn transmit(&self, output: &mut UplinkOutput, fragment: &[u8], rate: u64) {
+        // A failed write is a lost fragment, which is what the
+        // parity is for: not retried
+        if output.write(fragment).is_ok() {
+            self.stats.lock().unwrap().fragments_sent += 1;
+        }
+
+        if rate > 0 {
+            thread::sleep(Duration::from_secs_f64(fragment.len() as f64 / rate as f64));
+        }
+    }
+}
+
+impl Transport for SatelliteUplink {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Satellite
+    }
+
+    /// Only the uplink itself can be sent to (not downlink feeds)
+    fn send(&self, peer: &PeerId, data: &[u8]) {
+        if *peer == self.peer {
+            self.broadcast(data);
+        }
+    }
+
+    fn broadcast(&self, data: &[u8]) {
+        let Ok(fragments) = fragment(data, &self.fec) else {
+            return;
+        };
+
+        let mut live = self.live.lock().unwrap();
+        live.extend(fragments);
+        while live.len() > MAX_LIVE_FRAGMENTS {
+            live.pop_front();
+        }
+    }
+
+    fn peers(&self) -> Vec<PeerId> {
+        vec![self.peer.clone()]
+    }
+
+    fn floods(&self) -> bool {
+        true
+    }
+
+    fn link_stats(&self) -> Option<LinkStats> {
+        Some(*self.stats.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_survive_a_lossy_link() {
+        // A free port for the listener
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let listen: SocketAddr = ([127, 0, 0, 1], port).into();
+
+        let received: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&received);
+        SatelliteTransport::listen_udp(
+            &listen.to_string(),
+            Arc::new(move |_, frame| log.lock().unwrap().push(frame)),
+            Arc::new(|_| {}),
+        );
+
+        // 100 data + 50 parity fragments; bursts of two lost
+        let link = LossyLink::start(listen, 0.15, 2).unwrap();
+        let frame: Vec<u8> = (0..100 * 1024).map(|i| (i % 251) as u8).collect();
+        let fragments = fragment(&frame, &FecParams::default()).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        // Repeated like the carousel would, until it gets through
+        for _ in 0..3 {
+            for f in &fragments {
+                sender.send_to(f, link.addr()).unwrap();
+                thread::sleep(Duration::from_micros(200));
+            }
+            thread::sleep(Duration::from_millis(200));
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+        }
+
+        assert!(link.dropped() > 0);
+        assert!(link.forwarded() > 0);
+        assert_eq!(*received.lock().unwrap(), vec![frame]);
+    }
+    #[test]
+    fn uplink_counts_reach_transport_stats() {
+        use crate::node::router::{RouteConfig, TransportRouter};
+
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let options = UplinkOptions {
+            enabled: true,
+            udp: Some(sink.local_addr().unwrap()),
+            rate: 1024 * 1024,
+            ..Default::default()
+        };
+        let frame: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let carousel = Arc::new(move || vec![frame.clone()]);
+        let uplink = SatelliteUplink::start(options, carousel).unwrap();
+        let router = TransportRouter::new(uplink, RouteConfig::default());
+
+        // 3 data + 2 parity fragments per pass
+        let mut link = None;
+        for _ in 0..100 {
+            link = router.stats()[0].link;
+            if link.is_some_and(|l| l.carousel_cycles >= 2) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let link = link.unwrap();
+        assert!(link.carousel_cycles >= 2);
+        assert!(link.fragments_sent >= 5 * link.carousel_cycles);
+    }
+}
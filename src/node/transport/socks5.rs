@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::node::netaddr::PeerAddress;
+
+const VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+
+/// Reaching the proxy itself
+const PROXY_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Proxy negotiation, including the proxy's own connection
+/// to the target (onion circuits are slow to build)
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connect to `target` through the SOCKS5 proxy at `proxy`
+///
+/// Host names are passed to the proxy unresolved, so no DNS
+/// query leaves this machine. No authentication.
+pub fn connect(proxy: &SocketAddr, target: &PeerAddress) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect_timeout(proxy, PROXY_CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(NEGOTIATION_TIMEOUT))?;
+    stream.set_write_timeout(Some(NEGOTIATION_TIMEOUT))?;
+
+    // Greeting: one method, no authentication
+    stream.write_all(&[VERSION, 1, METHOD_NO_AUTH])?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice)?;
+    if choice != [VERSION, METHOD_NO_AUTH] {
+        return Err(proxy_error("proxy requires authentication"));
+    }
+
+    // CONNECT request
+    let mut req = vec![VERSION, CMD_CONNECT, 0x00];
+    match target {
+        PeerAddress::Ip(a) => match a.ip() {
+            IpAddr::V4(ip) => {
+                req.push(ATYP_IPV4);
+                req.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                req.push(ATYP_IPV6);
+                req.extend_from_slice(&ip.octets());
+            }
+        },
+        PeerAddress::Host { host, .. } => {
+            if host.len() > 255 {
+                return Err(proxy_error("host name too long"));
+            }
+            req.push(ATYP_DOMAIN);
+            req.push(host.len() as u8);
+            req.extend_from_slice(host.as_bytes());
+        }
+    }
+    req.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&req)?;
+
+    // Reply: VER REP RSV ATYP BND.ADDR BND.PORT
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head)?;
+    if head[0] != VERSION {
+        return Err(proxy_error("not a SOCKS5 proxy"));
+    }
+    if head[1] != REPLY_SUCCEEDED {
+        return Err(proxy_error(match head[1] {
+            REPLY_HOST_UNREACHABLE => "host unreachable",
+            0x05 => "connection refused",
+            0x06 => "TTL expired",
+            _ => "proxy connect failed",
+        }));
+    }
+
+    let addr_len = match head[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut n = [0u8; 1];
+            stream.read_exact(&mut n)?;
+            n[0] as usize
+        }
+        _ => return Err(proxy_error("bad reply address")),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    Ok(stream)
+}
+
+fn proxy_error(msg: &str) -> io::Error {
+    io::Error::other(format!("SOCKS5: {}", msg))
+}
+
+/// Minimal local SOCKS5 proxy, for tests and development
+///
+/// Stands in for Tor: CONNECT to an IP goes straight out, a
+/// host name is looked up in a fixed table (e.g. onion name →
+/// local listener). Every target asked for is recorded, so a
+/// test can check that nothing bypassed the proxy.
+pub struct LocalProxy {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl LocalProxy {
+    /// Listen on `bind`, routing host names through `hosts`
+    pub fn start(bind: &str, hosts: HashMap<String, SocketAddr>) -> io::Result<Self> {
+        let listener = TcpListener::bind(bind)?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let hosts = Arc::new(hosts);
+
+        let log = Arc::clone(&requests);
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let hosts = Arc::clone(&hosts);
+                let log = Arc::clone(&log);
+                thread::spawn(move || {
+                    let _ = serve(client, &hosts, &log);
+                });
+            }
+        });
+
+        Ok(Self { addr, requests })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Targets requested so far ("host:port" / "ip:port")
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// One proxied connection: negotiate, connect, then relay
+fn serve(
+    mut client: TcpStream,
+    hosts: &HashMap<String, SocketAddr>,
+    log: &Mutex<Vec<String>>,
+) -> io::Result<()> {
+    let mut head = [0u8; 2];
+    client.read_exact(&mut head)?;
+    let mut methods = vec![0u8; head[1] as usize];
+    client.read_exact(&mut methods)?;
+
+    if head[0] != VERSION || !methods.contains(&METHOD_NO_AUTH) {
+        client.write_all(&[VERSION, METHOD_NONE_ACCEPTABLE])?;
+        return Ok(());
+    }
+    client.write_all(&[VERSION, METHOD_NO_AUTH])?;
+
+    let mut req = [0u8; 4];
+    client.read_exact(&mut req)?;
+    if req[1] != CMD_CONNECT {
+        return reply(&mut client, REPLY_COMMAND_NOT_SUPPORTED);
+    }
+
+    let target = match req[3] {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip)?;
+            PeerAddress::Ip(SocketAddr::new(Ipv4Addr::from(ip).into(), read_port(&mut client)?))
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            client.read_exact(&mut ip)?;
+            PeerAddress::Ip(SocketAddr::new(Ipv6Addr::from(ip).into(), read_port(&mut client)?))
+        }
+        ATYP_DOMAIN => {
+            let mut n = [0u8; 1];
+            client.read_exact(&mut n)?;
+            let mut host = vec![0u8; n[0] as usize];
+            client.read_exact(&mut host)?;
+            PeerAddress::Host {
+                host: String::from_utf8_lossy(&host).to_ascii_lowercase(),
+                port: read_port(&mut client)?,
+            }
+        }
+        _ => return reply(&mut client, REPLY_GENERAL_FAILURE),
+    };
+
+    log.lock().unwrap().push(target.to_string());
+
+    let dest = match &target {
+        PeerAddress::Ip(a) => Some(*a),
+        PeerAddress::Host { host, .. } => hosts.get(host).copied(),
+    };
+
+    let upstream = match dest.map(|d| TcpStream::connect_timeout(&d, PROXY_CONNECT_TIMEOUT)) {
+        Some(Ok(s)) => s,
+        _ => return reply(&mut client, REPLY_HOST_UNREACHABLE),
+    };
+
+    reply(&mut client, REPLY_SUCCEEDED)?;
+
+    let (mut c_read, mut u_write) = (client.try_clone()?, upstream.try_clone()?);
+    thread::spawn(move || {
+        let _ = io::copy(&mut c_read, &mut u_write);
+        let _ = u_write.shutdown(Shutdown::Write);
+    });
+
+    let (mut u_read, mut c_write) = (upstream, client);
+    let _ = io::copy(&mut u_read, &mut c_write);
+    let _ = c_write.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+fn read_port(stream: &mut TcpStream) -> io::Result<u16> {
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port)?;
+    Ok(u16::from_be_bytes(port))
+}
+
+fn reply(client: &mut TcpStream, code: u8) -> io::Result<()> {
+    client.write_all(&[VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Listener answering every connection with what it receives
+    fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut reader = stream.try_clone().unwrap();
+                    let mut writer = stream;
+                    let _ = io::copy(&mut reader, &mut writer);
+                });
+            }
+        });
+        addr
+    }
+
+    /// Proxy answering one client with fixed bytes: the method
+    /// choice, then (if it accepted) the CONNECT reply
+    fn scripted_proxy(choice: [u8; 2], reply: Vec<u8>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting).unwrap();
+            client.write_all(&choice).unwrap();
+            if choice[1] == METHOD_NO_AUTH {
+                // IPv4 CONNECT request
+                let mut req = [0u8; 10];
+                client.read_exact(&mut req).unwrap();
+                client.write_all(&reply).unwrap();
+            }
+        });
+        addr
+    }
+
+    fn round_trip(stream: &mut TcpStream) {
+        stream.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn connects_to_an_ip_through_the_proxy() {
+        let target = echo_server();
+        let proxy = LocalProxy::start("127.0.0.1:0", HashMap::new()).unwrap();
+
+        let mut stream = connect(&proxy.addr(), &PeerAddress::Ip(target)).unwrap();
+        round_trip(&mut stream);
+        assert_eq!(proxy.requests(), [target.to_string()]);
+    }
+
+    #[test]
+    fn host_names_reach_the_proxy_unresolved() {
+        let onion = format!("{}.onion", "b".repeat(56));
+        let target = echo_server();
+        let proxy =
+            LocalProxy::start("127.0.0.1:0", HashMap::from([(onion.clone(), target)])).unwrap();
+
+        let addr = PeerAddress::parse(&format!("{}:8333", onion.to_uppercase())).unwrap();
+        let mut stream = connect(&proxy.addr(), &addr).unwrap();
+        round_trip(&mut stream);
+
+        // A name the proxy cannot reach
+        let unknown = PeerAddress::parse("unknown.example.org:8333").unwrap();
+        let err = connect(&proxy.addr(), &unknown).unwrap_err();
+        assert!(err.to_string().contains("host unreachable"));
+
+        assert_eq!(
+            proxy.requests(),
+            [
+                format!("{}:8333", onion),
+                "unknown.example.org:8333".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_proxy_errors() {
+        let target = PeerAddress::Ip("1.2.3.4:8333".parse().unwrap());
+        let reply = |code: u8| vec![VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0];
+
+        let cases = [
+            (
+                scripted_proxy([VERSION, METHOD_NONE_ACCEPTABLE], vec![]),
+                "requires authentication",
+            ),
+            (
+                scripted_proxy([VERSION, METHOD_NO_AUTH], reply(REPLY_HOST_UNREACHABLE)),
+                "host unreachable",
+            ),
+            (
+                scripted_proxy([VERSION, METHOD_NO_AUTH], reply(0x05)),
+                "connection refused",
+            ),
+            (
+                scripted_proxy([VERSION, METHOD_NO_AUTH], reply(0x06)),
+                "TTL expired",
+            ),
+            (
+                scripted_proxy([VERSION, METHOD_NO_AUTH], reply(REPLY_GENERAL_FAILURE)),
+                "proxy connect failed",
+            ),
+            (
+                scripted_proxy([VERSION, METHOD_NO_AUTH], vec![0x04, 0, 0, 0]),
+                "not a SOCKS5 proxy",
+            ),
+            (
+                scripted_proxy([VERSION, METHOD_NO_AUTH], vec![VERSION, 0, 0, 0x09]),
+                "bad reply address",
+            ),
+        ];
+
+        for (proxy, expected) in cases {
+            let err = connect(&proxy, &target).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}: {}", expected, err);
+        }
+    }
+
+    #[test]
+    fn refuses_overlong_host_names() {
+        let proxy = LocalProxy::start("127.0.0.1:0", HashMap::new()).unwrap();
+        let target = PeerAddress::Host {
+            host: "a".repeat(256),
+            port: 8333,
+        };
+        assert!(connect(&proxy.addr(), &target).is_err());
+        assert!(proxy.requests().is_empty());
+    }
+}
@@ -1,84 +1,867 @@
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream, SocketAddr};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::node::transport::Transport;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::{watch, Notify};
+use tokio::task::block_in_place;
+use tokio::time::timeout;
 
-const MAX_MESSAGE_SIZE: usize = 1 * 1024 * 1024;
+use crate::node::transport::{socks5, EventFn, PeerEvent, ReceiveFn, Transport};
+use crate::node::transport::encryption::{
+    EncryptionPolicy, Handshake, PacketDecoder, RecvCipher, SendCipher,
+    ENC_AUTH, KEY_EXCHANGE_SIZE,
+};
+use crate::node::netaddr::{host_socket_addr, is_host_mapped, PeerAddress};
+use crate::node::peer_id::{PeerId, TransportKind};
+use crate::node::wire::{decode_frame, FrameDecoder, NETWORK_MAGIC};
 
+/// Bytes read from the socket at a time
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Outbound connect timeout
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Encryption negotiation must finish within this
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Frames queued per peer; a peer that lets its queue fill up
+/// is not reading and is disconnected instead of waited for
+pub const SEND_QUEUE_LEN: usize = 1024;
+
+/// A single write taking longer than this drops the peer
+const WRITE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A peer that closed on our key exchange is dialled in
+/// plaintext for this long
+const PLAINTEXT_MEMORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Open connections per direction, counted from accept / dial
+/// (the peer manager only counts handshaked peers)
+pub const DEFAULT_MAX_INBOUND: usize = 125;
+pub const DEFAULT_MAX_OUTBOUND: usize = 32;
+
+/// Runtime worker threads, shared by all connections
+const WORKER_THREADS: usize = 2;
+
+struct Conn {
+    inbound: bool,
+    /// Set once negotiation finished; frames may be queued
+    queue: Option<mpsc::Sender<Vec<u8>>>,
+    /// Wakes the connection task to close it
+    close: Arc<Notify>,
+}
+
+/// Outcome of negotiation
+enum Negotiated {
+    Plaintext {
+        /// Bytes already read, the start of the first frame
+        leftover: Vec<u8>,
+    },
+    /// We dialled and the peer closed on our key or answered in
+    /// plaintext: it does not encrypt (plaintext next time)
+    Declined,
+    Encrypted {
+        send: Box<SendCipher>,
+        recv: Box<RecvCipher>,
+        /// Bytes already read past the key exchange
+        leftover: Vec<u8>,
+    },
+}
+
+/// Incoming byte stream → frames
+enum Reader {
+    Plain(FrameDecoder),
+    Sealed(PacketDecoder, Box<RecvCipher>),
+}
+
+impl Reader {
+    fn feed(&mut self, data: &[u8]) {
+        match self {
+            Reader::Plain(d) => d.feed(data),
+            Reader::Sealed(d, _) => d.feed(data),
+        }
+    }
+
+    /// Next complete frame; an error (bad frame, failed
+    /// decryption) means the stream cannot be trusted
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, &'static str> {
+        match self {
+            Reader::Plain(d) => d.next_frame(),
+            Reader::Sealed(d, cipher) => d.next_packet(cipher),
+        }
+    }
+}
+
+/// Optional TCP transport features
+pub struct TcpOptions {
+    /// None: plaintext only
+    pub encryption: Option<EncryptionPolicy>,
+
+    /// SOCKS5 proxy for onion addresses (e.g. Tor at 127.0.0.1:9050)
+    pub proxy: Option<SocketAddr>,
+
+    /// Every outbound connection goes through `proxy`
+    pub proxy_only: bool,
+
+    /// Connection limits (negotiating connections included)
+    pub max_inbound: usize,
+    pub max_outbound: usize,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            encryption: None,
+            proxy: None,
+            proxy_only: false,
+            max_inbound: DEFAULT_MAX_INBOUND,
+            max_outbound: DEFAULT_MAX_OUTBOUND,
+        }
+    }
+}
+
+/// State shared with connection tasks
+struct Shared {
+    peers: Mutex<HashMap<SocketAddr, Conn>>,
+    on_receive: ReceiveFn,
+    on_event: EventFn,
+
+    /// None: plaintext only
+    encryption: Option<EncryptionPolicy>,
+
+    /// Outbound connections still being set up
+    pending: Mutex<HashSet<SocketAddr>>,
+
+    /// Peers that declined encryption, and when
+    plaintext_peers: Mutex<HashMap<SocketAddr, Instant>>,
+
+    max_inbound: usize,
+    max_outbound: usize,
+
+    handle: Handle,
+
+    /// Cloned into every task; taken on shutdown so no task can
+    /// start, after which `tasks_done` closes once all have ended
+    tasks: Mutex<Option<mpsc::Sender<()>>>,
+    tasks_done: Mutex<Option<mpsc::Receiver<()>>>,
+
+    /// Set on shutdown
+    stop: watch::Sender<bool>,
+}
+
+impl Shared {
+    /// Run `task` on the transport runtime; false once shut down
+    fn spawn<F>(&self, task: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let guard = match &*self.tasks.lock().unwrap() {
+            Some(g) => g.clone(),
+            None => return false,
+        };
+
+        self.handle.spawn(async move {
+            let _guard = guard;
+            task.await;
+        });
+        true
+    }
+
+    /// Resolves once shutdown has started
+    fn stopped(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut stop = self.stop.subscribe();
+        async move {
+            let _ = stop.wait_for(|s| *s).await;
+        }
+    }
+
+    fn count(&self, inbound: bool) -> usize {
+        self.peers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|c| c.inbound == inbound)
+            .count()
+    }
+
+    /// Dial `addr` without offering encryption: it declined
+    /// recently and nothing requires it
+    fn dial_plaintext(&self, addr: &SocketAddr, policy: &EncryptionPolicy) -> bool {
+        if policy.require || policy.peer_keys.contains_key(addr) {
+            return false;
+        }
+
+        let mut peers = self.plaintext_peers.lock().unwrap();
+        peers.retain(|_, at| at.elapsed() < PLAINTEXT_MEMORY);
+        peers.contains_key(addr)
+    }
+
+    /// Reserve an outbound slot for `addr`
+    fn begin_outbound(&self, addr: SocketAddr) -> bool {
+        if self.peers.lock().unwrap().contains_key(&addr) {
+            return false;
+        }
+
+        let outbound = self.count(false);
+        let mut pending = self.pending.lock().unwrap();
+        if outbound + pending.len() >= self.max_outbound {
+            return false;
+        }
+        pending.insert(addr)
+    }
+}
+
+/// TCP transport on its own tokio runtime
+///
+/// Every connection is one task (plus a writer task). Sends
+/// never block the caller: frames go into a bounded per-peer
+/// queue drained under a write timeout, so a slow peer cannot
+/// hold up broadcasts.
 pub struct TcpTransport {
-    peers: Arc<Mutex<HashMap<SocketAddr, TcpStream>>>,
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    proxy: Option<SocketAddr>,
+    proxy_only: bool,
+    runtime: Option<Runtime>,
 }
 
 impl TcpTransport {
+    /// Listen on `bind`; every connection (inbound or outbound)
+    /// is reported through `on_event`
     pub fn new(
         bind: &str,
-        on_receive: Arc<dyn Fn(SocketAddr, Vec<u8>) + Send + Sync>,
+        on_receive: ReceiveFn,
+        on_event: EventFn,
+    ) -> Arc<Self> {
+        Self::with_options(bind, on_receive, on_event, TcpOptions::default())
+    }
+
+    /// As `new`, with encryption, a proxy and / or other limits
+    ///
+    /// With encryption, peers that predate it fall back to
+    /// plaintext unless `policy.require` is set.
+    pub fn with_options(
+        bind: &str,
+        on_receive: ReceiveFn,
+        on_event: EventFn,
+        options: TcpOptions,
     ) -> Arc<Self> {
-        let listener = TcpListener::bind(bind).expect("TCP bind failed");
+        assert!(
+            options.proxy.is_some() || !options.proxy_only,
+            "proxy_only needs a proxy"
+        );
+
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(WORKER_THREADS)
+            .thread_name("p2p-tcp")
+            .enable_all()
+            .build()
+            .expect("Tokio runtime failed");
+
+        let listener = std::net::TcpListener::bind(bind).expect("TCP bind failed");
         listener.set_nonblocking(true).unwrap();
+        let local_addr = listener.local_addr().unwrap();
 
-        let peers = Arc::new(Mutex::new(HashMap::new()));
-        let peers_accept = Arc::clone(&peers);
-        let on_receive = Arc::clone(&on_receive);
+        let listener = {
+            let _enter = runtime.enter();
+            TcpListener::from_std(listener).expect("TCP bind failed")
+        };
 
-        thread::spawn(move || loop {
-            match listener.accept() {
-                Ok((mut stream, addr)) => {
-                    stream
-                        .set_read_timeout(Some(Duration::from_secs(30)))
-                        .ok();
-
-                    peers_accept
-                        .lock()
-                        .unwrap()
-                        .insert(addr, stream.try_clone().unwrap());
-
-                    let peers_inner = Arc::clone(&peers_accept);
-                    let on_receive = Arc::clone(&on_receive);
-
-                    thread::spawn(move || {
-                        let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
-                        loop {
-                            match stream.read(&mut buf) {
-                                Ok(0) | Err(_) => break,
-                                Ok(n) => (on_receive)(addr, buf[..n].to_vec()),
-                            }
-                        }
-                        peers_inner.lock().unwrap().remove(&addr);
-                    });
-                }
-                Err(_) => thread::sleep(Duration::from_millis(50)),
+        let (tasks, tasks_done) = mpsc::channel(1);
+        let (stop, _) = watch::channel(false);
+
+        let shared = Arc::new(Shared {
+            peers: Mutex::new(HashMap::new()),
+            on_receive,
+            on_event,
+            encryption: options.encryption,
+            pending: Mutex::new(HashSet::new()),
+            plaintext_peers: Mutex::new(HashMap::new()),
+            max_inbound: options.max_inbound,
+            max_outbound: options.max_outbound,
+            handle: runtime.handle().clone(),
+            tasks: Mutex::new(Some(tasks)),
+            tasks_done: Mutex::new(Some(tasks_done)),
+            stop,
+        });
+
+        shared.spawn(accept_loop(Arc::clone(&shared), listener));
+
+        Arc::new(Self {
+            shared,
+            local_addr,
+            proxy: options.proxy,
+            proxy_only: options.proxy_only,
+            runtime: Some(runtime),
+        })
+    }
+
+    /// Stop listening, close every connection and wait until
+    /// all connection tasks have ended
+    ///
+    /// Open connections are reported Disconnected as usual.
+    /// Later sends and connects are ignored.
+    pub async fn shutdown(&self) {
+        self.shared.stop.send_replace(true);
+        self.shared.tasks.lock().unwrap().take();
+
+        let done = self.shared.tasks_done.lock().unwrap().take();
+        if let Some(mut done) = done {
+            while done.recv().await.is_some() {}
+        }
+    }
+
+    /// `shutdown` for callers outside any tokio runtime
+    pub fn shutdown_blocking(&self) {
+        self.shared.handle.block_on(self.shutdown());
+    }
+
+    /// Dial `target` through the proxy; reported as `key`
+    fn connect_proxied(&self, target: PeerAddress, key: SocketAddr) -> bool {
+        let Some(proxy) = self.proxy else {
+            return false;
+        };
+
+        if !self.shared.begin_outbound(key) {
+            return false;
+        }
+
+        let shared = Arc::clone(&self.shared);
+        let stopped = self.shared.stopped();
+
+        // The SOCKS5 client blocks; circuits can take seconds
+        let started = self.shared.spawn(async move {
+            let dial = tokio::task::spawn_blocking(move || {
+                let stream = socks5::connect(&proxy, &target)
+                    .map_err(|e| format!("{} failed: {}", target, e))?;
+                stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+                Ok::<_, String>(stream)
+            });
+
+            let result = tokio::select! {
+                r = dial => r,
+                _ = stopped => Ok(Err("shutting down".to_string())),
+            };
+
+            match result {
+                Ok(Ok(stream)) => match TcpStream::from_std(stream) {
+                    Ok(s) => register(&shared, s, key, false),
+                    Err(e) => println!("🧅 Proxy connection failed: {}", e),
+                },
+                Ok(Err(e)) => println!("🧅 Proxy connection to {}", e),
+                Err(_) => {}
             }
+            shared.pending.lock().unwrap().remove(&key);
         });
 
-        Arc::new(Self { peers })
+        if !started {
+            self.shared.pending.lock().unwrap().remove(&key);
+        }
+        started
     }
+}
 
-    pub fn connect(&self, addr: SocketAddr) {
-        if let Ok(stream) = TcpStream::connect(addr) {
-            self.peers.lock().unwrap().insert(addr, stream);
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        // Dropping a runtime may not block inside another one
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+async fn accept_loop(shared: Arc<Shared>, listener: TcpListener) {
+    let stopped = shared.stopped();
+    tokio::pin!(stopped);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, addr)) = accepted else {
+                    continue;
+                };
+
+                // Over the limit: closed right away
+                if shared.count(true) >= shared.max_inbound {
+                    continue;
+                }
+                register(&shared, stream, addr, true);
+            }
+            _ = &mut stopped => return,
         }
     }
 }
 
+/// Track the connection and start its task, which negotiates
+/// encryption and then reports it
+fn register(shared: &Arc<Shared>, stream: TcpStream, addr: SocketAddr, inbound: bool) {
+    let close = Arc::new(Notify::new());
+
+    shared.peers.lock().unwrap().insert(
+        addr,
+        Conn {
+            inbound,
+            queue: None,
+            close: Arc::clone(&close),
+        },
+    );
+
+    let task = run_connection(Arc::clone(shared), stream, addr, inbound, close);
+    if !shared.spawn(task) {
+        shared.peers.lock().unwrap().remove(&addr);
+    }
+}
+
+async fn run_connection(
+    shared: Arc<Shared>,
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    inbound: bool,
+    close: Arc<Notify>,
+) {
+    let negotiated = match &shared.encryption {
+        Some(p) if inbound || !shared.dial_plaintext(&addr, p) => tokio::select! {
+            r = timeout(NEGOTIATION_TIMEOUT, negotiate(&mut stream, addr, inbound, p)) => {
+                r.unwrap_or(Err("negotiation timed out"))
+            }
+            _ = shared.stopped() => Err("shutting down"),
+        },
+        _ => Ok(Negotiated::Plaintext { leftover: vec![] }),
+    };
+
+    let (reader, cipher, leftover) = match negotiated {
+        Ok(Negotiated::Plaintext { leftover }) => {
+            (Reader::Plain(FrameDecoder::new()), None, leftover)
+        }
+        Ok(Negotiated::Encrypted { send, recv, leftover }) => {
+            (Reader::Sealed(PacketDecoder::new(), recv), Some(*send), leftover)
+        }
+        Ok(Negotiated::Declined) => {
+            println!("🔓 {} does not encrypt, next connection in plaintext", addr);
+            shared.plaintext_peers.lock().unwrap().insert(addr, Instant::now());
+            shared.peers.lock().unwrap().remove(&addr);
+            return;
+        }
+        Err(e) => {
+            println!("🔒 Connection {} dropped: {}", addr, e);
+            shared.peers.lock().unwrap().remove(&addr);
+            return;
+        }
+    };
+
+    let encrypted = cipher.is_some();
+    let (read_half, write_half) = stream.into_split();
+    let (queue, rx) = mpsc::channel(SEND_QUEUE_LEN);
+
+    if let Some(c) = shared.peers.lock().unwrap().get_mut(&addr) {
+        c.queue = Some(queue);
+    }
+    let writer = write_loop(addr, write_half, rx, cipher, Arc::clone(&close), shared.stopped());
+    shared.spawn(writer);
+
+    let on_event = &shared.on_event;
+    let on_receive = &shared.on_receive;
+
+    // Callbacks take node locks; keep them off the async workers
+    let peer = PeerId::Tcp(addr);
+    block_in_place(|| (on_event)(PeerEvent::Connected { peer: peer.clone(), inbound, encrypted }));
+
+    let result = tokio::select! {
+        r = read_loop(read_half, addr, reader, leftover, on_receive) => r,
+        _ = close.notified() => Ok(()),
+        _ = shared.stopped() => Ok(()),
+    };
+
+    if result.is_err() {
+        block_in_place(|| (on_event)(PeerEvent::Malformed(peer.clone())));
+    }
+
+    // Dropping the queue ends the writer, which closes the socket
+    shared.peers.lock().unwrap().remove(&addr);
+    block_in_place(|| (on_event)(PeerEvent::Disconnected(peer)));
+}
+
+/// Deliver frames until the peer disconnects (Ok) or sends
+/// something that breaks the stream (Err)
+async fn read_loop(
+    mut stream: OwnedReadHalf,
+    addr: SocketAddr,
+    mut reader: Reader,
+    leftover: Vec<u8>,
+    on_receive: &ReceiveFn,
+) -> Result<(), &'static str> {
+    reader.feed(&leftover);
+    let mut buf = vec![0u8; READ_CHUNK];
+    let mut delivered = false;
+
+    loop {
+        loop {
+            let frame = match reader.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                // A plaintext stream that never made sense is
+                // most likely a key we do not answer: just closed
+                Err(_) if !delivered && matches!(reader, Reader::Plain(_)) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            delivered = true;
+            block_in_place(|| (on_receive)(PeerId::Tcp(addr), frame));
+        }
+
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return Ok(()),
+            Ok(n) => reader.feed(&buf[..n]),
+        }
+    }
+}
+
+/// Drain the send queue until it closes; a write that fails
+/// or times out closes the connection. Queued frames are still
+/// flushed after a disconnect, not after shutdown.
+async fn write_loop(
+    addr: SocketAddr,
+    mut stream: OwnedWriteHalf,
+    mut queue: mpsc::Receiver<Vec<u8>>,
+    mut cipher: Option<SendCipher>,
+    close: Arc<Notify>,
+    stopped: impl Future<Output = ()>,
+) {
+    tokio::pin!(stopped);
+
+    while let Some(frame) = queue.recv().await {
+        let data = match &mut cipher {
+            Some(c) => c.seal(&frame),
+            None => frame,
+        };
+
+        let written = tokio::select! {
+            r = timeout(WRITE_TIMEOUT, stream.write_all(&data)) => r,
+            _ = &mut stopped => break,
+        };
+
+        match written {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => {
+                close.notify_one();
+                break;
+            }
+            Err(_) => {
+                println!("🐌 Write to {} timed out, disconnecting", addr);
+                close.notify_one();
+                break;
+            }
+        }
+    }
+
+    let _ = stream.shutdown().await;
+}
+
+/// Opportunistic encryption at connection start
+///
+/// The initiator sends its ephemeral key as 64 raw bytes
+/// (ElligatorSwift: indistinguishable from random) and an
+/// encrypting responder answers with its own, so nothing on
+/// the wire marks the protocol. A stream starting with the
+/// network magic is a plaintext peer; a peer that closes on
+/// our key or answers in plaintext does not encrypt. Both sides then send ENC_AUTH
+/// under encryption, proving their static node key.
+async fn negotiate(
+    stream: &mut TcpStream,
+    addr: SocketAddr,
+    inbound: bool,
+    policy: &EncryptionPolicy,
+) -> Result<Negotiated, &'static str> {
+    let expected = if inbound { None } else { policy.peer_keys.get(&addr) };
+    let handshake = Handshake::new(!inbound);
+    let mut received = Vec::new();
+
+    if inbound {
+        read_at_least(stream, &mut received, NETWORK_MAGIC.len()).await?;
+        if received[..NETWORK_MAGIC.len()] == NETWORK_MAGIC {
+            if policy.require {
+                return Err("peer does not encrypt");
+            }
+            return Ok(Negotiated::Plaintext { leftover: received });
+        }
+
+        read_at_least(stream, &mut received, KEY_EXCHANGE_SIZE).await?;
+        write_all(stream, &handshake.key_bytes()).await?;
+    } else {
+        write_all(stream, &handshake.key_bytes()).await?;
+
+        // Closing on our key or greeting us in plaintext both
+        // mean the peer does not encrypt
+        let declined = read_at_least(stream, &mut received, NETWORK_MAGIC.len()).await.is_err()
+            || received[..NETWORK_MAGIC.len()] == NETWORK_MAGIC
+            || read_at_least(stream, &mut received, KEY_EXCHANGE_SIZE).await.is_err();
+        if declined {
+            if policy.require || expected.is_some() {
+                return Err("peer does not encrypt");
+            }
+            return Ok(Negotiated::Declined);
+        }
+    }
+
+    let leftover = received.split_off(KEY_EXCHANGE_SIZE);
+    let mut session = handshake.complete(received.try_into().unwrap());
+    let auth = session.send.seal(&session.auth_frame(&policy.node_key));
+    write_all(stream, &auth).await?;
+
+    // Their ENC_AUTH is the first encrypted packet
+    let mut packets = PacketDecoder::new();
+    packets.feed(&leftover);
+
+    let mut buf = vec![0u8; READ_CHUNK];
+    let auth = loop {
+        if let Some(frame) = packets.next_packet(&mut session.recv)? {
+            break frame;
+        }
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return Err("closed during negotiation"),
+            Ok(n) => packets.feed(&buf[..n]),
+        }
+    };
+
+    let auth = decode_frame(&auth)?;
+    if auth.command != ENC_AUTH {
+        return Err("expected encauth");
+    }
+    let their_static = session.verify_auth(&auth.payload)?;
+
+    if let Some(key) = expected {
+        if *key != their_static {
+            return Err("peer key mismatch");
+        }
+    }
+
+    Ok(Negotiated::Encrypted {
+        send: Box::new(session.send),
+        recv: Box::new(session.recv),
+        leftover: packets.take_buffered(),
+    })
+}
+
+async fn write_all(stream: &mut TcpStream, data: &[u8]) -> Result<(), &'static str> {
+    stream.write_all(data).await.map_err(|_| "write failed")
+}
+
+/// Read until `buf` holds at least `len` bytes
+async fn read_at_least(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    len: usize,
+) -> Result<(), &'static str> {
+    let mut chunk = vec![0u8; READ_CHUNK];
+    while buf.len() < len {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err("closed during negotiation"),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+    Ok(())
+}
+
 impl Transport for TcpTransport {
-    fn send(&self, addr: &SocketAddr, data: &[u8]) {
-        if let Some(s) = self.peers.lock().unwrap().get_mut(addr) {
-            let _ = s.write_all(data);
+    fn kind(&self) -> TransportKind {
+        TransportKind::Tcp
+    }
+
+    /// Queue `data` for `peer` (dropped while still negotiating)
+    fn send(&self, peer: &PeerId, data: &[u8]) {
+        let PeerId::Tcp(addr) = peer else {
+            return;
+        };
+
+        let peers = self.shared.peers.lock().unwrap();
+        let Some(conn) = peers.get(addr) else {
+            return;
+        };
+        let Some(queue) = &conn.queue else {
+            return;
+        };
+
+        if let Err(TrySendError::Full(_)) = queue.try_send(data.to_vec()) {
+            println!("🐌 Send queue to {} full, disconnecting", addr);
+            conn.close.notify_one();
         }
     }
 
     fn broadcast(&self, data: &[u8]) {
-        for s in self.peers.lock().unwrap().values_mut() {
-            let _ = s.write_all(data);
+        for peer in self.peers() {
+            self.send(&peer, data);
         }
     }
 
-    fn peers(&self) -> Vec<SocketAddr> {
-        self.peers.lock().unwrap().keys().cloned().collect()
+    fn peers(&self) -> Vec<PeerId> {
+        self.shared
+            .peers
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|a| PeerId::Tcp(*a))
+            .collect()
+    }
+
+    /// Start dialling `addr`; true if an attempt was started
+    /// (success is reported as a Connected event)
+    fn connect(&self, addr: &SocketAddr) -> bool {
+        // Stand-in for a host name, not an address to dial
+        if is_host_mapped(addr) {
+            return false;
+        }
+
+        if self.proxy_only {
+            return self.connect_proxied(PeerAddress::Ip(*addr), *addr);
+        }
+
+        let addr = *addr;
+        if !self.shared.begin_outbound(addr) {
+            return false;
+        }
+
+        let shared = Arc::clone(&self.shared);
+        let stopped = self.shared.stopped();
+
+        let started = self.shared.spawn(async move {
+            let dial = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr));
+
+            tokio::select! {
+                r = dial => {
+                    if let Ok(Ok(stream)) = r {
+                        register(&shared, stream, addr, false);
+                    }
+                }
+                _ = stopped => {}
+            }
+            shared.pending.lock().unwrap().remove(&addr);
+        });
+
+        if !started {
+            self.shared.pending.lock().unwrap().remove(&addr);
+        }
+        started
+    }
+
+    fn connect_host(&self, host: &str, port: u16) -> Option<SocketAddr> {
+        let key = host_socket_addr(host, port);
+        let target = PeerAddress::Host {
+            host: host.to_ascii_lowercase(),
+            port,
+        };
+
+        self.connect_proxied(target, key).then_some(key)
+    }
+
+    fn reaches_hosts(&self) -> bool {
+        self.proxy.is_some()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        Some(self.local_addr)
+    }
+
+    fn disconnect(&self, peer: &PeerId) {
+        let PeerId::Tcp(addr) = peer else {
+            return;
+        };
+
+        // The connection task wakes and reports Disconnected
+        if let Some(c) = self.shared.peers.lock().unwrap().get(addr) {
+            c.close.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::transport::socks5::LocalProxy;
+    use std::io::ErrorKind;
+    use std::net::TcpListener as StdListener;
+
+    fn transport(options: TcpOptions) -> Arc<TcpTransport> {
+        TcpTransport::with_options(
+            "127.0.0.1:0",
+            Arc::new(|_, _| {}),
+            Arc::new(|_| {}),
+            options,
+        )
+    }
+
+    /// True if `listener` is dialled within `wait`
+    fn dialled(listener: &StdListener, wait: Duration) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < wait {
+            match listener.accept() {
+                Ok(_) => return true,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(20))
+                }
+                Err(e) => panic!("accept: {}", e),
+            }
+        }
+        false
+    }
+
+    fn target() -> (StdListener, SocketAddr) {
+        let listener = StdListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        (listener, addr)
+    }
+
+    #[test]
+    fn proxy_only_never_dials_directly() {
+        let (listener, addr) = target();
+
+        // The proxy is down: the attempt fails rather than going direct
+        let dead = StdListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let t = transport(TcpOptions {
+            proxy: Some(dead),
+            proxy_only: true,
+            ..Default::default()
+        });
+        assert!(t.connect(&addr));
+        assert!(!dialled(&listener, Duration::from_secs(1)));
+        t.shutdown_blocking();
+
+        // Through a working proxy the target is reached
+        let proxy = LocalProxy::start("127.0.0.1:0", HashMap::new()).unwrap();
+        let t = transport(TcpOptions {
+            proxy: Some(proxy.addr()),
+            proxy_only: true,
+            ..Default::default()
+        });
+        assert!(t.connect(&addr));
+        assert!(dialled(&listener, Duration::from_secs(5)));
+        assert_eq!(proxy.requests(), [addr.to_string()]);
+        t.shutdown_blocking();
+    }
+
+    #[test]
+    fn mapped_host_addresses_are_not_dialled() {
+        let proxy = LocalProxy::start("127.0.0.1:0", HashMap::new()).unwrap();
+        let onion = format!("{}.onion", "a".repeat(56));
+
+        for proxy_only in [false, true] {
+            let t = transport(TcpOptions {
+                proxy: Some(proxy.addr()),
+                proxy_only,
+                ..Default::default()
+            });
+            assert!(!t.connect(&host_socket_addr(&onion, 8333)));
+            t.shutdown_blocking();
+        }
+        assert!(proxy.requests().is_empty());
     }
 }
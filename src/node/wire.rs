@@ -0,0 +1,283 @@
+use sha2::{Sha256, Digest};
+
+use crate::node::message::NetworkMessage;
+
+/// Network magic — first bytes of every frame
+///
+/// Lets a node drop traffic from other networks / protocols
+/// and detect a desynchronised stream.
+pub const NETWORK_MAGIC: [u8; 4] = *b"REV3";
+
+/// Largest payload accepted (checked before allocation)
+///
+/// Room for a maximum size block plus serialization overhead.
+pub const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Command name field width (NUL padded ASCII)
+pub const COMMAND_SIZE: usize = 12;
+
+/// magic (4) | command (12) | length u32 LE (4) | checksum (4)
+pub const HEADER_SIZE: usize = 4 + COMMAND_SIZE + 4 + 4;
+
+/// A decoded frame
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub command: String,
+    pub payload: Vec<u8>,
+}
+
+/// First 4 bytes of SHA256(SHA256(payload))
+pub fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Wrap `payload` in a frame envelope
+pub fn encode_frame(command: &str, payload: &[u8]) -> Vec<u8> {
+    assert!(command.len() <= COMMAND_SIZE, "command too long");
+
+    let mut out = Vec::with_capacity(HEADER_SIZE + payload.len());
+    out.extend_from_slice(&NETWORK_MAGIC);
+
+    let mut name = [0u8; COMMAND_SIZE];
+    name[..command.len()].copy_from_slice(command.as_bytes());
+    out.extend_from_slice(&name);
+
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&checksum(payload));
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Serialize and frame a network message
+pub fn encode_message(msg: &NetworkMessage) -> Vec<u8> {
+    let payload = bincode::serialize(msg).unwrap();
+    encode_frame(msg.command(), &payload)
+}
+
+/// Decode one complete frame (datagram transports, or
+/// frames already split off a stream by `FrameDecoder`)
+pub fn decode_frame(data: &[u8]) -> Result<Frame, &'static str> {
+    let (command, len) = parse_header(data)?;
+
+    if data.len() != HEADER_SIZE + len {
+        return Err("frame length mismatch");
+    }
+
+    let payload = &data[HEADER_SIZE..];
+    if checksum(payload) != data[HEADER_SIZE - 4..HEADER_SIZE] {
+        return Err("bad checksum");
+    }
+
+    Ok(Frame {
+        command,
+        payload: payload.to_vec(),
+    })
+}
+
+/// Decode a frame and its NetworkMessage payload
+///
+/// The command in the envelope must match the message.
+pub fn decode_message(data: &[u8]) -> Result<NetworkMessage, &'static str> {
+    let frame = decode_frame(data)?;
+
+    let msg: NetworkMessage =
+        bincode::deserialize(&frame.payload).map_err(|_| "undecodable payload")?;
+
+    if msg.command() != frame.command {
+        return Err("command mismatch");
+    }
+
+    Ok(msg)
+}
+
+/// Command of a frame without checking its payload
+///
+/// Cheap enough to route or filter raw frames before decoding.
+pub fn peek_command(data: &[u8]) -> Option<String> {
+    parse_header(data).ok().map(|(command, _)| command)
+}
+
+/// Validate a header; returns (command, payload length)
+fn parse_header(data: &[u8]) -> Result<(String, usize), &'static str> {
+    if data.len() < HEADER_SIZE {
+        return Err("short frame");
+    }
+
+    if data[..4] != NETWORK_MAGIC {
+        return Err("bad magic");
+    }
+
+    let name = &data[4..4 + COMMAND_SIZE];
+    let end = name.iter().position(|b| *b == 0).unwrap_or(COMMAND_SIZE);
+    if name[end..].iter().any(|b| *b != 0) || !name[..end].is_ascii() {
+        return Err("bad command");
+    }
+    let command = String::from_utf8_lossy(&name[..end]).into_owned();
+
+    let len_start = 4 + COMMAND_SIZE;
+    let len = u32::from_le_bytes(
+        data[len_start..len_start + 4].try_into().unwrap(),
+    ) as usize;
+
+    if len > MAX_MESSAGE_SIZE {
+        return Err("oversized frame");
+    }
+
+    Ok((command, len))
+}
+
+/// Streaming frame decoder (one per connection)
+///
+/// Byte streams (TCP, pipes) may split a frame across reads
+/// or coalesce several frames into one. Feed every read in,
+/// then take complete frames out.
+///
+/// Any error means the stream can no longer be trusted to be
+/// in sync; the caller should drop the connection.
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Next complete frame (raw envelope bytes), None if more
+    /// data is needed
+    ///
+    /// The header is checked as soon as it arrives, so an
+    /// oversized frame is rejected before its payload is buffered.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, &'static str> {
+        if self.buf.len() < HEADER_SIZE {
+            // Fail fast on garbage instead of waiting for a header
+            let n = self.buf.len().min(NETWORK_MAGIC.len());
+            if self.buf[..n] != NETWORK_MAGIC[..n] {
+                return Err("bad magic");
+            }
+            return Ok(None);
+        }
+
+        let (_, len) = parse_header(&self.buf)?;
+
+        let total = HEADER_SIZE + len;
+        if self.buf.len() < total {
+            return Ok(None);
+        }
+
+        let frame: Vec<u8> = self.buf.drain(..total).collect();
+        if checksum(&frame[HEADER_SIZE..]) != frame[HEADER_SIZE - 4..HEADER_SIZE] {
+            return Err("bad checksum");
+        }
+
+        Ok(Some(frame))
+    }
+
+    /// Bytes waiting for the rest of a frame
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Remove and return the buffered bytes (the stream switches
+    /// to another encoding after the current frame)
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_blocks(from_height: u64) -> Vec<u8> {
+        encode_message(&NetworkMessage::GetBlocks {
+            from_height,
+            count: 16,
+        })
+    }
+
+    #[test]
+    fn reassembles_a_frame_fed_in_pieces() {
+        let frame = get_blocks(7);
+        let mut decoder = FrameDecoder::new();
+
+        for piece in frame.chunks(5) {
+            assert_eq!(decoder.next_frame(), Ok(None));
+            decoder.feed(piece);
+        }
+
+        assert_eq!(decoder.next_frame(), Ok(Some(frame)));
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn splits_two_frames_from_one_read() {
+        let first = get_blocks(1);
+        let second = encode_message(&NetworkMessage::Ping);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&[first.clone(), second.clone()].concat());
+
+        assert_eq!(decoder.next_frame(), Ok(Some(first)));
+        assert_eq!(decoder.next_frame(), Ok(Some(second)));
+        assert_eq!(decoder.next_frame(), Ok(None));
+    }
+
+    #[test]
+    fn rejects_oversized_length_from_the_header() {
+        let mut frame = encode_frame("block", &[]);
+        frame[16..20].copy_from_slice(&(MAX_MESSAGE_SIZE as u32 + 1).to_le_bytes());
+
+        // Header only: no payload has arrived yet
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&frame);
+        assert_eq!(decoder.next_frame(), Err("oversized frame"));
+        assert_eq!(decode_frame(&frame).unwrap_err(), "oversized frame");
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut frame = get_blocks(1);
+        frame[0] ^= 0xff;
+        assert_eq!(decode_frame(&frame).unwrap_err(), "bad magic");
+
+        // The stream decoder fails on the first wrong byte
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&frame[..1]);
+        assert_eq!(decoder.next_frame(), Err("bad magic"));
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut frame = get_blocks(1);
+        let last = frame.len() - 1;
+        frame[last] ^= 0xff;
+        assert_eq!(decode_frame(&frame).unwrap_err(), "bad checksum");
+
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&frame);
+        assert_eq!(decoder.next_frame(), Err("bad checksum"));
+    }
+
+    #[test]
+    fn decode_message_checks_the_command() {
+        let payload = bincode::serialize(&NetworkMessage::Ping).unwrap();
+
+        let frame = encode_frame("ping", &payload);
+        assert!(matches!(decode_message(&frame), Ok(NetworkMessage::Ping)));
+
+        let frame = encode_frame("pong", &payload);
+        assert_eq!(decode_message(&frame).unwrap_err(), "command mismatch");
+    }
+}
@@ -1,3 +1,7 @@
+use serde::{Serialize, Deserialize};
+
+use crate::core::transaction::Transaction;
+
 /// Policy limits (NOT consensus yet)
 pub const MAX_BLOCK_SIZE: usize = 1_000_000; // 1 MB
 pub const MAX_BLOCK_TXS: usize = 2_000;
@@ -7,3 +11,91 @@ pub const MAX_BLOCK_TX_BYTES: usize = MAX_BLOCK_SIZE - 1_000;
 
 /// Mempool policy
 pub const MAX_TX_SIZE: usize = 100_000; // 100 KB
+
+/// Outputs worth less than this are not relayed by default
+pub const DEFAULT_DUST_THRESHOLD: u64 = 546;
+
+/// Standard outputs pay to a 32-byte pubkey hash
+pub const STANDARD_PUBKEY_HASH_LEN: usize = 32;
+
+/// Relay / mempool standardness settings (POLICY ONLY)
+///
+/// Nodes may differ here without affecting consensus.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PolicyConfig {
+    /// Minimum fee rate (per serialized byte) to enter the mempool
+    pub min_relay_fee_per_byte: u64,
+
+    /// Outputs below this value are dust and non-standard
+    pub dust_threshold: u64,
+
+    pub max_tx_inputs: usize,
+    pub max_tx_outputs: usize,
+
+    /// Mempool capacity (serialized bytes / transaction count)
+    pub max_mempool_bytes: usize,
+    pub max_mempool_txs: usize,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_relay_fee_per_byte: 1,
+            dust_threshold: DEFAULT_DUST_THRESHOLD,
+            max_tx_inputs: 500,
+            max_tx_outputs: 500,
+            max_mempool_bytes: 300_000_000,
+            max_mempool_txs: 50_000,
+        }
+    }
+}
+
+/// Standardness checks applied before relay / mempool admission
+///
+/// These run BEFORE consensus validation and never make a
+/// consensus-valid block invalid.
+pub fn check_standard(
+    tx: &Transaction,
+    policy: &PolicyConfig,
+) -> Result<(), &'static str> {
+    if tx.inputs.is_empty() {
+        return Err("coinbase not relayed");
+    }
+
+    if tx.inputs.len() > policy.max_tx_inputs {
+        return Err("too many inputs");
+    }
+
+    if tx.outputs.is_empty() {
+        return Err("no outputs");
+    }
+
+    if tx.outputs.len() > policy.max_tx_outputs {
+        return Err("too many outputs");
+    }
+
+    if tx.serialized_size() > MAX_TX_SIZE {
+        return Err("tx too large");
+    }
+
+    for output in &tx.outputs {
+        if output.pubkey_hash.len() != STANDARD_PUBKEY_HASH_LEN {
+            return Err("non-standard output");
+        }
+
+        if output.value < policy.dust_threshold {
+            return Err("dust output");
+        }
+    }
+
+    Ok(())
+}
+
+/// Fee rate (per byte, rounded down)
+pub fn fee_rate(fee: u64, size: usize) -> u64 {
+    if size == 0 {
+        return 0;
+    }
+    fee / size as u64
+}
@@ -27,10 +27,14 @@ use crate::crypto::{
 
 use crate::core::transaction::{Transaction, TxInput, TxOutput};
 use crate::core::utxo::UTXOSet;
+use crate::policy::PolicyConfig;
 
 const WALLET_FILE: &str = "data/wallet.dat";
 const COINBASE_MATURITY: u64 = 100;
 
+/// Unused addresses scanned past the last handed out
+const ADDRESS_LOOKAHEAD: u32 = 20;
+
 /* ───────── Encrypted Wallet File ───────── */
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -63,6 +67,13 @@ fn derive_child_seed(master: &[u8; 32], index: u32) -> [u8; 32] {
     out
 }
 
+fn address_at(master: &[u8; 32], index: u32) -> Vec<u8> {
+    let child = derive_child_seed(master, index);
+    let sk = secret_key_from_seed(&child);
+    let pk = public_key(&sk);
+    pubkey_hash(&pk)
+}
+
 /* ───────── Wallet Struct ───────── */
 
 pub struct Wallet {
@@ -241,31 +252,93 @@ impl Wallet {
 
     pub fn address(&self) -> Result<Vec<u8>, &'static str> {
         let master = self.master_seed.ok_or("wallet locked")?;
-        let child = derive_child_seed(&master, 0);
-        let sk = secret_key_from_seed(&child);
-        let pk = public_key(&sk);
-        Ok(pubkey_hash(&pk))
+        Ok(address_at(&master, 0))
+    }
+
+    /// Hand out the next unused address (persists the index)
+    pub fn new_address(&mut self) -> Result<Vec<u8>, &'static str> {
+        let master = self.master_seed.ok_or("wallet locked")?;
+
+        let data = fs::read(WALLET_FILE).map_err(|_| "wallet file missing")?;
+        let mut wf: WalletFile =
+            bincode::deserialize(&data).map_err(|_| "wallet file corrupted")?;
+
+        self.next_index += 1;
+        wf.next_index = self.next_index;
+
+        fs::write(WALLET_FILE, bincode::serialize(&wf).unwrap())
+            .map_err(|_| "wallet write failed")?;
+
+        Ok(address_at(&master, self.next_index))
     }
 
+    /// Every address handed out so far (index 0 included)
+    pub fn addresses(&self) -> Result<Vec<Vec<u8>>, &'static str> {
+        let master = self.master_seed.ok_or("wallet locked")?;
+        Ok((0..=self.next_index)
+            .map(|index| address_at(&master, index))
+            .collect())
+    }
+
+    /// Build and sign a payment paying `fee_per_byte` on the
+    /// size of the signed transaction
+    ///
+    /// Change below the policy's dust threshold goes to the fee,
+    /// so the node's own mempool accepts the result.
     pub fn create_transaction(
         &mut self,
         utxos: &UTXOSet,
         to_pubkey_hash: Vec<u8>,
         amount: u64,
+        fee_per_byte: u64,
+        policy: &PolicyConfig,
     ) -> Result<Transaction, &'static str> {
         let master_seed = self.master_seed.ok_or("wallet locked")?;
 
+        // Size depends on the inputs selected, so re-select until
+        // the fee paid covers the rate on the signed transaction
+        let mut fee = 0u64;
+        loop {
+            let tx = self.build_transaction(
+                &master_seed,
+                utxos,
+                to_pubkey_hash.clone(),
+                amount,
+                fee,
+                policy.dust_threshold,
+            )?;
+
+            let required = tx.serialized_size() as u64 * fee_per_byte;
+            if required <= fee {
+                return Ok(tx);
+            }
+            fee = required;
+        }
+    }
+
+    fn build_transaction(
+        &self,
+        master_seed: &[u8; 32],
+        utxos: &UTXOSet,
+        to_pubkey_hash: Vec<u8>,
+        amount: u64,
+        fee: u64,
+        dust_threshold: u64,
+    ) -> Result<Transaction, &'static str> {
+        let needed = amount.checked_add(fee).ok_or("amount too large")?;
+
         let mut collected = 0u64;
         let mut selected = Vec::new();
 
+        let known: Vec<(u32, Vec<u8>)> = (0..self.next_index + ADDRESS_LOOKAHEAD)
+            .map(|index| (index, address_at(master_seed, index)))
+            .collect();
+
         for (key, utxo) in utxos {
-            for index in 0..20 {
-                let child = derive_child_seed(&master_seed, index);
-                let sk = secret_key_from_seed(&child);
-                let pk = public_key(&sk);
-                let hash = pubkey_hash(&pk);
+            for (index, hash) in &known {
+                let index = *index;
 
-                if hash == utxo.pubkey_hash {
+                if *hash == utxo.pubkey_hash {
                     let parts: Vec<&str> = key.split(':').collect();
                     let txid = hex::decode(parts[0]).unwrap();
                     let vout = parts[1].parse::<u32>().unwrap();
@@ -273,17 +346,17 @@ impl Wallet {
                     selected.push((txid, vout, index, utxo.value));
                     collected += utxo.value;
 
-                    if collected >= amount {
+                    if collected >= needed {
                         break;
                     }
                 }
             }
-            if collected >= amount {
+            if collected >= needed {
                 break;
             }
         }
 
-        if collected < amount {
+        if collected < needed {
             return Err("not enough funds");
         }
 
@@ -292,8 +365,9 @@ impl Wallet {
             pubkey_hash: to_pubkey_hash,
         }];
 
-        let change = collected - amount;
-        if change > 0 {
+        // Change below the dust threshold is left to the fee
+        let change = collected - needed;
+        if change >= dust_threshold {
             let change_addr = self.address()?;
             outputs.push(TxOutput {
                 value: change,
@@ -311,10 +385,10 @@ impl Wallet {
         for (txid, vout, index, _) in selected {
             let sig = sign(
                 &sighash,
-                &secret_key_from_seed(&derive_child_seed(&master_seed, index)),
+                &secret_key_from_seed(&derive_child_seed(master_seed, index)),
             );
             let pk = public_key(&secret_key_from_seed(
-                &derive_child_seed(&master_seed, index),
+                &derive_child_seed(master_seed, index),
             ));
 
             tx.inputs.push(TxInput {